@@ -1,21 +1,124 @@
-use std::{cell::RefCell, fmt::Debug, path::PathBuf};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeSet, HashMap},
+    fmt::Debug,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 use waybar_cffi::gtk::{
-    self as gtk, Border, CssProvider, IconLookupFlags, IconSize, IconTheme, ReliefStyle,
-    StateFlags,
-    gdk_pixbuf::Pixbuf,
-    glib::Propagation,
-    prelude::{ButtonExt, CssProviderExt, GdkPixbufExt, IconThemeExt, StyleContextExt, WidgetExt},
+    self as gtk, Border, IconLookupFlags, IconSize, IconTheme, ImageType, PropagationPhase,
+    ReliefStyle, StateFlags, cairo,
+    gdk::{
+        Event, EventMask, EventScroll, EventType, InputSource, RGBA, ScrollDirection,
+        keys::constants as keys, prelude::DeviceExt,
+    },
+    glib::{self, ControlFlow, Propagation},
+    pango::{self, EllipsizeMode},
+    prelude::{
+        ButtonExt, ContainerExt, EventControllerExt, GdkPixbufExt, GestureExt, GestureSingleExt,
+        IconThemeExt, ImageExt, IsA, LabelExt, OverlayExt, PopoverExt, ProgressBarExt,
+        StyleContextExt, WidgetExt, WidgetExtManual,
+    },
+};
+
+use niri_ipc::Workspace;
+
+pub use windows::{Member, Windows};
+
+use crate::{
+    config::{ButtonAction, FocusedClick, IconStyle, Input, Trigger, file},
+    css, dnd,
+    error::Error,
+    exec, icon, launcher, menu,
+    niri::{Niri, Window},
+    notify::{self, EnrichedNotification, Urgency},
+    process,
+    state::State,
+    thumbnail,
 };
 
-use crate::state::State;
+mod windows;
 
 /// A taskbar button.
 pub struct Button {
     app_id: Option<String>,
+    pid: Option<i32>,
+    windows: Windows,
     button: gtk::Button,
+    image: Option<gtk::Image>,
+    // The icon file, which is looked up in the background, and again if the icon theme changes.
+    icon_path: Rc<RefCell<Option<PathBuf>>>,
+    icon_pending: Rc<Cell<bool>>,
+    label: gtk::Label,
+    count: gtk::Label,
+    progress: gtk::ProgressBar,
+    badge: gtk::Label,
     state: State,
     layout: niri_ipc::WindowLayout,
+    // Gtk 3 gestures aren't owned by their widget, so we have to keep this alive ourselves.
+    long_press: gtk::GestureLongPress,
+    // The timer toggling the urgent-flash class, if the button is currently flashing.
+    flash: Rc<RefCell<Option<glib::SourceId>>>,
+    // The timer clearing the urgent class, if the button is urgent and a timeout is configured.
+    urgent_timeout: Rc<RefCell<Option<glib::SourceId>>>,
+    // Whether Niri last reported any of the windows as urgent.
+    compositor_urgent: Cell<bool>,
+    // The badge count from the app's launcher entry, and the number of notifications attributed to
+    // the windows since they were last focused, which is shown when the app doesn't set a count.
+    launcher_count: Cell<Option<i64>>,
+    //
+    // Notifications the daemon gave an ID are tracked by it, so that a notification that replaces
+    // an earlier one isn't counted twice, and so that they can be forgotten when they're closed and
+    // dismissed when the button is clicked. Those without an ID are only counted, and are only
+    // forgotten when the windows are focused.
+    notification_ids: Rc<RefCell<BTreeSet<u32>>>,
+    anonymous_notifications: Cell<usize>,
+    // The tooltip rendered for the windows, and the latest notification, which is appended to it
+    // until the notifications are cleared.
+    tooltip: RefCell<Option<String>>,
+    notification_tooltip: RefCell<Option<String>>,
+    // The highest urgency of the notifications that arrived during do not disturb, which is
+    // applied once it's turned off.
+    held: Cell<Option<Urgency>>,
+    // Whether a tray item of the windows needs attention, which is forgotten along with the
+    // notifications.
+    tray_attention: Cell<bool>,
+    // The names of the workspaces the windows are on, for the workspaces placeholder.
+    workspaces: RefCell<Vec<String>>,
+    // When the button was created, and when its window was last focused, for sorting.
+    created: Cell<Instant>,
+    last_focused: Cell<Option<Instant>>,
+}
+
+/// The state of a button that comes from what has happened since it was created, rather than from
+/// its windows or the configuration, which is carried over when the taskbar is rebuilt.
+#[derive(Debug, Clone)]
+pub struct Carried {
+    compositor_urgent: bool,
+    urgent: bool,
+    urgency: Option<Urgency>,
+    notification_ids: BTreeSet<u32>,
+    anonymous_notifications: usize,
+    notification_tooltip: Option<String>,
+    held: Option<Urgency>,
+    tray_attention: bool,
+    created: Instant,
+    last_focused: Option<Instant>,
+}
+
+impl Drop for Button {
+    fn drop(&mut self) {
+        // The timers only hold references to the widgets, so they'd otherwise keep running after
+        // the button has been removed.
+        if let Some(source) = self.flash.take() {
+            source.remove();
+        }
+        if let Some(source) = self.urgent_timeout.take() {
+            source.remove();
+        }
+    }
 }
 
 impl Debug for Button {
@@ -30,61 +133,258 @@ impl Debug for Button {
 // Practically, we're likely to be doing everything from the main thread anyway, but Glib can
 // figure that out.
 thread_local! {
-    static BUTTON_CSS_PROVIDER: CssProvider = {
-        let css = CssProvider::new();
-        if let Err(e) = css.load_from_data(include_bytes!("style.css")) {
-            tracing::error!(%e, "CSS parse error");
-        }
+    // Keyed by the configured theme name, since bars on different outputs can use different
+    // themes, and the theme can change when the configuration is reloaded.
+    static ICON_THEMES: RefCell<HashMap<Option<String>, IconTheme>> = RefCell::default();
+}
 
-        css
+/// Returns the ID to look the icon up with, which is the app ID except for programs running under
+/// Wine, which are looked up by their executable, or the icon configured for it.
+async fn wine_icon_id(state: &State, app_id: String, pid: Option<i32>) -> String {
+    let config = state.config();
+    if !config.wine_enabled() {
+        return app_id;
+    }
+
+    let exe = if icon::wine::is_exe(&app_id) {
+        app_id
+    } else if let Some(pid) = pid
+        && config.wine_generic_app_id(&app_id)
+    {
+        match process::exe_name(pid.into()).await {
+            Ok(Some(exe)) if icon::wine::is_exe(&exe) => exe,
+            Ok(_) => return app_id,
+            Err(e) => {
+                tracing::info!(%e, pid, "cannot find executable of Wine window");
+                return app_id;
+            }
+        }
+    } else {
+        return app_id;
     };
 
-    static ICON_THEME: IconTheme = {
-        IconTheme::default().unwrap_or_default()
+    match config.wine_icon(&exe) {
+        Some(icon) => file::expand_home(icon).to_string_lossy().into_owned(),
+        None => exe,
     }
 }
 
+/// Returns the icon theme used for fallback icons.
+///
+/// If a theme is configured, then we need our own theme object: setting a custom theme on the
+/// default theme would change it for the rest of Waybar, too.
+fn icon_theme(name: Option<&str>) -> IconTheme {
+    ICON_THEMES.with_borrow_mut(|themes| {
+        themes
+            .entry(name.map(String::from))
+            .or_insert_with(|| match name {
+                Some(name) => {
+                    let theme = IconTheme::new();
+                    theme.set_custom_theme(Some(name));
+                    theme
+                }
+                None => IconTheme::default().unwrap_or_default(),
+            })
+            .clone()
+    })
+}
+
 impl Button {
     /// Instantiates a new button, including creating a new Gtk button internally.
     #[tracing::instrument(level = "TRACE", fields(app_id = &window.app_id))]
     pub fn new(state: &State, window: &niri_ipc::Window) -> Self {
         let state = state.clone();
 
-        // Set up the basic image button.
+        // Set up the basic button, along with the image and label within it that the display mode
+        // calls for.
         //
-        // Note that we don't actually set the image here: we need to know the size before doing so
+        // Note that we don't actually load the icon here: we need to know the size before doing so
         // in order to load the most appropriate icon from the icon theme, and we won't know that
         // until we get an actual size allocation.
         let button = gtk::Button::new();
-        button.set_always_show_image(true);
         button.set_relief(ReliefStyle::None);
 
-        // Provide the base CSS for each button that users can then extend.
-        BUTTON_CSS_PROVIDER.with(|provider| {
-            button
-                .style_context()
-                .add_provider(provider, gtk::STYLE_PROVIDER_PRIORITY_APPLICATION);
+        let content = gtk::Box::new(
+            gtk::Orientation::Horizontal,
+            state.config().spacing().button_content(),
+        );
+        let image = state.config().display().icon().then(|| {
+            let image = gtk::Image::new();
+            css::add_provider(&state, &image);
+            content.add(&image);
+            image
         });
 
+        // The label is always created, since app rules can add a label to any button. Its
+        // visibility is managed when the title is set.
+        let label = gtk::Label::new(None);
+        label.set_ellipsize(EllipsizeMode::End);
+        label.set_no_show_all(true);
+        content.add(&label);
+
+        // Gtk 3 labels can only be limited to a number of characters, so the maximum button width
+        // is converted using the average character width of the label's font. The icon has to be
+        // accounted for, too, but we won't know its actual size until we get a size allocation,
+        // so we'll use the configured size or Gtk's default button icon size.
+        if let Some(max_width) = state.config().max_button_width() {
+            let icon_width = match image {
+                Some(_) => {
+                    state.config().icon_size().unwrap_or(DEFAULT_ICON_SIZE)
+                        + state.config().spacing().button_content()
+                }
+                None => 0,
+            };
+            let char_width = label
+                .pango_context()
+                .metrics(None, None)
+                .approximate_char_width()
+                / pango::SCALE;
+            label.set_max_width_chars(((max_width - icon_width) / char_width.max(1)).max(1));
+        }
+
+        // Similarly, the count is only shown when the button represents more than one window.
+        let count = gtk::Label::new(None);
+        count.style_context().add_class("niri-taskbar-count");
+        css::add_provider(&state, &count);
+        count.set_no_show_all(true);
+        content.add(&count);
+
+        // Everything else is overlaid on the content, rather than packed alongside it, so that it
+        // doesn't affect the size allocation used to size the icon.
+        let overlay = gtk::Overlay::new();
+        overlay.add(&content);
+        button.add(&overlay);
+
+        if state.config().focus_indicator() {
+            let indicator = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+            indicator.set_valign(gtk::Align::End);
+            indicator
+                .style_context()
+                .add_class("niri-taskbar-indicator");
+            css::add_provider(&state, &indicator);
+            overlay.add_overlay(&indicator);
+        }
+
+        // The launcher entry progress and badge are only shown when an app provides them.
+        let progress = gtk::ProgressBar::new();
+        progress.set_valign(gtk::Align::End);
+        progress.style_context().add_class("niri-taskbar-progress");
+        css::add_provider(&state, &progress);
+        progress.set_no_show_all(true);
+        overlay.add_overlay(&progress);
+
+        let badge = gtk::Label::new(None);
+        badge.set_halign(gtk::Align::End);
+        badge.set_valign(gtk::Align::Start);
+        badge.style_context().add_class("niri-taskbar-badge");
+        css::add_provider(&state, &badge);
+        badge.set_no_show_all(true);
+        overlay.add_overlay(&badge);
+
+        // Buttons can be focused for keyboard navigation, but clicking them shouldn't steal focus.
+        button.set_can_focus(true);
+        button.set_focus_on_click(false);
+
+        // Provide the base CSS for each button that users can then extend.
+        css::add_provider(&state, &button);
+
         let app_id = window.app_id.clone();
-        let icon_path = app_id
-            .as_deref()
-            .and_then(|id| state.icon_cache().lookup(id));
+
+        let long_press = gtk::GestureLongPress::new(&button);
 
         let button = Self {
             app_id,
+            pid: window.pid,
+            windows: Windows::new(window),
             button,
+            image,
+            icon_path: Rc::default(),
+            icon_pending: Rc::default(),
+            label,
+            count,
+            progress,
+            badge,
             state,
             layout: window.layout.clone(),
+            long_press,
+            flash: Rc::default(),
+            urgent_timeout: Rc::default(),
+            compositor_urgent: Cell::default(),
+            launcher_count: Cell::default(),
+            notification_ids: Rc::default(),
+            anonymous_notifications: Cell::default(),
+            tooltip: RefCell::default(),
+            notification_tooltip: RefCell::default(),
+            held: Cell::default(),
+            tray_attention: Cell::default(),
+            workspaces: RefCell::default(),
+            created: Cell::new(Instant::now()),
+            last_focused: Cell::default(),
         };
 
         // Set up our event handlers. It's easier to do this with self already available.
-        button.connect_click_handler(window.id);
-        button.connect_size_allocate(icon_path);
+        //
+        // Drag and drop has to be connected before the click handler, since the click handler
+        // stops the button press event from propagating to the drag source.
+        button.connect_drag_and_drop();
+        button.connect_click_handler();
+        button.connect_scroll_handler();
+        button.connect_key_handler();
+        if button.state.config().thumbnails().enabled() {
+            button.connect_thumbnail();
+        }
+        if button.image.is_some() {
+            button.connect_size_allocate();
+            button.load_icon();
+        }
 
         button
     }
 
+    /// Returns the state to carry over to the button that replaces this one when the taskbar is
+    /// rebuilt.
+    pub fn carry(&self) -> Carried {
+        let context = self.button.style_context();
+        Carried {
+            compositor_urgent: self.compositor_urgent.get(),
+            urgent: context.has_class("urgent"),
+            urgency: URGENCY_CLASSES
+                .iter()
+                .filter(|(_, class)| context.has_class(class))
+                .map(|(level, _)| *level)
+                .max(),
+            notification_ids: self.notification_ids.borrow().clone(),
+            anonymous_notifications: self.anonymous_notifications.get(),
+            notification_tooltip: self.notification_tooltip.borrow().clone(),
+            held: self.held.get(),
+            tray_attention: self.tray_attention.get(),
+            created: self.created.get(),
+            last_focused: self.last_focused.get(),
+        }
+    }
+
+    /// Restores the state carried over from the button this one replaces.
+    pub fn restore(&self, carried: Carried) {
+        self.compositor_urgent.set(carried.compositor_urgent);
+        *self.notification_ids.borrow_mut() = carried.notification_ids;
+        self.anonymous_notifications
+            .set(carried.anonymous_notifications);
+        self.notification_tooltip
+            .replace(carried.notification_tooltip);
+        self.held.set(carried.held);
+        self.tray_attention.set(carried.tray_attention);
+        self.created.set(carried.created);
+        self.last_focused.set(carried.last_focused);
+
+        self.update_badge();
+        self.update_tooltip();
+        match carried.urgency {
+            Some(urgency) => self.set_notification_urgent(urgency),
+            None if carried.urgent => self.set_urgent(),
+            None => {}
+        }
+    }
+
     /// Sets whether the window represented by this button is currently focused.
     #[tracing::instrument(level = "TRACE")]
     pub fn set_focus(&self, focus: bool) {
@@ -92,91 +392,944 @@ impl Button {
 
         if focus {
             context.add_class("focused");
-            context.remove_class("urgent");
+            self.last_focused.set(Some(Instant::now()));
+            self.clear_notifications();
         } else {
             context.remove_class("focused");
         }
+
+        // Dimming is applied to the image rather than the button, so that the rest of the button
+        // isn't affected.
+        if let Some(image) = &self.image {
+            if self.state.config().dim_unfocused() && !focus {
+                image.style_context().add_class("dimmed");
+            } else {
+                image.style_context().remove_class("dimmed");
+            }
+        }
     }
 
-    /// Sets the window title.
-    #[tracing::instrument(level = "TRACE")]
-    pub fn set_title(&self, title: Option<&str>) {
-        self.button.set_tooltip_text(title);
-
-        // Apply any app styling rules.
-        if let Some(app_id) = &self.app_id {
-            if let Some(title) = title {
-                let config = self.state.config();
-                let context = self.button.style_context();
-
-                // First, remove all the possible classes for this app.
-                for class in config.app_classes(app_id) {
-                    context.remove_class(class);
-                }
+    /// Sets the window title, along with the rest of the window information shown in the label and
+    /// tooltip.
+    #[tracing::instrument(level = "TRACE", skip(window, workspace))]
+    pub fn set_title(&self, window: &Window, workspace: Option<&Workspace>) {
+        let title = window.title.as_deref();
+        self.set_tooltip(window, workspace);
+        self.set_label(window, workspace);
+
+        // Apply any app and title styling rules. Title rules apply even if there's no app ID.
+        if let Some(title) = title {
+            let app_id = self.app_id.as_deref().unwrap_or_default();
+            let config = self.state.config();
+            let context = self.button.style_context();
+
+            // First, remove all the possible classes for this app.
+            for class in config.app_classes(app_id) {
+                context.remove_class(class);
+            }
 
-                // Now add the classes that actually do match.
-                for class in config.app_matches(app_id, title) {
-                    context.add_class(class);
+            // Now add the classes that actually do match.
+            for class in config.app_matches(app_id, title) {
+                context.add_class(class);
+            }
+        }
+    }
+
+    fn set_tooltip(&self, window: &Window, workspace: Option<&Workspace>) {
+        let config = self.state.config();
+        if !config.tooltip() {
+            self.tooltip.replace(None);
+            self.update_tooltip();
+            return;
+        }
+
+        // An app rule can replace the tooltip, or remove it with an empty template.
+        let format = config
+            .app_tooltip_format(
+                self.app_id.as_deref().unwrap_or_default(),
+                window.title.as_deref().unwrap_or_default(),
+            )
+            .unwrap_or_else(|| config.tooltip_format());
+        let tooltip = format.render_lines(|key| {
+            // The template is markup, so the values need to be escaped.
+            self.placeholder(window, workspace, key)
+                .map(|value| glib::markup_escape_text(&value).to_string())
+        });
+
+        self.tooltip
+            .replace((!tooltip.is_empty()).then_some(tooltip));
+        self.update_tooltip();
+    }
+
+    /// Shows the tooltip for the windows, followed by the latest notification, if any.
+    fn update_tooltip(&self) {
+        let tooltip = self.tooltip.borrow();
+        let notification = self.notification_tooltip.borrow();
+        let markup = match (tooltip.as_deref(), notification.as_deref()) {
+            (Some(tooltip), Some(notification)) => Some(format!("{tooltip}\n\n{notification}")),
+            (tooltip, None) => tooltip.map(String::from),
+            // Without a tooltip for the windows, there's nothing to add the notification to: it's
+            // either been disabled, or an app rule removed it.
+            (None, Some(_)) => None,
+        };
+
+        self.button.set_tooltip_markup(markup.as_deref());
+    }
+
+    fn set_label(&self, window: &Window, workspace: Option<&Workspace>) {
+        let config = self.state.config();
+        let title = window.title.as_deref().unwrap_or_default();
+
+        // An app rule with a label template takes precedence over the display mode, which allows
+        // labels to be shown for specific windows even if the taskbar is otherwise icon-only.
+        let (text, markup) =
+            match config.app_label(self.app_id.as_deref().unwrap_or_default(), title) {
+                Some((template, captures)) => {
+                    let text = template.render_lines(|key| {
+                        // Capture groups from the rule's regex can be referenced by name or by index.
+                        captures
+                            .name(key)
+                            .or_else(|| key.parse().ok().and_then(|i| captures.get(i)))
+                            .map(|m| m.as_str().to_string())
+                            .or_else(|| self.placeholder(window, workspace, key))
+                            .map(|value| {
+                                if config.label_markup() {
+                                    glib::markup_escape_text(&value).to_string()
+                                } else {
+                                    value
+                                }
+                            })
+                    });
+                    (text, config.label_markup())
                 }
+                None if config.display().label() => (
+                    window
+                        .title
+                        .as_deref()
+                        .or(self.app_id.as_deref())
+                        .unwrap_or_default()
+                        .to_string(),
+                    false,
+                ),
+                None => (String::new(), false),
+            };
+
+        if markup {
+            self.label.set_markup(&text);
+        } else {
+            self.label.set_text(&text);
+        }
+        self.label.set_visible(!text.is_empty());
+    }
+
+    /// Returns the value of a template placeholder describing the window.
+    fn placeholder(
+        &self,
+        window: &Window,
+        workspace: Option<&Workspace>,
+        key: &str,
+    ) -> Option<String> {
+        match key {
+            "title" => window.title.clone(),
+            "app_id" => window.app_id.clone(),
+            "app_name" => window
+                .app_id
+                .as_deref()
+                .and_then(|id| self.state.desktop_cache().name(id)),
+            "workspace" => workspace.map(|workspace| match &workspace.name {
+                Some(name) => name.clone(),
+                None => workspace.idx.to_string(),
+            }),
+            "workspaces" => {
+                let workspaces = self.workspaces.borrow();
+                (!workspaces.is_empty()).then(|| workspaces.join(", "))
             }
+            "workspace_idx" => workspace.map(|workspace| workspace.idx.to_string()),
+            "workspace_name" => workspace.and_then(|workspace| workspace.name.clone()),
+            "output" => window.output().map(String::from),
+            "pid" => window.pid.map(|pid| pid.to_string()),
+            _ => None,
         }
     }
 
     /// Sets the window to urgent: that is, needing attention.
     ///
-    /// This state is automatically cleared the next time the window is focused.
+    /// This state is automatically cleared the next time the window is focused, or once the
+    /// urgent timeout has passed, if one is configured.
     #[tracing::instrument(level = "TRACE")]
     pub fn set_urgent(&self) {
         self.button.style_context().add_class("urgent");
+
+        let config = self.state.config();
+        let flash = config.urgent_flash();
+        if flash.enabled() && self.flash.borrow().is_none() {
+            self.start_flash(flash.interval(), flash.duration());
+        }
+
+        // Each new request for attention restarts the timeout.
+        if let Some(timeout) = config.urgent_timeout() {
+            if let Some(source) = self.urgent_timeout.take() {
+                source.remove();
+            }
+
+            // The notifications are cleared by the taskbar, rather than here, so that it can work
+            // out the workspace's urgency again afterwards.
+            let state = self.state.clone();
+            let window_id = self.windows.current();
+            let urgent_timeout = self.urgent_timeout.clone();
+            let source = glib::timeout_add_local_once(timeout, move || {
+                urgent_timeout.take();
+                state.urgent_timed_out(window_id);
+            });
+            self.urgent_timeout.replace(Some(source));
+        }
+    }
+
+    /// Applies the urgency Niri reports for the windows.
+    ///
+    /// Only changes are applied, so that an urgent window that was focused or timed out doesn't
+    /// become urgent again on the next snapshot, and so that urgency from notifications isn't
+    /// cleared by snapshots where Niri's view hasn't changed.
+    pub fn set_compositor_urgent(&self, urgent: bool) {
+        if urgent == self.compositor_urgent.replace(urgent) {
+            return;
+        }
+
+        if urgent {
+            self.set_urgent();
+        } else {
+            self.clear_urgent();
+        }
+    }
+
+    /// Records a notification attributed to one of the windows, which also makes the button
+    /// urgent.
+    ///
+    /// The notification's urgency is added as a class too, but only the highest urgency since the
+    /// button was last cleared is kept, so that a chat message doesn't hide a critical alert.
+    ///
+    /// During do not disturb, the notification is counted, but the button isn't made urgent until
+    /// [`Button::release_held`] is called.
+    pub fn notify(&self, notification: &EnrichedNotification, do_not_disturb: bool) {
+        match notification.id() {
+            Some(id) => {
+                self.notification_ids.borrow_mut().insert(id);
+            }
+            None => self
+                .anonymous_notifications
+                .set(self.anonymous_notifications.get() + 1),
+        }
+        self.update_badge();
+        self.set_notification_tooltip(notification);
+        self.adopt_notification_icon(notification);
+
+        let urgency = notification.notification().urgency();
+
+        if do_not_disturb {
+            let held = self.held.get().map_or(urgency, |held| held.max(urgency));
+            self.held.set(Some(held));
+        } else {
+            self.set_notification_urgent(urgency);
+        }
+    }
+
+    /// Uses the icon the notification asked for if the app doesn't have one of its own, which is
+    /// often the case for Electron apps.
+    fn adopt_notification_icon(&self, notification: &EnrichedNotification) {
+        let (Some(image), Some(app_id)) = (self.image.clone(), self.app_id.clone()) else {
+            return;
+        };
+        let Some(hint) = notification
+            .notification()
+            .app_icon
+            .as_deref()
+            .filter(|icon| !icon.is_empty())
+            .map(String::from)
+        else {
+            return;
+        };
+        if self.icon_pending.get() || self.icon_path.borrow().is_some() {
+            return;
+        }
+
+        let state = self.state.clone();
+        let icon_path = self.icon_path.clone();
+        let button = self.button.clone();
+        glib::spawn_future_local(async move {
+            let Some(path) = state.icon_cache().adopt_in_background(&app_id, &hint).await else {
+                return;
+            };
+
+            icon_path.replace(Some(path));
+            image.clear();
+            button.queue_resize();
+            state.icon_adopted(&app_id);
+        });
+    }
+
+    /// Appends the notification's summary, and optionally its body, to the tooltip.
+    fn set_notification_tooltip(&self, notification: &EnrichedNotification) {
+        let config = self.state.config();
+        if !config.notifications_tooltip() {
+            return;
+        }
+
+        // Some daemons support markup in the body, but we can't know which subset the sender
+        // expected, so it's all shown as text.
+        let notification = notification.notification();
+        let mut markup = format!("<b>{}</b>", glib::markup_escape_text(&notification.summary));
+        if config.notifications_tooltip_body()
+            && let Some(body) = notification.body.as_deref().filter(|body| !body.is_empty())
+        {
+            markup.push('\n');
+            markup.push_str(&glib::markup_escape_text(body));
+        }
+
+        self.notification_tooltip.replace(Some(markup));
+        self.update_tooltip();
+    }
+
+    /// Removes the notification from the tooltip.
+    fn clear_notification_tooltip(&self) {
+        if self.notification_tooltip.take().is_some() {
+            self.update_tooltip();
+        }
+    }
+
+    /// Applies a tray item of the windows needing attention, or no longer needing it.
+    ///
+    /// As with notifications, the button isn't made urgent during do not disturb until
+    /// [`Button::release_held`] is called. The urgent state is only removed when the item no
+    /// longer needs attention if nothing else has made the button urgent.
+    pub fn set_tray_attention(&self, attention: bool, do_not_disturb: bool) {
+        if attention == self.tray_attention.replace(attention) {
+            return;
+        }
+
+        if attention {
+            if !do_not_disturb {
+                self.set_urgent();
+            }
+        } else if !self.compositor_urgent.get() && self.notification_count() == 0 {
+            self.clear_urgent();
+        }
+    }
+
+    /// Makes the button urgent for the notifications and tray attention that arrived during do
+    /// not disturb, if any.
+    pub fn release_held(&self) {
+        if let Some(urgency) = self.held.take() {
+            self.set_notification_urgent(urgency);
+        } else if self.tray_attention.get() && !self.is_urgent() {
+            self.set_urgent();
+        }
+    }
+
+    fn set_notification_urgent(&self, urgency: Urgency) {
+        self.set_urgent();
+
+        let context = self.button.style_context();
+        let highest = URGENCY_CLASSES
+            .iter()
+            .filter(|(_, class)| context.has_class(class))
+            .map(|(level, _)| *level)
+            .fold(urgency, Urgency::max);
+        for (level, class) in URGENCY_CLASSES {
+            if level == highest {
+                context.add_class(class);
+            } else {
+                context.remove_class(class);
+            }
+        }
+    }
+
+    /// Forgets a notification that was closed in the notification daemon, removing the urgent
+    /// state once no notifications are left.
+    pub fn notification_closed(&self, id: u32) {
+        if !self.notification_ids.borrow_mut().remove(&id) {
+            return;
+        }
+
+        self.update_badge();
+        if self.notification_count() == 0 {
+            self.held.take();
+            self.clear_notification_tooltip();
+            self.clear_urgent();
+        }
+    }
+
+    /// Closes the notifications attributed to the windows in the notification daemon, if that's
+    /// configured, as when the button is activated.
+    pub fn dismiss_notifications(&self) {
+        dismiss(&self.state, &self.notification_ids);
+    }
+
+    /// Forgets the notifications attributed to the windows, and removes the urgent state.
+    pub fn clear_notifications(&self) {
+        let count = self.notification_count();
+        self.notification_ids.borrow_mut().clear();
+        self.anonymous_notifications.set(0);
+        if count > 0 {
+            self.update_badge();
+        }
+        self.held.take();
+        self.tray_attention.set(false);
+        self.clear_notification_tooltip();
+        self.clear_urgent();
+    }
+
+    /// Removes the urgent state, along with any flashing or pending timeout.
+    fn clear_urgent(&self) {
+        remove_urgent_classes(&self.button.style_context());
+        self.stop_flash();
+        if let Some(source) = self.urgent_timeout.take() {
+            source.remove();
+        }
+    }
+
+    /// Returns true if the window is currently marked as urgent.
+    pub fn is_urgent(&self) -> bool {
+        self.button.style_context().has_class("urgent")
+    }
+
+    /// Toggles the `urgent-flash` class on the button every interval until the duration has passed.
+    fn start_flash(&self, interval: Duration, duration: Duration) {
+        let button = self.button.clone();
+        let flash = self.flash.clone();
+        let started = Instant::now();
+
+        let source = glib::timeout_add_local(interval, move || {
+            let context = button.style_context();
+            if started.elapsed() >= duration {
+                context.remove_class("urgent-flash");
+                flash.take();
+                return ControlFlow::Break;
+            }
+
+            if context.has_class("urgent-flash") {
+                context.remove_class("urgent-flash");
+            } else {
+                context.add_class("urgent-flash");
+            }
+            ControlFlow::Continue
+        });
+
+        self.flash.replace(Some(source));
+    }
+
+    fn stop_flash(&self) {
+        if let Some(source) = self.flash.take() {
+            source.remove();
+        }
+        self.button.style_context().remove_class("urgent-flash");
+    }
+
+    /// Shows the progress and badge count from the app's launcher entry, if any.
+    pub fn set_launcher_entry(&self, entry: Option<&launcher::Entry>) {
+        match entry.and_then(launcher::Entry::progress) {
+            Some(progress) => {
+                self.progress.set_fraction(progress);
+                self.progress.show();
+            }
+            None => self.progress.hide(),
+        }
+
+        self.launcher_count
+            .set(entry.and_then(launcher::Entry::count));
+        self.update_badge();
+    }
+
+    /// Returns the number of notifications attributed to the windows.
+    fn notification_count(&self) -> usize {
+        self.notification_ids.borrow().len() + self.anonymous_notifications.get()
+    }
+
+    /// Shows the launcher entry count on the badge, or the number of notifications if the app
+    /// doesn't set a count.
+    fn update_badge(&self) {
+        let notifications = self.notification_count();
+        let count = match self.launcher_count.get() {
+            Some(count) => count,
+            None if notifications > 0 => notifications as i64,
+            None => {
+                self.badge.hide();
+                return;
+            }
+        };
+
+        self.badge.set_text(&count.to_string());
+        self.badge.show();
+    }
+
+    /// Sets the windows represented by this button, in display order.
+    ///
+    /// Normally this is only one window, but it can be more when grouping windows by app.
+    pub fn set_windows(&self, windows: &[&Window]) {
+        self.windows.set(windows);
+
+        let count = self.windows.len();
+        self.count.set_text(&count.to_string());
+        self.count.set_visible(count > 1);
+    }
+
+    /// Sets the names of the workspaces that the windows are on, which must be done before the
+    /// title is set.
+    pub fn set_workspaces(&self, workspaces: Vec<String>) {
+        self.workspaces.replace(workspaces);
+    }
+
+    /// Returns the windows represented by this button.
+    pub fn windows(&self) -> &Windows {
+        &self.windows
     }
 
     pub fn set_layout(&mut self, layout: niri_ipc::WindowLayout) {
         self.layout = layout
     }
 
+    /// Returns the app ID of the window represented by this button, if any.
+    pub fn app_id(&self) -> Option<&str> {
+        self.app_id.as_deref()
+    }
+
     /// Returns the actual [`gtk::Button`] widget.
     pub fn widget(&self) -> &gtk::Button {
         &self.button
     }
 
-    fn connect_click_handler(&self, window_id: u64) {
+    fn connect_drag_and_drop(&self) {
+        let state = self.state.clone();
+        let windows = self.windows.clone();
+
+        // Dropping one button on another moves the dropped window to the other window's position.
+        dnd::connect_source(&self.button, {
+            let windows = windows.clone();
+            move || windows.current()
+        });
+        dnd::connect_target(&self.button, move |_, source_id| {
+            let window_id = windows.current();
+            dnd::move_window_to_window(&state, source_id, window_id);
+        });
+    }
+
+    fn connect_click_handler(&self) {
+        // Touch presses are tracked so that they can be handled on release instead of on press:
+        // otherwise, starting a swipe or a long press on a button would immediately activate it.
+        let touch_press: Rc<Cell<Option<(f64, f64)>>> = Rc::default();
+
+        self.button.connect_button_press_event({
+            let state = self.state.clone();
+            let windows = self.windows.clone();
+            let notification_ids = self.notification_ids.clone();
+            let touch_press = touch_press.clone();
+
+            move |button, event| {
+                if is_touch(event) {
+                    touch_press.set(Some(event.position()));
+                    return Propagation::Stop;
+                }
+
+                // Gtk sends a normal press event for each click of a double click, followed by a
+                // double press event, so the single click action will always fire first.
+                let input = match (event.event_type(), event.button()) {
+                    (EventType::ButtonPress, 1) => Input::Left,
+                    (EventType::ButtonPress, 2) => Input::Middle,
+                    (EventType::ButtonPress, 3) => Input::Right,
+                    (EventType::DoubleButtonPress, 1) => Input::DoubleLeft,
+                    (EventType::DoubleButtonPress, 2) => Input::DoubleMiddle,
+                    (EventType::DoubleButtonPress, 3) => Input::DoubleRight,
+                    _ => return Propagation::Proceed,
+                };
+
+                Self::dispatch(
+                    &state,
+                    Trigger::with_state(input, event.state()),
+                    &windows,
+                    &notification_ids,
+                    button,
+                    Some(event),
+                )
+            }
+        });
+
+        self.button.connect_button_release_event({
+            let state = self.state.clone();
+            let windows = self.windows.clone();
+            let notification_ids = self.notification_ids.clone();
+            let touch_press = touch_press.clone();
+
+            move |button, event| {
+                let Some((x, y)) = touch_press.take() else {
+                    return Propagation::Proceed;
+                };
+
+                // If the touch point moved too far, then this was a swipe rather than a tap.
+                let (release_x, release_y) = event.position();
+                if (release_x - x).hypot(release_y - y) > TOUCH_SLOP {
+                    return Propagation::Stop;
+                }
+
+                Self::dispatch(
+                    &state,
+                    Trigger::with_state(Input::Left, event.state()),
+                    &windows,
+                    &notification_ids,
+                    button,
+                    Some(event),
+                )
+            }
+        });
+
+        // A long press on a touch screen opens the window menu, much like a right click. The
+        // gesture runs in the capture phase, since the handlers above stop the button events from
+        // propagating any further.
+        self.long_press.set_touch_only(true);
+        self.long_press
+            .set_propagation_phase(PropagationPhase::Capture);
+        self.long_press.connect_pressed({
+            let state = self.state.clone();
+            let windows = self.windows.clone();
+            let notification_ids = self.notification_ids.clone();
+            let button = self.button.clone();
+
+            move |gesture, _, _| {
+                // Forget the press, so that lifting the finger doesn't also activate the window.
+                touch_press.take();
+                Self::perform(
+                    &state,
+                    &ButtonAction::Menu,
+                    &windows,
+                    &notification_ids,
+                    &button,
+                    gesture
+                        .last_event(gesture.current_sequence().as_ref())
+                        .as_ref(),
+                );
+            }
+        });
+    }
+
+    fn connect_key_handler(&self) {
+        let state = self.state.clone();
+        let windows = self.windows.clone();
+        let notification_ids = self.notification_ids.clone();
+
+        // Moving between buttons is handled by the taskbar container, since it knows about all the
+        // buttons; here, we only have to handle the keys that act on this window.
+        self.button.connect_key_press_event(move |button, event| {
+            let action = match event.keyval() {
+                keys::Return | keys::KP_Enter | keys::space => ButtonAction::Activate,
+                keys::Menu => ButtonAction::Menu,
+                _ => return Propagation::Proceed,
+            };
+
+            Self::perform(
+                &state,
+                &action,
+                &windows,
+                &notification_ids,
+                button,
+                Some(event),
+            );
+            Propagation::Stop
+        });
+    }
+
+    fn connect_scroll_handler(&self) {
         let state = self.state.clone();
+        let windows = self.windows.clone();
+        let notification_ids = self.notification_ids.clone();
 
         self.button
-            .connect_button_press_event(move |_, event| match event.button() {
-                // Left mouse button
-                1 => {
-                    if let Err(e) = state.niri().activate_window(window_id) {
-                        tracing::warn!(%e, id = window_id, "error trying to activate window");
+            .add_events(EventMask::SCROLL_MASK | EventMask::SMOOTH_SCROLL_MASK);
+        let steps = ScrollSteps::default();
+        self.button.connect_scroll_event(move |button, event| {
+            let input = match steps.step(event) {
+                Some(true) => Input::ScrollDown,
+                Some(false) => Input::ScrollUp,
+                // Smooth scrolling that hasn't made up a step yet is still ours, so that it
+                // doesn't scroll the workspace behind the button instead.
+                None if event.direction() == ScrollDirection::Smooth => return Propagation::Stop,
+                None => return Propagation::Proceed,
+            };
+
+            Self::dispatch(
+                &state,
+                Trigger::with_state(input, event.state()),
+                &windows,
+                &notification_ids,
+                button,
+                Some(event),
+            )
+        });
+    }
+
+    fn connect_thumbnail(&self) {
+        let popover = gtk::Popover::new(Some(&self.button));
+        popover.set_modal(false);
+        popover.style_context().add_class("niri-taskbar-thumbnail");
+
+        let image = gtk::Image::new();
+        popover.add(&image);
+
+        // The pending timeout is tracked so that we can cancel it if the pointer leaves before the
+        // delay elapses. We also track whether the pointer is still hovering, since capturing the
+        // thumbnail is asynchronous.
+        let pending: Rc<RefCell<Option<glib::SourceId>>> = Rc::default();
+        let hovering = Rc::new(Cell::new(false));
+
+        self.button.connect_enter_notify_event({
+            let state = self.state.clone();
+            let windows = self.windows.clone();
+            let pending = pending.clone();
+            let hovering = hovering.clone();
+            let popover = popover.clone();
+
+            move |_, _| {
+                hovering.set(true);
+
+                let source = glib::timeout_add_local_once(state.config().thumbnails().delay(), {
+                    let state = state.clone();
+                    let windows = windows.clone();
+                    let pending = pending.clone();
+                    let hovering = hovering.clone();
+                    let popover = popover.clone();
+                    let image = image.clone();
+
+                    move || {
+                        pending.take();
+                        thumbnail::get(&state, windows.current(), move |pixbuf| {
+                            if hovering.get() {
+                                image.set_from_pixbuf(Some(&pixbuf));
+                                popover.show_all();
+                                popover.popup();
+                            }
+                        });
                     }
-                    Propagation::Stop
+                });
+
+                if let Some(old) = pending.replace(Some(source)) {
+                    old.remove();
                 }
-                // Middle mouse button
-                2 => {
-                    if let Err(e) = state.niri().close_window(window_id) {
-                        tracing::warn!(%e, id = window_id, "error trying to close window");
+                Propagation::Proceed
+            }
+        });
+
+        self.button.connect_leave_notify_event(move |_, _| {
+            hovering.set(false);
+            if let Some(source) = pending.take() {
+                source.remove();
+            }
+
+            popover.popdown();
+            Propagation::Proceed
+        });
+    }
+
+    /// Runs the action bound to the given trigger, if any.
+    fn dispatch(
+        state: &State,
+        trigger: Trigger,
+        windows: &Windows,
+        notification_ids: &RefCell<BTreeSet<u32>>,
+        button: &gtk::Button,
+        event: Option<&Event>,
+    ) -> Propagation {
+        let config = state.config();
+        let Some(action) = config.binding(&trigger) else {
+            return Propagation::Proceed;
+        };
+
+        Self::perform(state, action, windows, notification_ids, button, event);
+        Propagation::Stop
+    }
+
+    /// Runs the given action against the current window.
+    fn perform(
+        state: &State,
+        action: &ButtonAction,
+        windows: &Windows,
+        notification_ids: &RefCell<BTreeSet<u32>>,
+        button: &gtk::Button,
+        event: Option<&Event>,
+    ) {
+        if action == &ButtonAction::Activate {
+            dismiss(state, notification_ids);
+        }
+
+        if let Some(navigate) = navigation(action) {
+            state.niri().spawn("error handling button action", navigate);
+            return;
+        }
+
+        let window_id = windows.current();
+        // Requests to Niri are sent from a worker thread, so that a slow Niri doesn't freeze the
+        // bar, but anything touching Gtk has to happen here.
+        let spawn = |f: fn(&Niri, u64) -> Result<(), Error>, id: u64| {
+            state
+                .niri()
+                .spawn("error handling button action", move |niri| f(niri, id));
+        };
+        match action {
+            // Activating a focused group cycles through the windows in the group.
+            ButtonAction::Activate
+                if button.style_context().has_class("focused") && windows.len() > 1 =>
+            {
+                if let Some(next) = windows.next() {
+                    spawn(Niri::activate_window, next);
+                }
+            }
+            ButtonAction::Activate if button.style_context().has_class("focused") => {
+                match state.config().focused_click() {
+                    FocusedClick::Activate => spawn(Niri::activate_window, window_id),
+                    FocusedClick::FocusPrevious => {
+                        spawn(|niri, _| niri.focus_window_previous(), window_id)
                     }
-                    Propagation::Stop
+                    FocusedClick::Close => spawn(Niri::close_window, window_id),
+                    FocusedClick::None => {}
                 }
-                _ => Propagation::Proceed,
-            });
+            }
+            ButtonAction::Activate => spawn(Niri::activate_window, window_id),
+            ButtonAction::Close => spawn(Niri::close_window, window_id),
+            ButtonAction::Fullscreen => spawn(Niri::fullscreen_window, window_id),
+            ButtonAction::ToggleFloating => spawn(Niri::toggle_window_floating, window_id),
+            ButtonAction::SetWidth(change) => {
+                let change = *change;
+                state
+                    .niri()
+                    .spawn("error handling button action", move |niri| {
+                        niri.set_window_width(window_id, change)
+                    });
+            }
+            ButtonAction::MaximizeColumn => spawn(
+                |niri, id| {
+                    niri.activate_window(id)
+                        .and_then(|_| niri.maximize_column())
+                },
+                window_id,
+            ),
+            ButtonAction::Menu if windows.len() > 1 => {
+                popup_window_list(state, &windows.members(), button);
+            }
+            ButtonAction::Menu => {
+                menu::popup_window_menu(state, window_id, button, event);
+            }
+            ButtonAction::CycleNext | ButtonAction::CyclePrevious => {
+                // Cycling goes through the other windows of the same app, falling back to the
+                // other windows on the same workspace.
+                let forward = action == &ButtonAction::CycleNext;
+                if let Some(target) = state.window_order().cycle(window_id, forward) {
+                    spawn(Niri::activate_window, target);
+                }
+            }
+            ButtonAction::Exec(command) => {
+                // Window details can be substituted into the command. They're quoted, since
+                // titles in particular can contain anything.
+                let member = windows
+                    .members()
+                    .into_iter()
+                    .find(|member| member.id == window_id);
+                let command = command.render(|key| match key {
+                    "id" => window_id.to_string(),
+                    "app_id" => member
+                        .as_ref()
+                        .and_then(|member| member.app_id.clone())
+                        .unwrap_or_default(),
+                    "title" => member
+                        .as_ref()
+                        .and_then(|member| member.title.clone())
+                        .unwrap_or_default(),
+                    _ => String::new(),
+                });
+                if let Err(e) = exec::spawn(&command) {
+                    tracing::warn!(%e, command, "error spawning bound command");
+                }
+            }
+            // Handled above, since they don't act on the window.
+            ButtonAction::FocusColumnLeft
+            | ButtonAction::FocusColumnRight
+            | ButtonAction::FocusWindowUp
+            | ButtonAction::FocusWindowDown
+            | ButtonAction::None => {}
+        }
+    }
+
+    /// Looks the icon up in the background, and redraws it once it's found. This is also used to
+    /// reload the icon when the icon theme has changed.
+    ///
+    /// Nothing is drawn until the lookup finishes, so that the fallback icon doesn't flash up in
+    /// the meantime.
+    pub fn load_icon(&self) {
+        let (Some(image), Some(app_id)) = (self.image.clone(), self.app_id.clone()) else {
+            return;
+        };
+
+        self.icon_pending.set(true);
+        let state = self.state.clone();
+        let pid = self.pid;
+        let icon_path = self.icon_path.clone();
+        let icon_pending = self.icon_pending.clone();
+        let button = self.button.clone();
+        glib::spawn_future_local(async move {
+            let id = wine_icon_id(&state, app_id, pid).await;
+
+            // Configured icons can be files, rather than names in the icon theme.
+            let path = if Path::new(&id).is_absolute() {
+                Some(PathBuf::from(id))
+            } else {
+                let symbolic = state.config().icon_style() == IconStyle::Symbolic;
+                state.icon_cache().lookup_in_background(&id, symbolic).await
+            };
+
+            icon_path.replace(path);
+            icon_pending.set(false);
+
+            // Clearing the image forces the size allocation handler to redraw it.
+            image.clear();
+            button.queue_resize();
+        });
     }
 
     #[tracing::instrument(level = "TRACE")]
-    fn connect_size_allocate(&self, icon_path: Option<PathBuf>) {
+    fn connect_size_allocate(&self) {
+        let Some(image) = self.image.clone() else {
+            return;
+        };
+        let app_id = self.app_id.clone();
+        let icon_path = self.icon_path.clone();
+        let icon_pending = self.icon_pending.clone();
+        let icon_size = self.state.config().icon_size();
+        let icon_style = self.state.config().icon_style();
+        let icon_color = self.state.config().icon_color();
+        let theme = icon_theme(self.state.config().icon_theme());
         let last_size = RefCell::new(None);
 
+        // Moving to an output with a different scale doesn't necessarily change the allocation,
+        // so the icon has to be redrawn explicitly at the new scale.
+        self.button.connect_scale_factor_notify({
+            let image = image.clone();
+            move |button| {
+                image.clear();
+                button.queue_resize();
+            }
+        });
+
         self.button
             .connect_size_allocate(move |button, allocation| {
+                // The icon will be drawn once the lookup finishes.
+                if icon_pending.get() {
+                    return;
+                }
+
                 // Figure out if we actually need to redraw, since it's relatively expensive.
                 //
-                // The first condition is pretty easy: is there an image on the button? If not,
-                // then it's the first draw, and we have no choice but to draw.
-                let mut must_redraw = button.image().is_none();
+                // The first condition is pretty easy: has the image been set yet? If not, then
+                // it's the first draw, and we have no choice but to draw.
+                let mut must_redraw = image.storage_type() == ImageType::Empty;
 
                 // Otherwise, let's check if the size allocation has changed since the last time
-                // this was called.
-                if !must_redraw {
+                // this was called. If the icon size is fixed, then it doesn't matter.
+                if !must_redraw && icon_size.is_none() {
                     if let Some(last_size) = last_size.take() {
                         if &last_size != allocation {
                             must_redraw = true;
@@ -189,102 +1342,314 @@ impl Button {
                 }
 
                 if must_redraw {
-                    // Calculate the actual image size we need.
-                    //
-                    // Gtk3 doesn't provide a useful way to get the actual inner size of the
-                    // element after applying style rules, so we have to do that here, otherwise we
-                    // may draw the image too big and cause the container to grow. (Which will then
-                    // result in another size allocate signal, which will result in another
-                    // recalculation, which then results in your taskbar taking up your entire
-                    // display within a few seconds.)
-                    //
-                    // Blindly using StateFlags::NORMAL probably isn't actually the right
-                    // behaviour, but it's the best we've got for now.
-                    //
-                    // Note that we have to do this _after_ we figure out if we need to redraw:
-                    // calculating the style information is apparently expensive enough that Gtk
-                    // essentially busy-waits, which (a) burns CPU, and (b) means that :hover
-                    // styles don't get applied. What that means in practice is that, if waybar's
-                    // dynamically reloading CSS feature is enabled, sizing changes won't be
-                    // applied after the button is first rendered.
-                    //
-                    // That seems to be the price we have to pay, though, so here we are.
-                    let context = button.style_context();
-                    let border = context.border(StateFlags::NORMAL);
-                    let margin = context.margin(StateFlags::NORMAL);
-                    let padding = context.padding(StateFlags::NORMAL);
-
-                    let size = allocation.height()
-                        - border.vertical_size()
-                        - margin.vertical_size()
-                        - padding.vertical_size();
+                    // A configured icon size always wins over the allocation.
+                    let size = icon_size.unwrap_or_else(|| {
+                        // Calculate the actual image size we need.
+                        //
+                        // Gtk3 doesn't provide a useful way to get the actual inner size of the
+                        // element after applying style rules, so we have to do that here, otherwise
+                        // we may draw the image too big and cause the container to grow. (Which
+                        // will then result in another size allocate signal, which will result in
+                        // another recalculation, which then results in your taskbar taking up your
+                        // entire display within a few seconds.)
+                        //
+                        // Blindly using StateFlags::NORMAL probably isn't actually the right
+                        // behaviour, but it's the best we've got for now.
+                        //
+                        // Note that we have to do this _after_ we figure out if we need to redraw:
+                        // calculating the style information is apparently expensive enough that Gtk
+                        // essentially busy-waits, which (a) burns CPU, and (b) means that :hover
+                        // styles don't get applied. What that means in practice is that, if
+                        // waybar's dynamically reloading CSS feature is enabled, sizing changes
+                        // won't be applied after the button is first rendered.
+                        //
+                        // That seems to be the price we have to pay, though, so here we are.
+                        let context = button.style_context();
+                        let border = context.border(StateFlags::NORMAL);
+                        let margin = context.margin(StateFlags::NORMAL);
+                        let padding = context.padding(StateFlags::NORMAL);
+
+                        allocation.height()
+                            - border.vertical_size()
+                            - margin.vertical_size()
+                            - padding.vertical_size()
+                    });
+
+                    // Symbolic and monochrome icons are drawn in a single colour, which defaults to
+                    // the text colour of the button.
+                    let color = (icon_style != IconStyle::Normal).then(|| {
+                        icon_color
+                            .unwrap_or_else(|| button.style_context().color(StateFlags::NORMAL))
+                    });
 
                     // Now we know the size, we can actually load the image.
-                    let image =
-                        Self::icon_image(icon_path.as_ref(), button, size).unwrap_or_else(|| {
-                            // If we can't find an application icon, then we need to use a
-                            // fallback.
-                            static FALLBACK_ICON: &str = "application-x-executable";
-
-                            // We'll try to look the icon up in the default icon theme, since then
-                            // we can load up the actual image and control its scaling and display.
-                            ICON_THEME
-                                .with(|theme| {
-                                    theme.lookup_icon_for_scale(
-                                        FALLBACK_ICON,
-                                        size,
-                                        button.scale_factor(),
-                                        IconLookupFlags::empty(),
-                                    )
-                                })
-                                .and_then(|info| {
-                                    Self::icon_image(info.filename().as_ref(), button, size)
-                                })
-                                .unwrap_or_else(|| {
-                                    // But, if all else fails, we'll just use the default button
-                                    // size and YOLO it.
-                                    gtk::Image::from_icon_name(
-                                        Some(FALLBACK_ICON),
-                                        IconSize::Button,
-                                    )
-                                })
-                        });
+                    let surface = Self::icon_surface(
+                        icon_path.borrow().as_ref(),
+                        button,
+                        size,
+                        color.as_ref(),
+                    )
+                    .or_else(|| {
+                        // If we can't find an application icon, then we'll generate one from the
+                        // app ID, so that unknown apps can still be told apart.
+                        app_id.as_deref().and_then(|app_id| {
+                            icon::avatar::draw(app_id, size, button.scale_factor(), color.as_ref())
+                        })
+                    })
+                    .or_else(|| {
+                        // Without an app ID, we need to use a generic fallback.
+                        //
+                        // We'll try to look the icon up in the icon theme, since then we
+                        // can load up the actual image and control its scaling and
+                        // display.
+                        theme
+                            .lookup_icon_for_scale(
+                                FALLBACK_ICON,
+                                size,
+                                button.scale_factor(),
+                                IconLookupFlags::empty(),
+                            )
+                            .and_then(|info| {
+                                Self::icon_surface(
+                                    info.filename().as_ref(),
+                                    button,
+                                    size,
+                                    color.as_ref(),
+                                )
+                            })
+                    });
 
-                    // Finally, we can set the button image. Doing this from the callback doesn't
-                    // seem to work reliably for reasons I don't understand at all, but doing it
-                    // from the main loop as soon as possible does. :shrug:
-                    let button = button.clone();
-                    gtk::glib::source::idle_add_local_once(move || {
-                        button.set_image(Some(&image));
+                    // Finally, we can set the image. Doing this from the callback doesn't seem to
+                    // work reliably for reasons I don't understand at all, but doing it from the
+                    // main loop as soon as possible does. :shrug:
+                    let image = image.clone();
+                    gtk::glib::source::idle_add_local_once(move || match surface {
+                        Some(surface) => image.set_from_surface(Some(&surface)),
+                        // But, if all else fails, we'll just use the default button size and YOLO
+                        // it.
+                        None => image.set_from_icon_name(Some(FALLBACK_ICON), IconSize::Button),
                     });
                 }
             });
     }
 
-    fn icon_image(
+    fn icon_surface(
         icon_path: Option<&PathBuf>,
         button: &gtk::Button,
         size: i32,
-    ) -> Option<gtk::Image> {
-        let size = size * button.scale_factor();
-
+        color: Option<&RGBA>,
+    ) -> Option<cairo::Surface> {
         icon_path
-            .and_then(
-                |path| match Pixbuf::from_file_at_scale(path, size, size, true) {
-                    Ok(pixbuf) => Some(pixbuf),
-                    Err(e) => {
-                        tracing::info!(%e, ?path, "cannot load icon");
-                        None
-                    }
-                },
-            )
+            .and_then(|path| match icon::load(path, size, button.scale_factor()) {
+                Ok(pixbuf) => Some(pixbuf),
+                Err(e) => {
+                    tracing::info!(%e, ?path, "cannot load icon");
+                    None
+                }
+            })
+            .and_then(|pixbuf| match color {
+                Some(color) => icon::recolor(&pixbuf, color),
+                None => Some(pixbuf),
+            })
             .and_then(|pixbuf| pixbuf.create_surface(0, button.window().as_ref()))
-            .map(|surface| gtk::Image::from_surface(Some(&surface)))
     }
 
     pub fn pos(&self) -> &Option<(usize, usize)> {
         &self.layout.pos_in_scrolling_layout
     }
+
+    /// Returns when the button was created, which is when its first window was seen.
+    pub fn created(&self) -> Instant {
+        self.created.get()
+    }
+
+    /// Returns when a window represented by the button was last focused, if ever.
+    pub fn last_focused(&self) -> Option<Instant> {
+        self.last_focused.get()
+    }
+}
+
+/// Closes the given notifications in the notification daemon if they're configured to be dismissed
+/// when their button is activated.
+///
+/// The notifications are only closed here: the daemon's NotificationClosed signals will clear them
+/// from the button once it's done.
+fn dismiss(state: &State, notification_ids: &RefCell<BTreeSet<u32>>) {
+    if !state.config().notifications_dismiss_on_click() {
+        return;
+    }
+
+    let ids: Vec<u32> = notification_ids.borrow().iter().copied().collect();
+    if !ids.is_empty() {
+        notify::close(ids);
+    }
+}
+
+/// Pops up a list of windows attached to the given widget, which can be clicked to activate them.
+pub fn popup_window_list(state: &State, members: &[Member], widget: &impl IsA<gtk::Widget>) {
+    let popover = gtk::Popover::new(Some(widget));
+    popover
+        .style_context()
+        .add_class("niri-taskbar-window-list");
+
+    let list = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    for member in members.iter().cloned() {
+        let label = gtk::Label::new(Some(
+            member.title.as_deref().unwrap_or(&member.id.to_string()),
+        ));
+        label.set_xalign(0.0);
+        label.set_ellipsize(EllipsizeMode::End);
+        label.set_max_width_chars(50);
+
+        let item = gtk::Button::new();
+        item.set_relief(ReliefStyle::None);
+        item.add(&label);
+        item.connect_clicked({
+            let state = state.clone();
+            let popover = popover.clone();
+
+            move |_| {
+                state
+                    .niri()
+                    .spawn("error activating window from list", move |niri| {
+                        niri.activate_window(member.id)
+                    });
+                state.window_activated(member.id);
+                popover.popdown();
+            }
+        });
+        list.add(&item);
+    }
+    popover.add(&list);
+
+    // As with the window menu, the popover is detached once it's closed so that stale popovers
+    // don't accumulate on the widget.
+    popover.connect_closed(|popover| {
+        let popover = popover.clone();
+        glib::idle_add_local_once(move || popover.set_relative_to(None::<&gtk::Widget>));
+    });
+
+    popover.show_all();
+    popover.popup();
+}
+
+/// The size of the icon assumed when limiting the button width, which is Gtk's default size for
+/// button icons.
+pub const DEFAULT_ICON_SIZE: i32 = 16;
+
+/// The icon used when an application icon can't be found.
+pub static FALLBACK_ICON: &str = "application-x-executable";
+
+/// How far a touch point can move, in pixels, before a tap is considered to be a swipe instead.
+const TOUCH_SLOP: f64 = 16.0;
+
+/// Returns true if the event came from a touch screen.
+fn is_touch(event: &Event) -> bool {
+    event
+        .source_device()
+        .is_some_and(|device| device.source() == InputSource::Touchscreen)
+}
+
+/// The classes added for each notification urgency level.
+const URGENCY_CLASSES: [(Urgency, &str); 3] = [
+    (Urgency::Low, "urgent-low"),
+    (Urgency::Normal, "urgent-normal"),
+    (Urgency::Critical, "urgent-critical"),
+];
+
+/// Removes the urgent class, along with the urgency level and flashing classes.
+fn remove_urgent_classes(context: &gtk::StyleContext) {
+    context.remove_class("urgent");
+    context.remove_class("urgent-flash");
+    for (_, class) in URGENCY_CLASSES {
+        context.remove_class(class);
+    }
+}
+
+/// A request to Niri that doesn't need a window.
+type Request = fn(&Niri) -> Result<(), Error>;
+
+/// Returns the Niri request for an action that navigates the layout, rather than acting on the
+/// button's window.
+fn navigation(action: &ButtonAction) -> Option<Request> {
+    match action {
+        ButtonAction::FocusColumnLeft => Some(Niri::focus_column_left),
+        ButtonAction::FocusColumnRight => Some(Niri::focus_column_right),
+        ButtonAction::FocusWindowUp => Some(Niri::focus_window_up),
+        ButtonAction::FocusWindowDown => Some(Niri::focus_window_down),
+        _ => None,
+    }
+}
+
+/// Performs the layout navigation bound to a scroll step in the given direction, returning true if
+/// there was one.
+///
+/// This lets scrolling over the bar background navigate the layout, and not just scrolling over
+/// buttons.
+pub fn navigate_on_scroll(state: &State, event: &EventScroll, forward: bool) -> bool {
+    let input = if forward {
+        Input::ScrollDown
+    } else {
+        Input::ScrollUp
+    };
+
+    let config = state.config();
+    match config
+        .binding(&Trigger::with_state(input, event.state()))
+        .and_then(navigation)
+    {
+        Some(navigate) => {
+            state.niri().spawn("error navigating Niri layout", navigate);
+            true
+        }
+        None => false,
+    }
+}
+
+/// How far smooth scrolling has to go to count as one step, which is one notch of a scroll wheel.
+const SMOOTH_SCROLL_STEP: f64 = 1.0;
+
+/// Turns scroll events into steps.
+///
+/// Touchpads and high resolution wheels send many smooth scroll events with small deltas, so
+/// these are added up until they amount to a full step, rather than each being treated as one.
+#[derive(Debug, Default)]
+pub struct ScrollSteps(Cell<f64>);
+
+impl ScrollSteps {
+    /// Returns whether the event completes a step forward (down or right) or backward, or `None`
+    /// if it doesn't complete a step.
+    pub fn step(&self, event: &EventScroll) -> Option<bool> {
+        match event.direction() {
+            ScrollDirection::Down | ScrollDirection::Right => {
+                self.0.set(0.0);
+                Some(true)
+            }
+            ScrollDirection::Up | ScrollDirection::Left => {
+                self.0.set(0.0);
+                Some(false)
+            }
+            ScrollDirection::Smooth if event.is_stop() => {
+                self.0.set(0.0);
+                None
+            }
+            ScrollDirection::Smooth => {
+                let (dx, dy) = event.delta();
+                let delta = if dy.abs() >= dx.abs() { dy } else { dx };
+
+                // Any distance left over is kept towards the next step, so that a fast scroll
+                // still takes as many steps as it covers.
+                let total = self.0.get() + delta;
+                if total.abs() < SMOOTH_SCROLL_STEP {
+                    self.0.set(total);
+                    return None;
+                }
+                self.0.set(total - SMOOTH_SCROLL_STEP.copysign(total));
+                Some(total > 0.0)
+            }
+            _ => None,
+        }
+    }
 }
 
 trait BorderExt {
@@ -0,0 +1,108 @@
+//! The windows represented by a taskbar button.
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use crate::niri;
+
+/// The windows represented by a button.
+///
+/// Normally this is a single window, but a button represents every window of an app on a
+/// workspace when grouping by app. The handles are shared with the button's event handlers, so
+/// updating the windows here also updates which window the handlers act on.
+#[derive(Debug, Clone)]
+pub struct Windows {
+    current: Rc<Cell<u64>>,
+    members: Rc<RefCell<Vec<Member>>>,
+}
+
+/// A single window within a button.
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub id: u64,
+    pub app_id: Option<String>,
+    pub title: Option<String>,
+    pub pos: Option<(usize, usize)>,
+}
+
+impl Member {
+    fn new(window: &niri_ipc::Window) -> Self {
+        Self {
+            id: window.id,
+            app_id: window.app_id.clone(),
+            title: window.title.clone(),
+            pos: window.layout.pos_in_scrolling_layout,
+        }
+    }
+}
+
+impl Windows {
+    /// Creates a new set containing only the given window.
+    pub fn new(window: &niri_ipc::Window) -> Self {
+        Self {
+            current: Rc::new(Cell::new(window.id)),
+            members: Rc::new(RefCell::new(vec![Member::new(window)])),
+        }
+    }
+
+    /// Replaces the windows, which must be given in display order.
+    ///
+    /// The current window becomes the focused window if there is one; otherwise the current window
+    /// is kept if it still exists, falling back to the first window.
+    pub fn set(&self, windows: &[&niri::Window]) {
+        let current = self.current.get();
+        let next = windows
+            .iter()
+            .find(|window| window.is_focused)
+            .or_else(|| windows.iter().find(|window| window.id == current))
+            .or_else(|| windows.first());
+        if let Some(window) = next {
+            self.current.set(window.id);
+        }
+
+        self.members
+            .replace(windows.iter().map(|window| Member::new(window)).collect());
+    }
+
+    /// Returns the window that actions on the button apply to.
+    pub fn current(&self) -> u64 {
+        self.current.get()
+    }
+
+    /// Returns the window after the current window, wrapping around at the end, or `None` if
+    /// there's only one window.
+    pub fn next(&self) -> Option<u64> {
+        let members = self.members.borrow();
+        if members.len() < 2 {
+            return None;
+        }
+
+        let current = self.current.get();
+        let idx = members
+            .iter()
+            .position(|member| member.id == current)
+            .map(|idx| (idx + 1) % members.len())
+            .unwrap_or_default();
+        Some(members[idx].id)
+    }
+
+    /// Returns true if the given window is represented by the button.
+    pub fn contains(&self, window_id: u64) -> bool {
+        self.members
+            .borrow()
+            .iter()
+            .any(|member| member.id == window_id)
+    }
+
+    /// Returns the number of windows.
+    pub fn len(&self) -> usize {
+        self.members.borrow().len()
+    }
+
+    /// Returns a copy of the windows, in display order.
+    pub fn members(&self) -> Vec<Member> {
+        self.members.borrow().clone()
+    }
+}
@@ -1,34 +1,139 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, path::PathBuf, sync::LazyLock, time::Duration};
 
 use itertools::Itertools;
-use regex::Regex;
+use niri_ipc::Workspace;
+use regex::{Captures, Regex};
 use serde::{Deserialize, Deserializer};
 
+pub use bindings::{Bindings, ButtonAction, Input, Trigger};
+
+use waybar_cffi::gtk::gdk::RGBA;
+
+use crate::{notify::Urgency, template::Template};
+
+mod bindings;
+pub mod file;
+
+/// Options that aren't recognised, keyed by name.
+type Unknown = serde_json::Map<String, serde_json::Value>;
+
 /// The taskbar configuration.
 #[derive(Debug, Default, Deserialize)]
 pub struct Config {
     #[serde(default)]
-    apps: HashMap<String, Vec<AppConfig>>,
+    config: Option<String>,
+    #[serde(default, deserialize_with = "deserialise_apps")]
+    apps: Vec<AppRules>,
+    #[serde(default)]
+    title_rules: Vec<AppConfig>,
+    #[serde(default)]
+    ignore: Vec<IgnoreRule>,
     #[serde(default)]
     notifications: Notifications,
     #[serde(default)]
     show_all_outputs: bool,
+    #[serde(default, deserialize_with = "deserialise_outputs")]
+    output: Vec<String>,
     #[serde(default)]
-    orientation: Orientation,
+    exclude_outputs: Vec<String>,
     #[serde(default)]
-    workspace_format: String,
+    outputs: HashMap<String, serde_json::Map<String, serde_json::Value>>,
+    // The configuration as it was written, which per-output overrides are applied on top of.
+    #[serde(skip)]
+    raw: serde_json::Value,
     #[serde(default)]
-    workspace_format_focused: String,
+    orientation: Option<Orientation>,
+    #[serde(default)]
+    workspace_format: Template,
+    #[serde(default)]
+    workspace_format_focused: Template,
     #[serde(default)]
     close_to_tiling: bool,
     #[serde(default)]
     keep_on_active_workspace: KeepOnActiveWorkspace,
+    #[serde(default)]
+    bindings: Bindings,
+    #[serde(default)]
+    focused_click: FocusedClick,
+    #[serde(default = "default_true")]
+    tooltip: bool,
+    #[serde(default)]
+    tooltip_format: Option<Template>,
+    #[serde(default)]
+    thumbnails: Thumbnails,
+    #[serde(default)]
+    display: Display,
+    #[serde(default)]
+    icon_size: Option<i32>,
+    #[serde(default)]
+    spacing: Spacing,
+    #[serde(default)]
+    workspace_separators: bool,
+    #[serde(default)]
+    urgent_flash: UrgentFlash,
+    #[serde(default)]
+    urgent_timeout: Option<u64>,
+    #[serde(default)]
+    poll_interval: Option<u64>,
+    #[serde(default)]
+    focus_indicator: bool,
+    #[serde(default)]
+    group_by_app: bool,
+    #[serde(default)]
+    launcher_entries: LauncherEntries,
+    #[serde(default)]
+    tray_attention: TrayAttention,
+    #[serde(default)]
+    wine: Wine,
+    #[serde(default)]
+    overflow: Overflow,
+    #[serde(default)]
+    max_button_width: Option<i32>,
+    #[serde(default)]
+    icon_style: IconStyle,
+    #[serde(default, deserialize_with = "deserialise_color")]
+    icon_color: Option<RGBA>,
+    #[serde(default)]
+    dim_unfocused: bool,
+    #[serde(default)]
+    pinned: Vec<String>,
+    #[serde(default)]
+    css: Option<String>,
+    #[serde(default)]
+    icon_theme: Option<String>,
+    #[serde(default)]
+    workspace_names: bool,
+    #[serde(default)]
+    show_empty_workspaces: bool,
+    #[serde(default, deserialize_with = "deserialise_regexes")]
+    hidden_workspaces: Vec<Regex>,
+    #[serde(default)]
+    workspace_icons: HashMap<String, String>,
+    #[serde(default)]
+    follow_dropped_windows: bool,
+    #[serde(default)]
+    active_workspace_only: bool,
+    #[serde(default)]
+    layout: Layout,
+    #[serde(default)]
+    sort: Sort,
+    #[serde(default)]
+    stable_order: bool,
+    #[serde(default)]
+    output_headers: bool,
+    #[serde(default)]
+    label_markup: bool,
+    // Options we don't recognise, which are reported rather than silently ignored.
+    #[serde(flatten)]
+    unknown: Unknown,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Default, Deserialize)]
 pub struct KeepOnActiveWorkspace {
     floating: bool,
     apps: Vec<AppId>,
+    #[serde(flatten)]
+    unknown: Unknown,
 }
 
 #[derive(Debug, Deserialize)]
@@ -37,15 +142,6 @@ pub struct AppId {
     title: Option<String>,
 }
 
-impl Default for KeepOnActiveWorkspace {
-    fn default() -> Self {
-        KeepOnActiveWorkspace {
-            floating: false,
-            apps: vec![],
-        }
-    }
-}
-
 impl KeepOnActiveWorkspace {
     pub fn matches(&self, id: &str, title: &str, floating: bool) -> bool {
         if self.floating && floating {
@@ -79,6 +175,138 @@ pub struct Notifications {
     use_desktop_entry: bool,
     #[serde(default)]
     use_fuzzy_matching: bool,
+    #[serde(default)]
+    strategies: Option<Vec<MatchStrategy>>,
+    #[serde(default)]
+    apps: HashMap<String, AppNotifications>,
+    #[serde(default = "default_min_urgency")]
+    min_urgency: Urgency,
+    #[serde(default)]
+    cache: NotificationCache,
+    #[serde(default)]
+    do_not_disturb: bool,
+    #[serde(default)]
+    do_not_disturb_button: bool,
+    #[serde(default)]
+    dismiss_on_click: bool,
+    #[serde(default = "default_true")]
+    tooltip: bool,
+    #[serde(default)]
+    tooltip_body: bool,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+/// Settings for the cache mapping D-Bus connections to the PIDs that sent notifications.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct NotificationCache {
+    #[serde(default = "default_true")]
+    enabled: bool,
+    #[serde(default = "default_notification_cache_expiry")]
+    expiry: u64,
+    #[serde(default = "default_notification_cache_cleanup_interval")]
+    cleanup_interval: u64,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+impl Default for NotificationCache {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            expiry: default_notification_cache_expiry(),
+            cleanup_interval: default_notification_cache_cleanup_interval(),
+            unknown: Default::default(),
+        }
+    }
+}
+
+impl NotificationCache {
+    /// Returns true if connection PIDs should be cached, rather than looked up for every
+    /// notification.
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Returns how long an unused connection stays in the cache.
+    pub fn expiry(&self) -> Duration {
+        Duration::from_secs(self.expiry)
+    }
+
+    /// Returns how often expired connections are removed from the cache.
+    ///
+    /// An interval of zero would remove them continuously, so it's raised to one second.
+    pub fn cleanup_interval(&self) -> Duration {
+        if self.cleanup_interval == 0 {
+            tracing::warn!("notifications cache cleanup_interval is zero; using one second");
+            return Duration::from_secs(1);
+        }
+        Duration::from_secs(self.cleanup_interval)
+    }
+}
+
+fn default_notification_cache_expiry() -> u64 {
+    86400
+}
+
+fn default_notification_cache_cleanup_interval() -> u64 {
+    60
+}
+
+/// The shortest interval Niri is polled at.
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The options that Waybar handles itself for every module, which are passed to the module along
+/// with its own.
+const WAYBAR_OPTIONS: &[&str] = &[
+    "module_path",
+    "actions",
+    "expand",
+    "menu",
+    "menu-file",
+    "menu-actions",
+    "smooth-scrolling-threshold",
+    "on-click",
+    "on-click-middle",
+    "on-click-right",
+    "on-click-backward",
+    "on-click-forward",
+    "on-double-click",
+    "on-double-click-middle",
+    "on-double-click-right",
+    "on-triple-click",
+    "on-triple-click-middle",
+    "on-triple-click-right",
+    "on-scroll-up",
+    "on-scroll-down",
+    "on-scroll-left",
+    "on-scroll-right",
+];
+
+/// A way of matching a notification to the windows of the app that sent it.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MatchStrategy {
+    /// Walks up the process tree from the process that sent the notification.
+    Pid,
+    /// Matches app IDs against the Flatpak app that sent the notification, found from the
+    /// sender's cgroup.
+    Flatpak,
+    /// Matches app IDs against the desktop entry named in the notification.
+    DesktopEntry,
+    /// Matches app IDs against the desktop entry case insensitively, or by their last component.
+    Fuzzy,
+    /// Matches app IDs and desktop entry names against the notification's app name.
+    AppName,
+}
+
+/// Notification settings that override the global settings for a single app ID.
+#[derive(Debug, Deserialize)]
+pub struct AppNotifications {
+    #[serde(default = "default_true")]
+    enabled: bool,
+    #[serde(flatten)]
+    unknown: Unknown,
 }
 
 impl Default for Notifications {
@@ -88,40 +316,555 @@ impl Default for Notifications {
             map_app_ids: Default::default(),
             use_desktop_entry: true,
             use_fuzzy_matching: Default::default(),
+            strategies: None,
+            apps: Default::default(),
+            min_urgency: default_min_urgency(),
+            cache: Default::default(),
+            do_not_disturb: false,
+            do_not_disturb_button: false,
+            dismiss_on_click: false,
+            tooltip: true,
+            tooltip_body: false,
+            unknown: Default::default(),
+        }
+    }
+}
+
+/// How to handle more buttons than fit on the bar, which can be set separately for each bar
+/// orientation.
+#[derive(Debug, Default, Deserialize)]
+pub struct Overflow {
+    #[serde(default)]
+    horizontal: OverflowRule,
+    #[serde(default)]
+    vertical: OverflowRule,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OverflowRule {
+    #[serde(default)]
+    mode: OverflowMode,
+    #[serde(default = "default_overflow_max_size")]
+    max_size: i32,
+    #[serde(default = "default_overflow_max_buttons")]
+    max_buttons: usize,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+impl Default for OverflowRule {
+    fn default() -> Self {
+        Self {
+            mode: OverflowMode::default(),
+            max_size: default_overflow_max_size(),
+            max_buttons: default_overflow_max_buttons(),
+            unknown: Default::default(),
+        }
+    }
+}
+
+impl OverflowRule {
+    pub fn mode(&self) -> OverflowMode {
+        self.mode
+    }
+
+    /// Returns the maximum length of the taskbar in scroll mode, in pixels.
+    pub fn max_size(&self) -> i32 {
+        self.max_size
+    }
+
+    /// Returns the maximum number of buttons shown in chip mode.
+    pub fn max_buttons(&self) -> usize {
+        self.max_buttons
+    }
+}
+
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverflowMode {
+    /// The taskbar grows to fit every button.
+    #[default]
+    Grow,
+    /// The taskbar scrolls once it reaches its maximum size.
+    Scroll,
+    /// Buttons beyond the maximum are replaced by a chip that lists them.
+    Chip,
+}
+
+fn default_overflow_max_size() -> i32 {
+    800
+}
+
+fn default_overflow_max_buttons() -> usize {
+    10
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LauncherEntries {
+    #[serde(default = "default_true")]
+    enabled: bool,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+impl Default for LauncherEntries {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            unknown: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TrayAttention {
+    #[serde(default = "default_true")]
+    enabled: bool,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+impl Default for TrayAttention {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            unknown: Default::default(),
+        }
+    }
+}
+
+/// Settings for finding the icons of Windows programs running under Wine.
+#[derive(Debug, Deserialize)]
+pub struct Wine {
+    #[serde(default = "default_true")]
+    enabled: bool,
+    #[serde(default = "default_wine_app_ids")]
+    app_ids: Vec<String>,
+    #[serde(default)]
+    icons: HashMap<String, String>,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+impl Default for Wine {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            app_ids: default_wine_app_ids(),
+            icons: Default::default(),
+            unknown: Default::default(),
+        }
+    }
+}
+
+fn default_wine_app_ids() -> Vec<String> {
+    ["wine", "wine64", "wine-preloader", "wine64-preloader"]
+        .into_iter()
+        .map(String::from)
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Thumbnails {
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default = "default_thumbnail_size")]
+    size: i32,
+    #[serde(default = "default_thumbnail_delay")]
+    delay: u64,
+    #[serde(default = "default_thumbnail_max_age")]
+    max_age: u64,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+impl Default for Thumbnails {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            size: default_thumbnail_size(),
+            delay: default_thumbnail_delay(),
+            max_age: default_thumbnail_max_age(),
+            unknown: Default::default(),
+        }
+    }
+}
+
+impl Thumbnails {
+    /// Returns true if hover thumbnails are enabled.
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Returns the maximum width or height of a thumbnail, in pixels.
+    pub fn size(&self) -> i32 {
+        self.size
+    }
+
+    /// Returns how long the pointer has to hover over a button before a thumbnail is shown.
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay)
+    }
+
+    /// Returns how long a captured thumbnail is reused before a new one is captured.
+    pub fn max_age(&self) -> Duration {
+        Duration::from_secs(self.max_age)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UrgentFlash {
+    #[serde(default)]
+    enabled: bool,
+    #[serde(default = "default_urgent_flash_duration")]
+    duration: u64,
+    #[serde(default = "default_urgent_flash_interval")]
+    interval: u64,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+impl Default for UrgentFlash {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            duration: default_urgent_flash_duration(),
+            interval: default_urgent_flash_interval(),
+            unknown: Default::default(),
+        }
+    }
+}
+
+impl UrgentFlash {
+    /// Returns true if urgent buttons should flash.
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Returns how long an urgent button flashes for.
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.duration)
+    }
+
+    /// Returns how long each half of a flash lasts.
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval)
+    }
+}
+
+fn default_urgent_flash_duration() -> u64 {
+    5
+}
+
+fn default_urgent_flash_interval() -> u64 {
+    500
+}
+
+/// Spacing between the widgets within the taskbar, in pixels.
+#[derive(Debug, Deserialize)]
+pub struct Spacing {
+    #[serde(default)]
+    workspaces: i32,
+    #[serde(default)]
+    buttons: i32,
+    #[serde(default = "default_button_content_spacing")]
+    button_content: i32,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+impl Default for Spacing {
+    fn default() -> Self {
+        Self {
+            workspaces: 0,
+            buttons: 0,
+            button_content: default_button_content_spacing(),
+            unknown: Default::default(),
         }
     }
 }
 
+impl Spacing {
+    /// Returns the spacing between workspaces.
+    pub fn workspaces(&self) -> i32 {
+        self.workspaces
+    }
+
+    /// Returns the spacing between the buttons within a workspace.
+    pub fn buttons(&self) -> i32 {
+        self.buttons
+    }
+
+    /// Returns the spacing between the icon and label within a button.
+    pub fn button_content(&self) -> i32 {
+        self.button_content
+    }
+}
+
+fn default_button_content_spacing() -> i32 {
+    4
+}
+
+fn default_thumbnail_size() -> i32 {
+    256
+}
+
+fn default_thumbnail_delay() -> u64 {
+    500
+}
+
+fn default_thumbnail_max_age() -> u64 {
+    10
+}
+
 fn default_true() -> bool {
     true
 }
 
-#[derive(Debug, Deserialize, Clone, Copy)]
+fn default_min_urgency() -> Urgency {
+    Urgency::Low
+}
+
+#[derive(Debug, Default, Deserialize, Clone, Copy)]
 pub enum Orientation {
     #[serde(rename = "vertical")]
     Vertical,
+    #[default]
     #[serde(rename = "horizontal")]
     Horizontal,
 }
 
-impl Default for Orientation {
-    fn default() -> Self {
-        Orientation::Horizontal
+static DEFAULT_TOOLTIP_FORMAT: LazyLock<Template> = LazyLock::new(|| {
+    Template::new("<b>{title}</b>\n{app_name}\nWorkspace {workspace} on {output}\nPID {pid}")
+});
+
+/// How application icons are drawn.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum IconStyle {
+    /// The icon is drawn as is.
+    #[default]
+    Normal,
+    /// The symbolic variant of the icon is preferred, and is drawn in the icon colour.
+    Symbolic,
+    /// The icon is drawn in the icon colour.
+    Monochrome,
+}
+
+/// What each window button shows.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Display {
+    /// Only the application icon.
+    #[default]
+    Icon,
+    /// Only the window title.
+    Label,
+    /// The application icon, followed by the window title.
+    IconLabel,
+}
+
+impl Display {
+    /// Returns true if buttons should include the application icon.
+    pub fn icon(self) -> bool {
+        matches!(self, Self::Icon | Self::IconLabel)
+    }
+
+    /// Returns true if buttons should include a label.
+    pub fn label(self) -> bool {
+        matches!(self, Self::Label | Self::IconLabel)
     }
 }
 
+/// How window buttons are laid out.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// Buttons are grouped by workspace.
+    #[default]
+    Workspaces,
+    /// Each app has a single button, regardless of which workspaces its windows are on.
+    Apps,
+    /// A single strip of buttons, ordered by workspace, without workspace labels.
+    Flat,
+}
+
+impl Layout {
+    /// Returns true if buttons are grouped into workspace containers.
+    pub fn workspaces(self) -> bool {
+        self == Self::Workspaces
+    }
+}
+
+/// How window buttons are ordered within a workspace.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Sort {
+    /// Buttons follow the position of their windows in the Niri layout.
+    #[default]
+    Position,
+    /// Buttons stay in the order their windows were first seen.
+    Insertion,
+    /// Buttons are ordered alphabetically by app ID.
+    AppId,
+    /// The most recently focused window comes first.
+    Focus,
+}
+
+/// What to do when the activate action is triggered on the already focused window.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum FocusedClick {
+    /// Activates the window again.
+    #[default]
+    Activate,
+    /// Focuses the previously focused window.
+    FocusPrevious,
+    /// Closes the window.
+    Close,
+    /// Does nothing.
+    None,
+}
+
 impl Config {
+    /// Deserialises the configuration provided by Waybar.
+    ///
+    /// Waybar hands the configuration over as JSON, which is deserialised here rather than by
+    /// Waybar so that an invalid configuration can be reported in the taskbar.
+    pub fn from_value(value: serde_json::Value) -> Result<Self, serde_json::Error> {
+        let mut config = Self::deserialize(&value)?;
+        config.raw = value;
+        Ok(config)
+    }
+
+    /// Returns the names of any options that aren't recognised, which are probably typos.
+    ///
+    /// Waybar's own module options, such as `module_path`, are included in the module
+    /// configuration, so they aren't reported: these are all written with dashes rather than
+    /// underscores, except for `module_path`.
+    pub fn unknown_options(&self) -> Vec<String> {
+        let top_level = self
+            .unknown
+            .keys()
+            .filter(|key| !WAYBAR_OPTIONS.contains(&key.as_str()))
+            .cloned();
+
+        let nested = [
+            ("notifications", &self.notifications.unknown),
+            ("notifications.cache", &self.notifications.cache.unknown),
+            ("launcher_entries", &self.launcher_entries.unknown),
+            ("tray_attention", &self.tray_attention.unknown),
+            ("wine", &self.wine.unknown),
+            ("thumbnails", &self.thumbnails.unknown),
+            ("urgent_flash", &self.urgent_flash.unknown),
+            ("spacing", &self.spacing.unknown),
+            ("overflow", &self.overflow.unknown),
+            ("overflow.horizontal", &self.overflow.horizontal.unknown),
+            ("overflow.vertical", &self.overflow.vertical.unknown),
+            (
+                "keep_on_active_workspace",
+                &self.keep_on_active_workspace.unknown,
+            ),
+        ]
+        .into_iter()
+        .map(|(prefix, unknown)| (prefix.to_string(), unknown));
+
+        // Rules are identified by where they are in their list.
+        let rules =
+            self.apps
+                .iter()
+                .flat_map(|rules| {
+                    rules
+                        .rules
+                        .iter()
+                        .enumerate()
+                        .map(|(i, rule)| (format!("apps.{}[{i}]", rules.key), &rule.unknown))
+                })
+                .chain(
+                    self.title_rules
+                        .iter()
+                        .enumerate()
+                        .map(|(i, rule)| (format!("title_rules[{i}]"), &rule.unknown)),
+                )
+                .chain(
+                    self.ignore
+                        .iter()
+                        .enumerate()
+                        .map(|(i, rule)| (format!("ignore[{i}]"), &rule.unknown)),
+                )
+                .chain(
+                    self.notifications.apps.iter().map(|(app_id, app)| {
+                        (format!("notifications.apps.{app_id}"), &app.unknown)
+                    }),
+                );
+
+        let nested = nested
+            .chain(rules)
+            .flat_map(|(prefix, unknown)| unknown.keys().map(move |key| format!("{prefix}.{key}")));
+
+        // Overrides are only parsed once the output is known, so they're checked here too. The
+        // options they share with the main configuration have already been reported.
+        let mut unknown: Vec<String> = top_level.chain(nested).collect();
+        let overrides = self
+            .outputs
+            .keys()
+            .sorted()
+            .filter_map(|output| match self.for_output(output)? {
+                Ok(config) => Some((output, config.unknown_options())),
+                Err(_) => None,
+            })
+            .flat_map(|(output, options)| {
+                options
+                    .into_iter()
+                    .filter(|option| !unknown.contains(option))
+                    .map(move |option| format!("outputs.{output}.{option}"))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        unknown.extend(overrides);
+        unknown
+    }
+
+    /// Returns the configuration with the overrides for the given output applied, or `None` if
+    /// there aren't any overrides for the output.
+    ///
+    /// Each option in the overrides replaces the option of the same name, rather than being merged
+    /// into it.
+    pub fn for_output(&self, output: &str) -> Option<Result<Self, serde_json::Error>> {
+        let overrides = self.outputs.get(output)?;
+
+        let mut value = self.raw.clone();
+        if let Some(object) = value.as_object_mut() {
+            object.remove("outputs");
+            object.extend(overrides.clone());
+        }
+        Some(Self::from_value(value))
+    }
+
+    /// Returns the rules that apply to the given application.
+    ///
+    /// Rules keyed by the exact app ID come first, followed by the rules of any other keys whose
+    /// regex matches the app ID, in key order, and finally the title rules, which apply to every
+    /// application.
+    fn app_rules<'a>(&'a self, app_id: &str) -> impl Iterator<Item = &'a AppConfig> {
+        let exact = self.apps.iter().filter(move |rules| rules.key == app_id);
+        let matched = self
+            .apps
+            .iter()
+            .filter(move |rules| rules.key != app_id && rules.re.is_match(app_id));
+        exact
+            .chain(matched)
+            .flat_map(|rules| rules.rules.iter())
+            .chain(self.title_rules.iter())
+    }
+
     /// Returns all possible CSS classes that a particular application might have set.
     pub fn app_classes(&self, app_id: &str) -> Vec<&str> {
-        self.apps
-            .get(app_id)
-            .map(|configs| {
-                configs
-                    .iter()
-                    .map(|config| config.class.as_str())
-                    .collect_vec()
-            })
-            .unwrap_or_default()
+        self.app_rules(app_id)
+            .filter_map(|config| config.class.as_deref())
+            .collect_vec()
     }
 
     /// Returns the actual CSS classes that should be set for the given application and title.
@@ -129,16 +872,32 @@ impl Config {
         &'a self,
         app_id: &str,
         title: &'a str,
-    ) -> Box<dyn Iterator<Item = &'a str> + 'a> {
-        match self.apps.get(app_id) {
-            Some(configs) => Box::new(
-                configs
-                    .iter()
-                    .filter(|config| config.re.is_match(title))
-                    .map(|config| config.class.as_str()),
-            ),
-            None => Box::new(std::iter::empty()),
-        }
+    ) -> impl Iterator<Item = &'a str> {
+        self.app_rules(app_id)
+            .filter(|config| config.re.is_match(title))
+            .filter_map(|config| config.class.as_deref())
+    }
+
+    /// Returns the label template of the first matching rule for the given application and title
+    /// that has one, along with the regex captures from the title.
+    pub fn app_label<'a>(
+        &'a self,
+        app_id: &str,
+        title: &'a str,
+    ) -> Option<(&'a Template, Captures<'a>)> {
+        self.app_rules(app_id).find_map(|config| {
+            let template = config.label.as_ref()?;
+            let captures = config.re.captures(title)?;
+            Some((template, captures))
+        })
+    }
+
+    /// Returns the tooltip template of the first matching rule for the given application and title
+    /// that has one.
+    pub fn app_tooltip_format(&self, app_id: &str, title: &str) -> Option<&Template> {
+        self.app_rules(app_id)
+            .find(|config| config.tooltip_format.is_some() && config.re.is_match(title))
+            .and_then(|config| config.tooltip_format.as_ref())
     }
 
     /// Returns true if notification support is enabled.
@@ -154,32 +913,230 @@ impl Config {
             .map(String::as_str)
     }
 
-    /// Returns true if notification support should use the desktop entry as a
-    /// fallback.
-    pub fn notifications_use_desktop_entry(&self) -> bool {
-        self.notifications.use_desktop_entry
+    /// Returns the strategies to match notifications to windows with, in the order to try them.
+    ///
+    /// Without an explicit list, `use_desktop_entry` and `use_fuzzy_matching` decide which of the
+    /// desktop entry strategies are used.
+    pub fn notifications_strategies(&self) -> Vec<MatchStrategy> {
+        if let Some(strategies) = &self.notifications.strategies {
+            return strategies.clone();
+        }
+
+        let notifications = &self.notifications;
+        [
+            Some(MatchStrategy::Pid),
+            Some(MatchStrategy::Flatpak),
+            notifications
+                .use_desktop_entry
+                .then_some(MatchStrategy::DesktopEntry),
+            (notifications.use_desktop_entry && notifications.use_fuzzy_matching)
+                .then_some(MatchStrategy::Fuzzy),
+            Some(MatchStrategy::AppName),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// Returns the minimum urgency a notification must have to mark windows as urgent.
+    pub fn notifications_min_urgency(&self) -> Urgency {
+        self.notifications.min_urgency
+    }
+
+    /// Returns true if do not disturb should be on when the taskbar starts.
+    pub fn notifications_do_not_disturb(&self) -> bool {
+        self.notifications.do_not_disturb
+    }
+
+    /// Returns true if a button to toggle do not disturb should be shown.
+    pub fn notifications_do_not_disturb_button(&self) -> bool {
+        self.notifications.do_not_disturb_button
+    }
+
+    /// Returns true if activating a button should close the notifications attributed to it.
+    pub fn notifications_dismiss_on_click(&self) -> bool {
+        self.notifications.dismiss_on_click
+    }
+
+    /// Returns true if the latest notification's summary should be added to the button's tooltip.
+    pub fn notifications_tooltip(&self) -> bool {
+        self.notifications.tooltip
+    }
+
+    /// Returns true if the notification's body should be added to the tooltip as well.
+    pub fn notifications_tooltip_body(&self) -> bool {
+        self.notifications.tooltip_body
+    }
+
+    /// Returns the settings for the notification connection cache.
+    pub fn notifications_cache(&self) -> &NotificationCache {
+        &self.notifications.cache
+    }
+
+    /// Returns true if notifications from the given app ID should mark its windows as urgent.
+    pub fn notifications_enabled_for(&self, app_id: &str) -> bool {
+        self.notifications
+            .apps
+            .get(app_id)
+            .is_none_or(|app| app.enabled)
+    }
+
+    /// Returns true if progress and badge counts from the Unity LauncherEntry API should be shown.
+    pub fn launcher_entries_enabled(&self) -> bool {
+        self.launcher_entries.enabled
+    }
+
+    /// Returns true if tray items that need attention should mark their windows as urgent.
+    pub fn tray_attention_enabled(&self) -> bool {
+        self.tray_attention.enabled
+    }
+
+    /// Returns true if the icons of programs running under Wine should be found by their
+    /// executable.
+    pub fn wine_enabled(&self) -> bool {
+        self.wine.enabled
+    }
+
+    /// Returns true if the app ID is one Wine gives to every window, so the executable has to be
+    /// found from the window's process instead.
+    pub fn wine_generic_app_id(&self, app_id: &str) -> bool {
+        self.wine.app_ids.iter().any(|id| id == app_id)
+    }
+
+    /// Returns the icon configured for the given Wine executable, if any.
+    pub fn wine_icon(&self, exe: &str) -> Option<&str> {
+        self.wine
+            .icons
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(exe))
+            .map(|(_, icon)| icon.as_str())
     }
 
-    pub fn notifications_use_fuzzy_matching(&self) -> bool {
-        self.notifications.use_fuzzy_matching
+    /// Returns the path to the separate configuration file, if one is configured.
+    pub fn file(&self) -> Option<PathBuf> {
+        self.config.as_deref().map(file::expand_home)
+    }
+
+    /// Returns the path to the user CSS file, if one is configured.
+    pub fn css(&self) -> Option<PathBuf> {
+        self.css.as_deref().map(file::expand_home)
     }
 
     pub fn show_all_outputs(&self) -> bool {
         self.show_all_outputs
     }
 
-    pub fn orientation(&self) -> Orientation {
+    /// Returns the names of the outputs whose windows are shown, if they have been configured.
+    ///
+    /// This is usually just the output the bar is on, but can be several outputs, such as when the
+    /// bar spans a mirrored or virtual output.
+    pub fn output(&self) -> &[String] {
+        &self.output
+    }
+
+    /// Returns the names of the outputs whose windows are never shown.
+    pub fn exclude_outputs(&self) -> &[String] {
+        &self.exclude_outputs
+    }
+
+    /// Returns true if each output's workspaces should be preceded by a header with the output
+    /// name when showing all outputs.
+    pub fn output_headers(&self) -> bool {
+        self.output_headers
+    }
+
+    /// Returns the configured orientation, which overrides the orientation detected from the bar.
+    pub fn orientation(&self) -> Option<Orientation> {
         self.orientation
     }
 
-    pub fn workspace_format(&self) -> &str {
+    /// Returns the overflow handling for the given orientation.
+    pub fn overflow(&self, orientation: Orientation) -> &OverflowRule {
+        match orientation {
+            Orientation::Horizontal => &self.overflow.horizontal,
+            Orientation::Vertical => &self.overflow.vertical,
+        }
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Returns how window buttons are ordered within a workspace.
+    ///
+    /// A stable order overrides the configured sort.
+    pub fn sort(&self) -> Sort {
+        if self.stable_order {
+            Sort::Insertion
+        } else {
+            self.sort
+        }
+    }
+
+    /// Returns true if only the windows on the active workspace should be shown, without workspace
+    /// labels.
+    pub fn active_workspace_only(&self) -> bool {
+        self.active_workspace_only
+    }
+
+    /// Returns true if the given window shouldn't have a button.
+    pub fn window_ignored(&self, window: &niri_ipc::Window) -> bool {
+        self.ignore.iter().any(|rule| rule.matches(window))
+    }
+
+    /// Returns true if the given workspace should be hidden from the taskbar.
+    ///
+    /// Workspaces are matched by name, or by index if they don't have a name.
+    pub fn workspace_hidden(&self, workspace: &Workspace) -> bool {
+        let name = match &workspace.name {
+            Some(name) => name.clone(),
+            None => workspace.idx.to_string(),
+        };
+        self.hidden_workspaces.iter().any(|re| re.is_match(&name))
+    }
+
+    /// Returns the icon configured for the given workspace, if any.
+    ///
+    /// Icons are looked up by workspace name first, and then by index.
+    pub fn workspace_icon(&self, workspace: &Workspace) -> Option<&str> {
+        workspace
+            .name
+            .as_ref()
+            .and_then(|name| self.workspace_icons.get(name))
+            .or_else(|| self.workspace_icons.get(&workspace.idx.to_string()))
+            .map(String::as_str)
+    }
+
+    /// Returns true if empty workspaces should be shown even when they aren't focused.
+    pub fn show_empty_workspaces(&self) -> bool {
+        self.show_empty_workspaces
+    }
+
+    /// Returns true if workspace labels should include the workspace name.
+    pub fn workspace_names(&self) -> bool {
+        self.workspace_names
+    }
+
+    /// Returns true if workspace formats and app label templates are Pango markup.
+    pub fn label_markup(&self) -> bool {
+        self.label_markup
+    }
+
+    /// Returns the template used for the labels of unfocused workspaces.
+    pub fn workspace_format(&self) -> &Template {
         &self.workspace_format
     }
 
-    pub fn workspace_format_focused(&self) -> &str {
+    /// Returns the template used for the label of the focused workspace.
+    pub fn workspace_format_focused(&self) -> &Template {
         &self.workspace_format_focused
     }
 
+    /// Returns true if focus should follow windows dropped onto another workspace.
+    pub fn follow_dropped_windows(&self) -> bool {
+        self.follow_dropped_windows
+    }
+
     pub fn close_to_tiling(&self) -> bool {
         self.close_to_tiling
     }
@@ -187,13 +1144,233 @@ impl Config {
     pub fn keep_on_active_workspace(&self) -> &KeepOnActiveWorkspace {
         &self.keep_on_active_workspace
     }
+
+    /// Returns true if window buttons should have tooltips.
+    pub fn tooltip(&self) -> bool {
+        self.tooltip
+    }
+
+    /// Returns the template used to build window button tooltips.
+    pub fn tooltip_format(&self) -> &Template {
+        self.tooltip_format
+            .as_ref()
+            .unwrap_or(&DEFAULT_TOOLTIP_FORMAT)
+    }
+
+    pub fn thumbnails(&self) -> &Thumbnails {
+        &self.thumbnails
+    }
+
+    /// Returns the fixed icon size, if one is configured. Otherwise, the icon size is derived from
+    /// the size of the button.
+    pub fn icon_size(&self) -> Option<i32> {
+        self.icon_size
+    }
+
+    /// Returns true if the windows of each app should be grouped into one button per workspace.
+    pub fn group_by_app(&self) -> bool {
+        self.group_by_app
+    }
+
+    /// Returns true if buttons should include a focus indicator widget.
+    pub fn focus_indicator(&self) -> bool {
+        self.focus_indicator
+    }
+
+    pub fn urgent_flash(&self) -> &UrgentFlash {
+        &self.urgent_flash
+    }
+
+    /// Returns how long a button stays urgent if its window isn't focused, if there's a limit.
+    pub fn urgent_timeout(&self) -> Option<Duration> {
+        self.urgent_timeout.map(Duration::from_secs)
+    }
+
+    /// Returns how often Niri is polled for windows if its event stream is unavailable.
+    ///
+    /// Shorter intervals than [`MIN_POLL_INTERVAL`] are raised to it, since polling continuously
+    /// would keep both Niri and the bar busy.
+    pub fn poll_interval(&self) -> Duration {
+        let interval = Duration::from_millis(self.poll_interval.unwrap_or(1000));
+        if interval < MIN_POLL_INTERVAL {
+            tracing::warn!(?interval, min = ?MIN_POLL_INTERVAL, "poll_interval is too short; using the minimum");
+            return MIN_POLL_INTERVAL;
+        }
+        interval
+    }
+
+    /// Returns true if separators should be shown between workspaces.
+    pub fn workspace_separators(&self) -> bool {
+        self.workspace_separators
+    }
+
+    pub fn spacing(&self) -> &Spacing {
+        &self.spacing
+    }
+
+    /// Returns the maximum width of a button with a label, in pixels, if any.
+    pub fn max_button_width(&self) -> Option<i32> {
+        self.max_button_width
+    }
+
+    /// Returns the icon theme to look icons up in, if one is configured. Otherwise, the default
+    /// Gtk icon theme is used.
+    pub fn icon_theme(&self) -> Option<&str> {
+        self.icon_theme.as_deref()
+    }
+
+    /// Returns the desktop entry IDs of the apps pinned before the window buttons.
+    pub fn pinned(&self) -> &[String] {
+        &self.pinned
+    }
+
+    /// Returns true if the icons of unfocused windows should be dimmed.
+    pub fn dim_unfocused(&self) -> bool {
+        self.dim_unfocused
+    }
+
+    pub fn icon_style(&self) -> IconStyle {
+        self.icon_style
+    }
+
+    /// Returns the colour used to draw symbolic and monochrome icons, if one is configured.
+    /// Otherwise, the button's text colour is used.
+    pub fn icon_color(&self) -> Option<RGBA> {
+        self.icon_color
+    }
+
+    pub fn display(&self) -> Display {
+        self.display
+    }
+
+    pub fn focused_click(&self) -> FocusedClick {
+        self.focused_click
+    }
+
+    /// Returns the action bound to the given mouse event on a window button, if any.
+    pub fn binding(&self, trigger: &Trigger) -> Option<&ButtonAction> {
+        self.bindings.get(trigger)
+    }
+}
+
+/// A rule matching windows that shouldn't have buttons.
+///
+/// Every regex that is set has to match. A rule without any regexes matches nothing, rather than
+/// hiding every window.
+#[derive(Debug, Deserialize)]
+pub struct IgnoreRule {
+    #[serde(default, deserialize_with = "deserialise_optional_regex")]
+    app_id: Option<Regex>,
+    #[serde(default, deserialize_with = "deserialise_optional_regex")]
+    title: Option<Regex>,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+impl IgnoreRule {
+    fn matches(&self, window: &niri_ipc::Window) -> bool {
+        let matches = |re: &Option<Regex>, value: &Option<String>| match re {
+            Some(re) => value.as_deref().is_some_and(|value| re.is_match(value)),
+            None => true,
+        };
+
+        (self.app_id.is_some() || self.title.is_some())
+            && matches(&self.app_id, &window.app_id)
+            && matches(&self.title, &window.title)
+    }
+}
+
+/// The rules for the apps matching a key in the `apps` configuration.
+#[derive(Debug)]
+struct AppRules {
+    key: String,
+    // The key as a regex, which has to match the whole app ID.
+    re: Regex,
+    rules: Vec<AppConfig>,
 }
 
 #[derive(Deserialize, Debug)]
 struct AppConfig {
     #[serde(rename = "match", deserialize_with = "deserialise_regex")]
     re: Regex,
-    class: String,
+    #[serde(default)]
+    class: Option<String>,
+    #[serde(default)]
+    label: Option<Template>,
+    #[serde(default)]
+    tooltip_format: Option<Template>,
+    #[serde(flatten)]
+    unknown: Unknown,
+}
+
+fn deserialise_color<'de, D>(de: D) -> Result<Option<RGBA>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(de)? {
+        Some(color) => RGBA::parse(&color)
+            .map(Some)
+            .map_err(|_| serde::de::Error::custom(format!("invalid colour: {color}"))),
+        None => Ok(None),
+    }
+}
+
+fn deserialise_outputs<'de, D>(de: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    // Most bars are on a single output, so a plain string is accepted as well as a list.
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Outputs {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match Option::<Outputs>::deserialize(de)? {
+        Some(Outputs::One(output)) => vec![output],
+        Some(Outputs::Many(outputs)) => outputs,
+        None => Vec::new(),
+    })
+}
+
+fn deserialise_apps<'de, D>(de: D) -> Result<Vec<AppRules>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    // Keys are sorted so that the order in which regex keys are tried doesn't depend on the hash
+    // map. Keys that were valid as plain app IDs but aren't valid regexes only match exactly.
+    HashMap::<String, Vec<AppConfig>>::deserialize(de)?
+        .into_iter()
+        .sorted_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(key, rules)| {
+            let re = Regex::new(&format!("^(?:{key})$")).or_else(|e| {
+                tracing::debug!(%key, %e, "app key isn't a valid regex; matching it exactly");
+                Regex::new(&format!("^{}$", regex::escape(&key)))
+            });
+            let re = re.map_err(serde::de::Error::custom)?;
+            Ok(AppRules { key, re, rules })
+        })
+        .collect()
+}
+
+fn deserialise_optional_regex<'de, D>(de: D) -> Result<Option<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(de)?
+        .map(|re| Regex::new(&re).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+fn deserialise_regexes<'de, D>(de: D) -> Result<Vec<Regex>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(de)?
+        .iter()
+        .map(|re| Regex::new(re).map_err(serde::de::Error::custom))
+        .collect()
 }
 
 fn deserialise_regex<'de, D>(de: D) -> Result<Regex, D::Error>
@@ -0,0 +1,220 @@
+//! Mouse bindings for taskbar buttons.
+
+use std::{collections::HashMap, str::FromStr};
+
+use niri_ipc::SizeChange;
+use serde::{Deserialize, Deserializer};
+use thiserror::Error;
+use waybar_cffi::gtk::gdk::ModifierType;
+
+use crate::exec::Command;
+
+/// The table of mouse bindings for window buttons.
+#[derive(Debug, Clone)]
+pub struct Bindings(HashMap<Trigger, ButtonAction>);
+
+impl Bindings {
+    /// Returns the action bound to the given trigger, if any.
+    pub fn get(&self, trigger: &Trigger) -> Option<&ButtonAction> {
+        self.0.get(trigger)
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Self(HashMap::from([
+            (Trigger::new(Input::Left), ButtonAction::Activate),
+            (Trigger::new(Input::Middle), ButtonAction::Close),
+            (Trigger::new(Input::Right), ButtonAction::Menu),
+            (Trigger::new(Input::ScrollUp), ButtonAction::CyclePrevious),
+            (Trigger::new(Input::ScrollDown), ButtonAction::CycleNext),
+            (
+                Trigger::with_modifiers(Input::ScrollUp, Modifiers::SHIFT),
+                ButtonAction::FocusColumnLeft,
+            ),
+            (
+                Trigger::with_modifiers(Input::ScrollDown, Modifiers::SHIFT),
+                ButtonAction::FocusColumnRight,
+            ),
+            (
+                Trigger::with_modifiers(Input::ScrollUp, Modifiers::CTRL),
+                ButtonAction::FocusWindowUp,
+            ),
+            (
+                Trigger::with_modifiers(Input::ScrollDown, Modifiers::CTRL),
+                ButtonAction::FocusWindowDown,
+            ),
+        ]))
+    }
+}
+
+impl<'de> Deserialize<'de> for Bindings {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // User bindings are layered on top of the defaults, so only the bindings that differ need
+        // to be configured. A default binding can be removed by binding it to `none`.
+        let mut bindings = Self::default();
+        for (key, action) in HashMap::<String, ButtonAction>::deserialize(de)? {
+            let trigger = key.parse().map_err(serde::de::Error::custom)?;
+            bindings.0.insert(trigger, action);
+        }
+
+        Ok(bindings)
+    }
+}
+
+/// An action that can be bound to a mouse event on a window button.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ButtonAction {
+    /// Focuses the window.
+    Activate,
+    /// Closes the window.
+    Close,
+    /// Toggles fullscreen on the window.
+    Fullscreen,
+    /// Focuses the window and toggles maximising its column.
+    MaximizeColumn,
+    /// Moves the window between the floating and tiling layouts.
+    ToggleFloating,
+    /// Changes the width of the window, using the same syntax as `niri msg action
+    /// set-window-width`: for example, `50%`, `+10%`, or `800`.
+    SetWidth(#[serde(deserialize_with = "deserialise_size_change")] SizeChange),
+    /// Opens the window context menu.
+    Menu,
+    /// Focuses the next window of the same app.
+    CycleNext,
+    /// Focuses the previous window of the same app.
+    CyclePrevious,
+    /// Focuses the column to the left of the focused column, wherever the pointer is.
+    FocusColumnLeft,
+    /// Focuses the column to the right of the focused column.
+    FocusColumnRight,
+    /// Focuses the window above the focused window in its column.
+    FocusWindowUp,
+    /// Focuses the window below the focused window in its column.
+    FocusWindowDown,
+    /// Runs a shell command.
+    Exec(Command),
+    /// Does nothing.
+    None,
+}
+
+fn deserialise_size_change<'de, D>(de: D) -> Result<SizeChange, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    String::deserialize(de)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+/// A mouse event, including any modifiers held at the time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Trigger {
+    input: Input,
+    modifiers: Modifiers,
+}
+
+impl Trigger {
+    /// Creates a trigger without modifiers.
+    pub fn new(input: Input) -> Self {
+        Self {
+            input,
+            modifiers: Modifiers::empty(),
+        }
+    }
+
+    /// Creates a trigger with the given modifiers.
+    fn with_modifiers(input: Input, modifiers: Modifiers) -> Self {
+        Self { input, modifiers }
+    }
+
+    /// Creates a trigger from the Gdk modifier state of an event.
+    pub fn with_state(input: Input, state: ModifierType) -> Self {
+        Self {
+            input,
+            modifiers: Modifiers::from(state),
+        }
+    }
+}
+
+impl FromStr for Trigger {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Triggers are written as zero or more modifiers followed by the input, separated by `+`:
+        // for example, `ctrl+shift+left`.
+        let lower = s.to_lowercase();
+        let mut parts: Vec<_> = lower.split('+').map(str::trim).collect();
+        let input = match parts.pop() {
+            Some("left") => Input::Left,
+            Some("middle") => Input::Middle,
+            Some("right") => Input::Right,
+            Some("double-left") => Input::DoubleLeft,
+            Some("double-middle") => Input::DoubleMiddle,
+            Some("double-right") => Input::DoubleRight,
+            Some("scroll-up") => Input::ScrollUp,
+            Some("scroll-down") => Input::ScrollDown,
+            _ => return Err(ParseError(s.to_string())),
+        };
+
+        let mut modifiers = Modifiers::empty();
+        for part in parts {
+            modifiers |= match part {
+                "shift" => Modifiers::SHIFT,
+                "ctrl" | "control" => Modifiers::CTRL,
+                "alt" => Modifiers::ALT,
+                "super" | "mod4" => Modifiers::SUPER,
+                _ => return Err(ParseError(s.to_string())),
+            };
+        }
+
+        Ok(Self { input, modifiers })
+    }
+}
+
+/// The mouse inputs that can be bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Input {
+    Left,
+    Middle,
+    Right,
+    DoubleLeft,
+    DoubleMiddle,
+    DoubleRight,
+    ScrollUp,
+    ScrollDown,
+}
+
+bitflags::bitflags! {
+    /// The keyboard modifiers that can be combined with a mouse input.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Modifiers: u8 {
+        const SHIFT = 1 << 0;
+        const CTRL = 1 << 1;
+        const ALT = 1 << 2;
+        const SUPER = 1 << 3;
+    }
+}
+
+impl From<ModifierType> for Modifiers {
+    fn from(state: ModifierType) -> Self {
+        // Anything else, such as the lock masks, is ignored.
+        let mut modifiers = Self::empty();
+        modifiers.set(Self::SHIFT, state.contains(ModifierType::SHIFT_MASK));
+        modifiers.set(Self::CTRL, state.contains(ModifierType::CONTROL_MASK));
+        modifiers.set(Self::ALT, state.contains(ModifierType::MOD1_MASK));
+        modifiers.set(
+            Self::SUPER,
+            state.intersects(ModifierType::SUPER_MASK | ModifierType::MOD4_MASK),
+        );
+        modifiers
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("invalid mouse binding: {0}")]
+pub struct ParseError(String);
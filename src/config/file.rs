@@ -0,0 +1,152 @@
+//! Support for loading the configuration from a separate file, and reloading it when it changes.
+
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::Duration,
+};
+
+use futures::Stream;
+use thiserror::Error;
+use waybar_cffi::gtk::{
+    gio::{self, FileMonitorEvent, FileMonitorFlags, prelude::FileExt, traits::FileMonitorExt},
+    glib,
+};
+
+use super::Config;
+
+/// Loads the configuration from the given file.
+///
+/// Files with a `.toml` extension are parsed as TOML; anything else is parsed as JSON, like the
+/// Waybar configuration.
+#[tracing::instrument(level = "DEBUG", err)]
+pub fn load(path: &Path) -> Result<Config, Error> {
+    let data = std::fs::read_to_string(path).map_err(|e| Error::Read {
+        e,
+        path: path.to_owned(),
+    })?;
+
+    // Either way, the file goes through a JSON value, just like the Waybar configuration, so that
+    // per-output overrides can be applied later.
+    let value = if path.extension().is_some_and(|ext| ext == "toml") {
+        toml::from_str(&data).map_err(|e| Error::ParseToml {
+            e,
+            path: path.to_owned(),
+        })?
+    } else {
+        serde_json::from_str(&data).map_err(|e| Error::Parse {
+            e,
+            path: path.to_owned(),
+        })?
+    };
+
+    Config::from_value(value).map_err(|e| Error::Parse {
+        e,
+        path: path.to_owned(),
+    })
+}
+
+/// How long to wait after the file changes before loading it, so that the several events an
+/// editor's save produces only load it once.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Starts a stream of configurations, which are loaded each time the given file changes.
+///
+/// Errors are included in the stream, so that they can be reported without discarding the
+/// previous configuration: a typo in the middle of editing shouldn't blank the taskbar.
+pub fn watch(path: PathBuf) -> impl Stream<Item = Result<Config, Error>> {
+    let (tx, rx) = async_channel::unbounded();
+
+    // The monitor has to be kept alive for as long as we want to receive changes, so it's owned
+    // by the stream.
+    let monitor = gio::File::for_path(&path)
+        .monitor_file(FileMonitorFlags::WATCH_MOVES, gio::Cancellable::NONE)
+        .inspect_err(|e| tracing::error!(%e, ?path, "cannot watch configuration file"))
+        .ok();
+    if let Some(monitor) = &monitor {
+        let pending: Rc<RefCell<Option<glib::SourceId>>> = Rc::default();
+        monitor.connect_changed(move |_, _, _, event| {
+            // Editors variously write files in place or replace them, so we'll reload whenever a
+            // write is complete or a new file appears.
+            if !matches!(
+                event,
+                FileMonitorEvent::ChangesDoneHint
+                    | FileMonitorEvent::Created
+                    | FileMonitorEvent::MovedIn
+                    | FileMonitorEvent::Renamed
+            ) {
+                return;
+            }
+
+            if let Some(source) = pending.take() {
+                source.remove();
+            }
+
+            let source = glib::timeout_add_local_once(DEBOUNCE, {
+                let pending = pending.clone();
+                let path = path.clone();
+                let tx = tx.clone();
+                move || {
+                    pending.take();
+
+                    // Reading and parsing the file happens on a worker thread, so that a slow
+                    // disk doesn't freeze the bar.
+                    glib::spawn_future_local(async move {
+                        let config = match gio::spawn_blocking(move || load(&path)).await {
+                            Ok(config) => config,
+                            Err(_) => {
+                                tracing::error!("configuration loading panicked");
+                                return;
+                            }
+                        };
+                        if let Err(e) = tx.send(config).await {
+                            tracing::error!(%e, "error sending reloaded configuration");
+                        }
+                    });
+                }
+            });
+            pending.replace(Some(source));
+        });
+    }
+
+    async_stream::stream! {
+        let _monitor = monitor;
+        while let Ok(config) = rx.recv().await {
+            yield config;
+        }
+    }
+}
+
+/// Expands a leading `~` in the given path to the home directory.
+pub fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => glib::home_dir().join(rest),
+        None if path == "~" => glib::home_dir(),
+        None => PathBuf::from(path),
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("cannot read configuration file {path:?}: {e}")]
+    Read {
+        #[source]
+        e: std::io::Error,
+        path: PathBuf,
+    },
+
+    #[error("cannot parse configuration file {path:?}: {e}")]
+    Parse {
+        #[source]
+        e: serde_json::Error,
+        path: PathBuf,
+    },
+
+    #[error("cannot parse configuration file {path:?}: {e}")]
+    ParseToml {
+        #[source]
+        e: toml::de::Error,
+        path: PathBuf,
+    },
+}
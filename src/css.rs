@@ -0,0 +1,144 @@
+//! Support for user CSS, which is loaded after the base CSS for the widgets inside the taskbar that
+//! Waybar's own stylesheet can't reach.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use waybar_cffi::gtk::{
+    self as gtk, CssProvider,
+    gio::{self, FileMonitorEvent, FileMonitorFlags, prelude::FileExt, traits::FileMonitorExt},
+    glib::{self, prelude::IsA},
+    prelude::{CssProviderExt, StyleContextExt, WidgetExt},
+};
+
+use crate::state::State;
+
+/// The base CSS for the widgets inside the taskbar.
+static BASE_CSS: &str = include_str!("style.css");
+
+// These have to be declared as thread locals because Gtk objects are (generally) not Send.
+// Practically, we're likely to be doing everything from the main thread anyway, but Glib can
+// figure that out.
+thread_local! {
+    static PROVIDER: CssProvider = {
+        let css = CssProvider::new();
+        if let Err(e) = css.load_from_data(BASE_CSS.as_bytes()) {
+            tracing::error!(%e, "CSS parse error");
+        }
+
+        css
+    };
+
+    // User CSS is keyed by path rather than shared, since bars can have different files, or none.
+    static USER_PROVIDERS: RefCell<HashMap<PathBuf, CssProvider>> = RefCell::default();
+}
+
+/// Adds the CSS providers with the base CSS and the bar's user CSS to the given widget.
+///
+/// Providers only apply to the widget they're added to, so this has to be done for each widget
+/// that the base CSS styles, rather than just the button.
+pub fn add_provider(state: &State, widget: &impl IsA<gtk::Widget>) {
+    let context = widget.style_context();
+    PROVIDER.with(|provider| {
+        context.add_provider(provider, gtk::STYLE_PROVIDER_PRIORITY_APPLICATION);
+    });
+
+    // The user CSS is loaded at a higher priority, so that it overrides the base CSS.
+    if let Some(path) = state.config().css() {
+        context.add_provider(
+            &user_provider(&path),
+            gtk::STYLE_PROVIDER_PRIORITY_APPLICATION + 1,
+        );
+    }
+}
+
+fn user_provider(path: &Path) -> CssProvider {
+    USER_PROVIDERS.with_borrow_mut(|providers| {
+        providers
+            .entry(path.to_owned())
+            .or_insert_with(CssProvider::new)
+            .clone()
+    })
+}
+
+/// The user CSS file of a bar, if any, which is reloaded whenever it changes.
+///
+/// Widgets only pick up a new file when they're created, so the taskbar has to be rebuilt after
+/// the file changes.
+#[derive(Default)]
+pub struct UserCss {
+    path: Option<PathBuf>,
+    // The monitor stops watching the file when dropped.
+    monitor: Option<gio::FileMonitor>,
+}
+
+impl UserCss {
+    /// Loads the given file, replacing any user CSS that was previously loaded.
+    pub fn set(&mut self, path: Option<PathBuf>) {
+        if path == self.path {
+            // The file is already being watched, but the configuration may have been reloaded
+            // because the CSS was edited in the same save, so we'll reload it anyway.
+            if let Some(path) = &path {
+                load(path);
+            }
+            return;
+        }
+
+        if let Some(monitor) = self.monitor.take() {
+            monitor.cancel();
+        }
+
+        if let Some(path) = &path {
+            load(path);
+        }
+        self.monitor = path.as_deref().and_then(watch);
+        self.path = path;
+    }
+}
+
+/// Loads the user CSS from the given file into its provider.
+///
+/// If the file can't be read or parsed, the provider is emptied, so that only the base CSS applies.
+fn load(path: &Path) {
+    let provider = user_provider(path);
+    let css = match std::fs::read_to_string(path) {
+        Ok(css) => css,
+        Err(e) => {
+            tracing::warn!(%e, ?path, "cannot read user CSS");
+            String::new()
+        }
+    };
+
+    if let Err(e) = provider.load_from_data(css.as_bytes()) {
+        tracing::warn!(%e, ?path, "cannot parse user CSS");
+        if let Err(e) = provider.load_from_data(b"") {
+            tracing::error!(%e, "CSS parse error");
+        }
+    }
+}
+
+fn watch(path: &Path) -> Option<gio::FileMonitor> {
+    let monitor = gio::File::for_path(path)
+        .monitor_file(FileMonitorFlags::WATCH_MOVES, gio::Cancellable::NONE)
+        .inspect_err(|e: &glib::Error| tracing::error!(%e, ?path, "cannot watch user CSS"))
+        .ok()?;
+
+    let path = path.to_owned();
+    monitor.connect_changed(move |_, _, _, event| {
+        // As with the configuration file, editors variously write files in place or replace them.
+        if matches!(
+            event,
+            FileMonitorEvent::ChangesDoneHint
+                | FileMonitorEvent::Created
+                | FileMonitorEvent::MovedIn
+                | FileMonitorEvent::Renamed
+        ) {
+            load(&path);
+        }
+    });
+
+    Some(monitor)
+}
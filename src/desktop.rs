@@ -0,0 +1,140 @@
+//! Desktop entry lookups.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use waybar_cffi::gtk::{
+    gio::{AppInfo, DesktopAppInfo},
+    prelude::{AppInfoExt, Cast},
+};
+
+/// A cache for application names from desktop entries.
+#[derive(Debug, Clone, Default)]
+pub struct Cache(Arc<Mutex<HashMap<String, Option<String>>>>);
+
+impl Cache {
+    /// Look up the application name for the given application ID.
+    #[tracing::instrument(level = "TRACE", ret)]
+    pub fn name(&self, id: &str) -> Option<String> {
+        self.0
+            .lock()
+            .expect("desktop cache lock")
+            .entry(id.to_string())
+            .or_insert_with(|| candidates(id).next().map(|info| info.name().to_string()))
+            .clone()
+    }
+}
+
+/// Returns the desktop entries that might correspond to the given application ID, best match
+/// first.
+///
+/// The iterator is lazy, so the more expensive searches only happen if the caller keeps going.
+pub fn candidates(id: &str) -> impl Iterator<Item = DesktopAppInfo> + use<> {
+    // KDE applications are special, so we'll go hunt for them ourselves. Again, this is loosely
+    // adapted from wlr/taskbar.
+    let mut paths = Vec::new();
+    for dir in XDG_DATA_DIRS.iter() {
+        for prefix in [
+            "applications/",
+            "applications/kde/",
+            "applications/org.kde.",
+        ] {
+            for suffix in ["", ".desktop"] {
+                paths.push(dir.join(format!("{prefix}{id}{suffix}")));
+            }
+        }
+    }
+
+    // Many applications — particularly Electron and Java applications — use an app ID that
+    // doesn't match their desktop entry, but set StartupWMClass in the entry to the app ID.
+    let wm_class = std::iter::once(id.to_string()).flat_map(|id| {
+        AppInfo::all()
+            .into_iter()
+            .filter_map(|info| info.downcast::<DesktopAppInfo>().ok())
+            .filter(move |info| {
+                info.startup_wm_class()
+                    .is_some_and(|class| class.eq_ignore_ascii_case(&id))
+            })
+    });
+
+    // Snaps name their desktop entries `{snap}_{app}.desktop`, in a directory of their own, and
+    // their app IDs can be either half, or the whole thing.
+    let snap = std::iter::once(id.to_string()).flat_map(|id| {
+        std::fs::read_dir(SNAP_APPLICATIONS)
+            .into_iter()
+            .flatten()
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(move |path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.strip_suffix(".desktop"))
+                    .is_some_and(|name| {
+                        name == id
+                            || name.split_once('_').is_some_and(|(snap, app)| {
+                                snap.eq_ignore_ascii_case(&id) || app.eq_ignore_ascii_case(&id)
+                            })
+                    })
+            })
+    });
+
+    // This is _very_ roughly adapted from the wlr/taskbar module built into Waybar.
+    let search = std::iter::once(id.to_string())
+        .flat_map(|id| DesktopAppInfo::search(&id))
+        .flatten()
+        .filter_map(|possible| DesktopAppInfo::new(&possible));
+
+    paths
+        .into_iter()
+        .chain(snap)
+        .filter_map(DesktopAppInfo::from_filename)
+        .chain(wm_class)
+        .chain(search)
+}
+
+/// The directories desktop entries and icons are installed to, in order of preference.
+pub static XDG_DATA_DIRS: LazyLock<Vec<PathBuf>> = LazyLock::new(|| {
+    let mut dirs = Vec::new();
+
+    if let Ok(home) = std::env::var("HOME") {
+        dirs.push(PathBuf::from(home).join(".local/share"));
+    }
+
+    if let Ok(env) = std::env::var("XDG_DATA_DIRS") {
+        dirs.extend(env.split(':').map(PathBuf::from))
+    } else {
+        dirs.extend(
+            ["/usr/share", "/usr/local/share"]
+                .into_iter()
+                .map(PathBuf::from),
+        );
+    }
+
+    // Flatpak normally adds its exports to XDG_DATA_DIRS, but not every session sets it up.
+    for dir in FLATPAK_EXPORTS.iter() {
+        if !dirs.contains(dir) {
+            dirs.push(dir.clone());
+        }
+    }
+
+    dirs
+});
+
+/// The directory snapd installs the desktop entries of snaps to.
+static SNAP_APPLICATIONS: &str = "/var/lib/snapd/desktop/applications";
+
+/// The directories Flatpak exports the desktop entries and icons of installed apps to, for user
+/// installations and then system installations.
+pub static FLATPAK_EXPORTS: LazyLock<Vec<PathBuf>> = LazyLock::new(|| {
+    let mut dirs = Vec::new();
+
+    if let Ok(home) = std::env::var("HOME") {
+        dirs.push(PathBuf::from(home).join(".local/share/flatpak/exports/share"));
+    }
+    dirs.push(PathBuf::from("/var/lib/flatpak/exports/share"));
+
+    dirs
+});
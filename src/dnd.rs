@@ -0,0 +1,120 @@
+//! Drag and drop support for window buttons.
+
+use waybar_cffi::gtk::{
+    DestDefaults, TargetEntry, TargetFlags,
+    gdk::{DragAction, ModifierType},
+    prelude::{WidgetExt, WidgetExtManual},
+};
+
+use crate::{
+    error::Error,
+    niri::Niri,
+    state::{DisplayedWindow, State},
+};
+
+/// The drag target used for window buttons. The payload is the window ID.
+static WINDOW_TARGET: &str = "application/x-niri-taskbar-window";
+
+fn targets() -> Vec<TargetEntry> {
+    vec![TargetEntry::new(WINDOW_TARGET, TargetFlags::SAME_APP, 0)]
+}
+
+/// Makes the given widget a drag source for the window returned by `window_id` when a drag starts.
+pub fn connect_source(widget: &impl WidgetExtManual, window_id: impl Fn() -> u64 + 'static) {
+    widget.drag_source_set(ModifierType::BUTTON1_MASK, &targets(), DragAction::MOVE);
+    widget.connect_drag_data_get(move |_, _, data, _, _| {
+        data.set(&data.target(), 8, &window_id().to_le_bytes());
+    });
+}
+
+/// Makes the given widget a drop target for window buttons, calling `f` with the dropped window
+/// ID.
+pub fn connect_target<W, F>(widget: &W, f: F)
+where
+    W: WidgetExtManual + WidgetExt,
+    F: Fn(&W, u64) + 'static,
+{
+    widget.drag_dest_set(DestDefaults::ALL, &targets(), DragAction::MOVE);
+    widget.connect_drag_data_received(
+        move |widget, _, _, _, data, _, _| match <[u8; 8]>::try_from(data.data().as_slice()) {
+            Ok(bytes) => f(widget, u64::from_le_bytes(bytes)),
+            Err(_) => tracing::warn!(data = ?data.data(), "unexpected drag payload"),
+        },
+    );
+}
+
+/// Makes the given widget a drop target that moves dropped windows to the given workspace.
+///
+/// If configured, focus follows the window to the workspace.
+pub fn connect_workspace_target<W>(widget: &W, state: &State, workspace_id: u64)
+where
+    W: WidgetExtManual + WidgetExt,
+{
+    let state = state.clone();
+    connect_target(widget, move |_, window_id| {
+        let follow = state.config().follow_dropped_windows();
+        state
+            .niri()
+            .spawn("error moving dropped window", move |niri| {
+                niri.move_window_to_workspace(window_id, workspace_id, follow)
+            });
+    });
+}
+
+/// Moves the source window to the position of the target window within the Niri layout.
+///
+/// Niri's column actions all operate on the focused window, so the source window is focused
+/// first.
+#[tracing::instrument(level = "DEBUG", skip(state))]
+pub fn move_window_to_window(state: &State, source_id: u64, target_id: u64) {
+    let order = state.window_order();
+    let (Some(source), Some(target)) = (order.get(source_id), order.get(target_id)) else {
+        return;
+    };
+    if source_id == target_id {
+        return;
+    }
+
+    // The moves are a sequence of requests, which are sent together off the main thread.
+    let (source, target) = (source.clone(), target.clone());
+    state
+        .niri()
+        .spawn("error moving dropped window", move |niri| {
+            move_requests(niri, &source, &target)
+        });
+}
+
+fn move_requests(
+    niri: &Niri,
+    source: &DisplayedWindow,
+    target: &DisplayedWindow,
+) -> Result<(), Error> {
+    let source_id = source.id;
+    if source.workspace_id != target.workspace_id {
+        if let Some(workspace_id) = target.workspace_id {
+            niri.move_window_to_workspace(source_id, workspace_id, false)?;
+        }
+    }
+
+    let Some((target_column, target_tile)) = target.pos else {
+        // Floating windows have no position that we can move to.
+        return Ok(());
+    };
+
+    niri.activate_window(source_id)?;
+    match source.pos {
+        Some((source_column, source_tile))
+            if source.workspace_id == target.workspace_id && source_column == target_column =>
+        {
+            // Moving within a column is done one step at a time.
+            for _ in target_tile..source_tile {
+                niri.move_window_up()?;
+            }
+            for _ in source_tile..target_tile {
+                niri.move_window_down()?;
+            }
+            Ok(())
+        }
+        _ => niri.move_column_to_index(target_column),
+    }
+}
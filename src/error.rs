@@ -14,6 +14,12 @@ pub enum Error {
         response: Box<niri_ipc::Response>,
     },
 
+    #[error("{0} not supported by this version of niri")]
+    Unsupported(&'static str),
+
+    #[error("error received from gio while waiting for task")]
+    Task,
+
     #[error("window stream send error")]
     WindowStreamSend,
 }
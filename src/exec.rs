@@ -0,0 +1,129 @@
+use std::ffi::OsStr;
+
+use serde::{Deserialize, Deserializer};
+use waybar_cffi::gtk::{
+    gio::{Subprocess, SubprocessFlags},
+    glib,
+};
+
+/// Spawns the given command via `sh -c`.
+///
+/// The command runs asynchronously: GIO takes care of reaping the child once it exits, and we
+/// don't wait for it or care about its output.
+#[tracing::instrument(level = "DEBUG", err)]
+pub fn spawn(command: &str) -> Result<(), glib::Error> {
+    Subprocess::newv(
+        &[OsStr::new("sh"), OsStr::new("-c"), OsStr::new(command)],
+        SubprocessFlags::NONE,
+    )?;
+
+    Ok(())
+}
+
+/// Quotes the given value so that it is passed to the shell as a single word.
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// The window details that can be substituted into a command.
+const PLACEHOLDERS: &[&str] = &["id", "app_id", "title"];
+
+/// A shell command with placeholders for window details, parsed when the configuration is loaded.
+///
+/// Only the known placeholders are substituted; any other text, including braces, is passed to
+/// the shell as written. Values are quoted to suit where the placeholder appears, so that a
+/// placeholder inside quotes the user wrote doesn't gain literal quote characters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Command(Vec<Part>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Placeholder(&'static str, Quoting),
+}
+
+/// The shell quoting in effect at a placeholder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Quoting {
+    None,
+    Single,
+    Double,
+}
+
+impl Command {
+    /// Parses a command.
+    pub fn new(command: &str) -> Self {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut quoting = Quoting::None;
+        let mut escaped = false;
+        let mut rest = command;
+
+        while let Some(c) = rest.chars().next() {
+            if c == '{'
+                && !escaped
+                && let Some(name) = PLACEHOLDERS.iter().find(|name| {
+                    rest[1..]
+                        .strip_prefix(**name)
+                        .is_some_and(|after| after.starts_with('}'))
+                })
+            {
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(Part::Placeholder(name, quoting));
+                rest = &rest[name.len() + 2..];
+                continue;
+            }
+
+            // Only enough of the shell's syntax is followed to know whether we're inside quotes.
+            match (quoting, c) {
+                _ if escaped => escaped = false,
+                (Quoting::None | Quoting::Double, '\\') => escaped = true,
+                (Quoting::None, '\'') => quoting = Quoting::Single,
+                (Quoting::None, '"') => quoting = Quoting::Double,
+                (Quoting::Single, '\'') | (Quoting::Double, '"') => quoting = Quoting::None,
+                _ => {}
+            }
+            literal.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Self(parts)
+    }
+
+    /// Renders the command, substituting the value of each placeholder.
+    pub fn render(&self, lookup: impl Fn(&str) -> String) -> String {
+        self.0
+            .iter()
+            .map(|part| match part {
+                Part::Literal(text) => text.clone(),
+                Part::Placeholder(name, Quoting::None) => quote(&lookup(name)),
+                Part::Placeholder(name, Quoting::Single) => lookup(name).replace('\'', r"'\''"),
+                Part::Placeholder(name, Quoting::Double) => {
+                    let mut escaped = String::new();
+                    for c in lookup(name).chars() {
+                        if matches!(c, '"' | '\\' | '$' | '`') {
+                            escaped.push('\\');
+                        }
+                        escaped.push(c);
+                    }
+                    escaped
+                }
+            })
+            .collect()
+    }
+}
+
+impl<'de> Deserialize<'de> for Command {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Self::new(&String::deserialize(de)?))
+    }
+}
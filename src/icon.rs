@@ -1,78 +1,413 @@
 use std::{
+    cell::RefCell,
     collections::HashMap,
-    path::PathBuf,
-    sync::{Arc, LazyLock, Mutex},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use waybar_cffi::gtk::{
-    gio::DesktopAppInfo,
-    prelude::{AppInfoExt, IconExt},
+    self as gtk,
+    gdk::RGBA,
+    gdk_pixbuf::{Colorspace, Pixbuf, PixbufError, PixbufLoader, prelude::PixbufLoaderExt},
+    gio::{self, DesktopAppInfo},
+    glib::{self, Bytes},
+    prelude::{AppInfoExt, FileExt, GtkSettingsExt, IconExt},
 };
 
+use crate::desktop;
+
+pub mod avatar;
+pub mod wine;
+
+/// How long a failed lookup is remembered for, so that apps without an icon don't trigger a full
+/// search on every snapshot, but newly installed icons are still found eventually. Icons adopted
+/// from notifications are kept for the same time, so that an app that later gets an icon of its
+/// own switches to it.
+const MISSING_TTL: Duration = Duration::from_secs(60);
+
+/// Directories outside the data directories where apps install icons that stay put.
+const STABLE_ICON_DIRS: &[&str] = &["/usr", "/opt", "/snap"];
+
 /// A cache for taskbar icons.
 #[derive(Debug, Clone, Default)]
-pub struct Cache(Arc<Mutex<HashMap<String, PathBuf>>>);
+pub struct Cache {
+    icons: Arc<Mutex<HashMap<String, Cached>>>,
+    // The configured theme, which can change when the configuration is reloaded.
+    theme: Arc<Mutex<Option<String>>>,
+    // The theme Gtk is using, which is used if no theme is configured.
+    system_theme: Arc<Mutex<Option<String>>>,
+}
 
 impl Cache {
+    /// Instantiates a new cache that looks icons up in the given theme, or Gtk's theme if `None`.
+    pub fn new(theme: Option<String>) -> Self {
+        let system_theme = gtk::Settings::default()
+            .and_then(|settings| settings.gtk_icon_theme_name())
+            .map(String::from);
+
+        Self {
+            icons: Arc::default(),
+            theme: Arc::new(Mutex::new(theme)),
+            system_theme: Arc::new(Mutex::new(system_theme)),
+        }
+    }
+
+    /// Records that Gtk's icon theme has changed, and forgets the icons found in the old theme.
+    pub fn set_system_theme(&self, theme: Option<String>) {
+        *self.system_theme.lock().expect("icon theme lock") = theme;
+        self.icons.lock().expect("icon cache lock").clear();
+    }
+
+    /// Sets the configured icon theme, forgetting the icons found in the old theme if it changed.
+    ///
+    /// Returns true if the theme changed.
+    pub fn set_theme(&self, theme: Option<String>) -> bool {
+        let mut current = self.theme.lock().expect("icon theme lock");
+        if *current == theme {
+            return false;
+        }
+
+        *current = theme;
+        self.icons.lock().expect("icon cache lock").clear();
+        true
+    }
+
+    fn theme(&self) -> Option<String> {
+        self.theme
+            .lock()
+            .expect("icon theme lock")
+            .clone()
+            .or_else(|| self.system_theme.lock().expect("icon theme lock").clone())
+    }
+
     /// Look up an icon for the given application ID.
     #[tracing::instrument(level = "TRACE", ret)]
-    pub fn lookup(&self, id: &str) -> Option<PathBuf> {
-        let mut cache = self.0.lock().expect("icon cache lock");
+    fn lookup(&self, id: &str) -> Option<PathBuf> {
+        self.get_or_lookup(id, |theme| lookup(id, theme))
+    }
+
+    /// Look up a symbolic icon for the given application ID, falling back to the normal icon if
+    /// there isn't one.
+    #[tracing::instrument(level = "TRACE", ret)]
+    fn lookup_symbolic(&self, id: &str) -> Option<PathBuf> {
+        self.get_or_lookup(&format!("{id}-symbolic"), |theme| {
+            lookup_symbolic(id, theme)
+        })
+        .or_else(|| self.lookup(id))
+    }
+
+    /// Looks up an icon for the given application ID on a worker thread, since a lookup can
+    /// involve searching every desktop entry on the system, which would stall the Gtk main loop.
+    pub async fn lookup_in_background(&self, id: &str, symbolic: bool) -> Option<PathBuf> {
+        let cache = self.clone();
+        let id = id.to_string();
+        gio::spawn_blocking(move || {
+            if symbolic {
+                cache.lookup_symbolic(&id)
+            } else {
+                cache.lookup(&id)
+            }
+        })
+        .await
+        .inspect_err(|_| tracing::error!("icon lookup panicked"))
+        .ok()
+        .flatten()
+    }
 
-        if !cache.contains_key(id) {
-            if let Some(path) = lookup(id) {
-                cache.insert(id.to_string(), path);
+    /// Adopts the icon a notification from the given app asked for, if no icon has been found for
+    /// the app itself. The icon is then used for the app's windows until the app is looked up
+    /// again.
+    ///
+    /// The icon can be a name in the icon theme, a path, or a `file://` URI. Only paths where apps
+    /// install their icons are adopted, since paths elsewhere are often temporary files or the
+    /// avatar of whoever sent a message.
+    pub async fn adopt_in_background(&self, id: &str, icon: &str) -> Option<PathBuf> {
+        let cache = self.clone();
+        let id = id.to_string();
+        let icon = icon.to_string();
+        gio::spawn_blocking(move || {
+            if let Some(Cached::Found(path)) = cache.icons.lock().expect("icon cache lock").get(&id)
+            {
+                return Some(path.clone());
             }
+
+            let path = resolve_hint(&icon, cache.theme().as_deref())?;
+            cache.icons.lock().expect("icon cache lock").insert(
+                id,
+                Cached::Adopted {
+                    path: path.clone(),
+                    until: Instant::now() + MISSING_TTL,
+                },
+            );
+            Some(path)
+        })
+        .await
+        .inspect_err(|_| tracing::error!("icon lookup panicked"))
+        .ok()
+        .flatten()
+    }
+
+    fn get_or_lookup(
+        &self,
+        key: &str,
+        lookup: impl FnOnce(Option<&str>) -> Option<PathBuf>,
+    ) -> Option<PathBuf> {
+        match self.icons.lock().expect("icon cache lock").get(key) {
+            Some(Cached::Found(path)) => return Some(path.clone()),
+            Some(Cached::Adopted { path, until }) if *until > Instant::now() => {
+                return Some(path.clone());
+            }
+            Some(Cached::Missing { until }) if *until > Instant::now() => return None,
+            _ => {}
         }
 
-        cache.get(id).cloned()
+        // The lock isn't held during the lookup, so that a slow lookup on a worker thread doesn't
+        // block lookups of icons that are already cached. At worst, the same icon is looked up
+        // twice.
+        let path = lookup(self.theme().as_deref());
+        self.icons.lock().expect("icon cache lock").insert(
+            key.to_string(),
+            match &path {
+                Some(path) => Cached::Found(path.clone()),
+                None => Cached::Missing {
+                    until: Instant::now() + MISSING_TTL,
+                },
+            },
+        );
+
+        path
     }
 }
 
-fn lookup(id: &str) -> Option<PathBuf> {
-    if let Some(icon) = lookup_icon(id) {
-        return Some(icon);
+/// The result of an icon lookup.
+#[derive(Debug)]
+enum Cached {
+    Found(PathBuf),
+    /// No icon was found, which won't be looked up again until the given time.
+    Missing {
+        until: Instant,
+    },
+    /// No icon was found, but a notification named one, which is used until the given time.
+    Adopted {
+        path: PathBuf,
+        until: Instant,
+    },
+}
+
+// Pixbufs aren't Send, but icons are only drawn from the main thread anyway.
+thread_local! {
+    static PIXBUFS: RefCell<HashMap<(PathBuf, i32, i32), Pixbuf>> = RefCell::default();
+}
+
+/// Loads the icon at the given path, scaled to fit the given size at the given scale factor.
+///
+/// Decoded icons are cached by path, size, and scale, so that buttons for the same app share one
+/// decode. Pixbufs are immutable, so they can be shared freely.
+pub fn load(path: &Path, size: i32, scale: i32) -> Result<Pixbuf, glib::Error> {
+    let key = (path.to_path_buf(), size, scale);
+    if let Some(pixbuf) = PIXBUFS.with_borrow(|pixbufs| pixbufs.get(&key).cloned()) {
+        return Ok(pixbuf);
     }
 
-    // KDE applications are special, so we'll go hunt for them ourselves. Again, this is loosely
-    // adapted from wlr/taskbar.
-    for dir in XDG_DATA_DIRS.iter() {
-        for prefix in [
-            "applications/",
-            "applications/kde/",
-            "applications/org.kde.",
-        ] {
-            for suffix in ["", ".desktop"] {
-                let path = dir.join(format!("{prefix}{id}{suffix}"));
-                if let Some(info) = DesktopAppInfo::from_filename(&path) {
-                    if let Some(path) = info.icon_path() {
-                        return Some(path);
-                    }
-                }
-            }
+    let pixbuf = if is_svg(path) {
+        load_svg(path, size * scale)?
+    } else {
+        Pixbuf::from_file_at_scale(path, size * scale, size * scale, true)?
+    };
+    PIXBUFS.with_borrow_mut(|pixbufs| pixbufs.insert(key, pixbuf.clone()));
+    Ok(pixbuf)
+}
+
+fn is_svg(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("svg") || ext.eq_ignore_ascii_case("svgz"))
+}
+
+/// Renders an SVG so that its larger dimension is exactly the given number of device pixels.
+///
+/// Setting the size from the size-prepared signal has the SVG loader render straight at that size,
+/// rather than rendering at the document size and scaling the result, which blurs the edges.
+fn load_svg(path: &Path, pixels: i32) -> Result<Pixbuf, glib::Error> {
+    let (data, _) = gio::File::for_path(path).load_contents(gio::Cancellable::NONE)?;
+
+    let loader = PixbufLoader::new();
+    loader.connect_size_prepared(move |loader, width, height| {
+        if width <= 0 || height <= 0 {
+            return;
         }
+
+        let factor = f64::from(pixels) / f64::from(width.max(height));
+        let scale = |dimension: i32| ((f64::from(dimension) * factor).round() as i32).max(1);
+        loader.set_size(scale(width), scale(height));
+    });
+
+    // The loader has to be closed even if the write fails, or it complains when it's dropped.
+    let written = loader.write(&data);
+    let closed = loader.close();
+    written.and(closed)?;
+
+    loader.pixbuf().ok_or_else(|| {
+        glib::Error::new(
+            PixbufError::CorruptImage,
+            &format!("no image loaded from {}", path.display()),
+        )
+    })
+}
+
+/// Forgets the decoded icons, such as when the icon theme has changed.
+pub fn clear_loaded() {
+    PIXBUFS.with_borrow_mut(HashMap::clear);
+}
+
+/// Recolours the pixbuf to a single colour, keeping only its alpha channel.
+pub fn recolor(pixbuf: &Pixbuf, color: &RGBA) -> Option<Pixbuf> {
+    let pixbuf = pixbuf.add_alpha(false, 0, 0, 0).ok()?;
+    let channels = usize::try_from(pixbuf.n_channels()).ok()?;
+    let rgb = [color.red(), color.green(), color.blue()].map(|c| (c * 255.0).round() as u8);
+    let alpha = color.alpha();
+
+    let mut pixels = pixbuf.read_pixel_bytes().to_vec();
+    for pixel in pixels.chunks_exact_mut(channels) {
+        pixel[..3].copy_from_slice(&rgb);
+        pixel[3] = (f64::from(pixel[3]) * alpha).round() as u8;
     }
 
-    // This is _very_ roughly adapted from the wlr/taskbar module built into Waybar. We don't do
-    // the same startup_wm_class check here for now.
-    let infos = DesktopAppInfo::search(id);
-    for possible in infos.into_iter().flatten() {
-        if let Some(info) = DesktopAppInfo::new(&possible) {
-            if let Some(path) = info.icon_path() {
-                return Some(path);
-            }
+    Some(Pixbuf::from_bytes(
+        &Bytes::from_owned(pixels),
+        Colorspace::Rgb,
+        true,
+        pixbuf.bits_per_sample(),
+        pixbuf.width(),
+        pixbuf.height(),
+        pixbuf.rowstride(),
+    ))
+}
+
+fn lookup(id: &str, theme: Option<&str>) -> Option<PathBuf> {
+    if let Some(icon) = lookup_icon(id, theme) {
+        return Some(icon);
+    }
+
+    if let Some(icon) = desktop::candidates(id).find_map(|info| info.icon_path(theme)) {
+        return Some(icon);
+    }
+
+    if wine::is_exe(id)
+        && let Some(icon) = wine::lookup(id, theme)
+    {
+        return Some(icon);
+    }
+
+    // As a last resort, we'll guess at icon names based on the app ID.
+    variants(id).find_map(|variant| lookup_icon(&variant, theme))
+}
+
+/// Resolves the icon given in a notification's `app_icon` field.
+fn resolve_hint(icon: &str, theme: Option<&str>) -> Option<PathBuf> {
+    let path = if icon.starts_with("file://") {
+        glib::filename_from_uri(icon)
+            .inspect_err(|e| tracing::debug!(%e, icon, "cannot parse notification icon URI"))
+            .ok()?
+            .0
+    } else if Path::new(icon).is_absolute() {
+        PathBuf::from(icon)
+    } else {
+        return lookup_icon(icon, theme);
+    };
+
+    if !is_stable(&path) {
+        tracing::debug!(
+            ?path,
+            "not adopting notification icon outside the icon directories"
+        );
+        return None;
+    }
+    path.is_file().then_some(path)
+}
+
+/// Returns true if the path is somewhere apps install their icons, rather than somewhere they write
+/// images for individual messages, such as Chromium's temporary directories or avatar caches.
+fn is_stable(path: &Path) -> bool {
+    if path
+        .components()
+        .any(|component| component == Component::ParentDir)
+    {
+        return false;
+    }
+
+    STABLE_ICON_DIRS
+        .iter()
+        .map(Path::new)
+        .chain(desktop::XDG_DATA_DIRS.iter().map(PathBuf::as_path))
+        .any(|dir| path.starts_with(dir))
+}
+
+/// Suffixes that are often added to app IDs, but not to icon names.
+const SUFFIXES: &[&str] = &[
+    "-bin",
+    "-stable",
+    "-nightly",
+    "-beta",
+    ".AppImage",
+    ".appimage",
+];
+
+/// Returns alternative icon names for the given app ID, most likely first, without repeats.
+fn variants(id: &str) -> impl Iterator<Item = String> + use<> {
+    let stripped = SUFFIXES
+        .iter()
+        .find_map(|suffix| id.strip_suffix(suffix))
+        .unwrap_or(id);
+    let last = stripped.rsplit('.').next().unwrap_or(stripped);
+
+    let candidates = [
+        id.to_lowercase(),
+        stripped.to_string(),
+        stripped.to_lowercase(),
+        last.to_string(),
+        last.to_lowercase(),
+        stripped.replace('-', "."),
+    ];
+
+    let id = id.to_string();
+    let mut seen = Vec::new();
+    candidates.into_iter().filter(move |variant| {
+        if variant.is_empty() || *variant == id || seen.contains(variant) {
+            return false;
         }
+        seen.push(variant.clone());
+        true
+    })
+}
+
+fn lookup_symbolic(id: &str, theme: Option<&str>) -> Option<PathBuf> {
+    if let Some(icon) = lookup_icon(&format!("{id}-symbolic"), theme) {
+        return Some(icon);
     }
 
-    None
+    // Symbolic icons are usually named after the icon in the desktop entry, rather than the app ID.
+    desktop::candidates(id).find_map(|info| {
+        info.icon()
+            .and_then(|icon| IconExt::to_string(&icon))
+            .and_then(|name| lookup_icon(&format!("{name}-symbolic"), theme))
+    })
 }
 
-fn lookup_icon(id: &str) -> Option<PathBuf> {
-    if let Some(path) = freedesktop_icons::lookup(id).with_size(512).find() {
+fn lookup_icon(id: &str, theme: Option<&str>) -> Option<PathBuf> {
+    let mut freedesktop = freedesktop_icons::lookup(id).with_size(512);
+    if let Some(theme) = theme {
+        freedesktop = freedesktop.with_theme(theme);
+    }
+    if let Some(path) = freedesktop.find() {
         return Some(path);
     }
 
-    if let Some(path) = linicon::lookup_icon(id)
+    let mut linicon = linicon::lookup_icon(id);
+    if let Some(theme) = theme {
+        linicon = linicon.from_theme(theme);
+    }
+    if let Some(path) = linicon
         .with_size(512)
         .filter_map(|result| result.ok())
         .next()
@@ -80,37 +415,46 @@ fn lookup_icon(id: &str) -> Option<PathBuf> {
         return Some(path.path);
     }
 
-    None
+    lookup_flatpak_icon(id)
 }
 
-static XDG_DATA_DIRS: LazyLock<Vec<PathBuf>> = LazyLock::new(|| {
-    let mut dirs = Vec::new();
-
-    if let Ok(home) = std::env::var("HOME") {
-        dirs.push(PathBuf::from(home).join(".local/share"));
-    }
-
-    if let Ok(env) = std::env::var("XDG_DATA_DIRS") {
-        dirs.extend(env.split(':').map(PathBuf::from))
-    } else {
-        dirs.extend(
-            ["/usr/share", "/usr/local/share"]
-                .into_iter()
-                .map(PathBuf::from),
-        );
-    }
+/// Sizes to look for in Flatpak's exported icons, largest first.
+const FLATPAK_ICON_SIZES: &[&str] = &[
+    "512x512", "256x256", "128x128", "96x96", "64x64", "48x48", "32x32",
+];
 
-    dirs
-});
+/// Looks for an icon exported by a Flatpak app.
+///
+/// Flatpak only exports icons into the hicolor theme, and the export directories aren't always in
+/// the search path, such as when Waybar is started outside of the graphical session.
+fn lookup_flatpak_icon(id: &str) -> Option<PathBuf> {
+    desktop::FLATPAK_EXPORTS.iter().find_map(|dir| {
+        let hicolor = dir.join("icons/hicolor");
+        std::iter::once(hicolor.join(format!("scalable/apps/{id}.svg")))
+            .chain(
+                FLATPAK_ICON_SIZES
+                    .iter()
+                    .map(|size| hicolor.join(format!("{size}/apps/{id}.png"))),
+            )
+            .find(|path| path.is_file())
+    })
+}
 
 trait DesktopAppInfoExt {
-    fn icon_path(&self) -> Option<PathBuf>;
+    fn icon_path(&self, theme: Option<&str>) -> Option<PathBuf>;
 }
 
 impl DesktopAppInfoExt for DesktopAppInfo {
-    fn icon_path(&self) -> Option<PathBuf> {
-        self.icon()
-            .and_then(|icon| IconExt::to_string(&icon))
-            .and_then(|name| lookup_icon(&name))
+    fn icon_path(&self, theme: Option<&str>) -> Option<PathBuf> {
+        let name = self.icon().and_then(|icon| IconExt::to_string(&icon))?;
+
+        // Some desktop entries, such as those of snaps, point straight at a file rather than
+        // naming an icon in the theme.
+        let path = Path::new(name.as_str());
+        if path.is_absolute() {
+            return path.is_file().then(|| path.to_path_buf());
+        }
+
+        lookup_icon(&name, theme)
     }
 }
@@ -0,0 +1,102 @@
+//! Generated icons for apps without one of their own: a rounded square in a colour picked from the
+//! app ID, with the app's initial on it.
+
+use std::f64::consts::{FRAC_PI_2, PI};
+
+use waybar_cffi::gtk::{
+    cairo::{self, Context, FontSlant, FontWeight, Format, ImageSurface},
+    gdk::RGBA,
+};
+
+/// The background colours, which are the mid-tones of the GNOME palette.
+const PALETTE: &[(f64, f64, f64)] = &[
+    (0.208, 0.518, 0.894),
+    (0.200, 0.820, 0.478),
+    (0.965, 0.827, 0.176),
+    (1.000, 0.471, 0.000),
+    (0.878, 0.106, 0.141),
+    (0.569, 0.255, 0.675),
+    (0.596, 0.416, 0.267),
+    (0.467, 0.463, 0.482),
+];
+
+/// Draws the avatar for the given app ID, at the given size and scale.
+///
+/// With a colour, only the initial is drawn, in that colour, to match symbolic and monochrome
+/// icons.
+pub fn draw(id: &str, size: i32, scale: i32, color: Option<&RGBA>) -> Option<cairo::Surface> {
+    let initial = initial(id)?;
+
+    let surface = ImageSurface::create(Format::ARgb32, size * scale, size * scale)
+        .inspect_err(|e| tracing::warn!(%e, "cannot create avatar surface"))
+        .ok()?;
+    surface.set_device_scale(f64::from(scale), f64::from(scale));
+
+    let cr = Context::new(&surface).ok()?;
+    let size = f64::from(size);
+
+    match color {
+        Some(color) => {
+            cr.set_source_rgba(color.red(), color.green(), color.blue(), color.alpha());
+        }
+        None => {
+            let (r, g, b) = PALETTE[hash(id) % PALETTE.len()];
+            rounded_square(&cr, size, size / 5.0);
+            cr.set_source_rgb(r, g, b);
+            cr.fill().ok()?;
+            cr.set_source_rgb(1.0, 1.0, 1.0);
+        }
+    }
+
+    // Cairo's toy text API is plenty for a single character, and spares us laying out text with
+    // Pango.
+    let text = initial.to_string();
+    cr.select_font_face("Sans", FontSlant::Normal, FontWeight::Bold);
+    cr.set_font_size(size * 0.6);
+    let extents = cr.text_extents(&text).ok()?;
+    cr.move_to(
+        (size - extents.width()) / 2.0 - extents.x_bearing(),
+        (size - extents.height()) / 2.0 - extents.y_bearing(),
+    );
+    cr.show_text(&text).ok()?;
+
+    drop(cr);
+    Some((*surface).clone())
+}
+
+/// Returns the initial to show for the app ID.
+///
+/// Reverse DNS IDs are identified by their last component, since `org.gnome.Nautilus` should be
+/// `N`, not `O`.
+fn initial(id: &str) -> Option<char> {
+    let name = if super::wine::is_exe(id) {
+        id
+    } else {
+        id.rsplit('.').next().unwrap_or(id)
+    };
+
+    let alphanumeric = |name: &str| name.chars().find(|c| c.is_alphanumeric());
+    alphanumeric(name)
+        .or_else(|| alphanumeric(id))
+        .map(|c| c.to_uppercase().next().unwrap_or(c))
+}
+
+/// Hashes the app ID to pick its colour.
+///
+/// The standard library's hasher isn't guaranteed to be stable between releases, and an app
+/// changing colour after an upgrade would defeat the point, so this is FNV-1a.
+fn hash(id: &str) -> usize {
+    let hash = id.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    hash as usize
+}
+
+fn rounded_square(cr: &Context, size: f64, radius: f64) {
+    cr.new_sub_path();
+    cr.arc(size - radius, radius, radius, -FRAC_PI_2, 0.0);
+    cr.arc(size - radius, size - radius, radius, 0.0, FRAC_PI_2);
+    cr.arc(radius, size - radius, radius, FRAC_PI_2, PI);
+    cr.arc(radius, radius, radius, PI, 3.0 * FRAC_PI_2);
+    cr.close_path();
+}
@@ -0,0 +1,70 @@
+//! Icon lookups for Windows programs running under Wine, which winemenubuilder exports as desktop
+//! entries and icons of its own.
+
+use std::path::PathBuf;
+
+use waybar_cffi::gtk::{
+    gio::{AppInfo, DesktopAppInfo},
+    prelude::{AppInfoExt, Cast},
+};
+
+use super::DesktopAppInfoExt;
+
+/// Returns true if the ID looks like the name of a Windows executable.
+pub fn is_exe(id: &str) -> bool {
+    id.len() > 4
+        && id
+            .get(id.len() - 4..)
+            .is_some_and(|extension| extension.eq_ignore_ascii_case(".exe"))
+}
+
+/// Looks up the icon for the given executable name.
+pub fn lookup(exe: &str, theme: Option<&str>) -> Option<PathBuf> {
+    lookup_desktop_entry(exe, theme).or_else(|| lookup_exported_icon(exe))
+}
+
+/// Finds the icon of a desktop entry that Wine created for the executable.
+fn lookup_desktop_entry(exe: &str, theme: Option<&str>) -> Option<PathBuf> {
+    AppInfo::all()
+        .into_iter()
+        .filter_map(|info| info.downcast::<DesktopAppInfo>().ok())
+        .filter(|info| {
+            info.startup_wm_class()
+                .is_some_and(|class| class.eq_ignore_ascii_case(exe))
+                || info.commandline().is_some_and(|commandline| {
+                    let commandline = commandline.to_string_lossy().to_lowercase();
+                    commandline.contains("wine") && commandline.contains(&exe.to_lowercase())
+                })
+        })
+        .find_map(|info| info.icon_path(theme))
+}
+
+/// Sizes to look for in the icons Wine exports, largest first.
+const SIZES: &[&str] = &["256x256", "128x128", "64x64", "48x48", "32x32", "16x16"];
+
+/// Finds an icon that Wine extracted from the executable.
+///
+/// winemenubuilder names these `{hash}_{name}.{index}.png` in the hicolor theme, where the name is
+/// the executable's name without the extension.
+fn lookup_exported_icon(exe: &str) -> Option<PathBuf> {
+    let home = std::env::var("HOME").ok()?;
+    let hicolor = PathBuf::from(home).join(".local/share/icons/hicolor");
+    let stem = exe.get(..exe.len().checked_sub(4)?)?.to_lowercase();
+
+    SIZES.iter().find_map(|size| {
+        std::fs::read_dir(hicolor.join(size).join("apps"))
+            .ok()?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .find(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.split_once('_'))
+                    .is_some_and(|(_, rest)| {
+                        rest.to_lowercase()
+                            .strip_prefix(&stem)
+                            .is_some_and(|rest| rest.starts_with('.') && rest.ends_with(".png"))
+                    })
+            })
+    })
+}
@@ -0,0 +1,150 @@
+//! Support for the Unity LauncherEntry API, which applications use to show progress and badge
+//! counts on their launcher.
+
+use async_channel::Sender;
+use futures::{Stream, TryStreamExt};
+use waybar_cffi::gtk::glib;
+use zbus::{
+    Connection, MatchRule, MessageStream,
+    message::Type as MessageType,
+    zvariant::{DeserializeDict, Type},
+};
+
+/// Starts a stream of launcher entry updates.
+///
+/// Unlike notifications, updates are broadcast as signals, so we don't need to monitor the bus:
+/// a normal match rule is enough.
+pub fn stream() -> impl Stream<Item = Update> {
+    let (tx, rx) = async_channel::unbounded();
+    glib::spawn_future_local(async move {
+        match listen(tx).await {
+            Ok(()) => tracing::info!("no longer listening for launcher entry updates"),
+            Err(e) => tracing::error!(%e, "D-Bus error listening for launcher entry updates"),
+        }
+    });
+
+    async_stream::stream! {
+        while let Ok(update) = rx.recv().await {
+            yield update;
+        }
+    }
+}
+
+/// An update to the launcher entry of an application.
+#[derive(Debug, Clone)]
+pub struct Update {
+    app_uri: String,
+    properties: Properties,
+}
+
+impl Update {
+    /// Returns the desktop entry ID the update applies to.
+    pub fn desktop_id(&self) -> &str {
+        // The URI is of the form `application://firefox.desktop`.
+        let id = self
+            .app_uri
+            .strip_prefix("application://")
+            .unwrap_or(&self.app_uri);
+        id.strip_suffix(".desktop").unwrap_or(id)
+    }
+}
+
+/// The properties sent in an update. Any property that isn't included is unchanged.
+#[derive(Debug, Clone, DeserializeDict, Type)]
+#[zvariant(rename_all = "kebab-case", signature = "a{sv}")]
+struct Properties {
+    count: Option<i64>,
+    count_visible: Option<bool>,
+    progress: Option<f64>,
+    progress_visible: Option<bool>,
+    urgent: Option<bool>,
+}
+
+/// The current state of an application's launcher entry, built up from the updates it has sent.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Entry {
+    count: i64,
+    count_visible: bool,
+    progress: f64,
+    progress_visible: bool,
+    urgent: bool,
+}
+
+impl Entry {
+    /// Applies an update to the entry.
+    pub fn apply(&mut self, update: &Update) {
+        let properties = &update.properties;
+        if let Some(count) = properties.count {
+            self.count = count;
+        }
+        if let Some(visible) = properties.count_visible {
+            self.count_visible = visible;
+        }
+        if let Some(progress) = properties.progress {
+            self.progress = progress.clamp(0.0, 1.0);
+        }
+        if let Some(visible) = properties.progress_visible {
+            self.progress_visible = visible;
+        }
+        if let Some(urgent) = properties.urgent {
+            self.urgent = urgent;
+        }
+    }
+
+    /// Returns the badge count, if it should be shown.
+    pub fn count(&self) -> Option<i64> {
+        self.count_visible.then_some(self.count)
+    }
+
+    /// Returns the progress, between 0 and 1, if it should be shown.
+    pub fn progress(&self) -> Option<f64> {
+        self.progress_visible.then_some(self.progress)
+    }
+
+    /// Returns true if the application has requested attention.
+    pub fn urgent(&self) -> bool {
+        self.urgent
+    }
+}
+
+/// Returns true if the given desktop entry ID refers to the given app ID.
+///
+/// Desktop entry IDs and app IDs often differ in case, and sometimes one is reverse DNS while the
+/// other isn't, so this is a little fuzzy.
+pub fn matches(desktop_id: &str, app_id: &str) -> bool {
+    if desktop_id.eq_ignore_ascii_case(app_id) {
+        return true;
+    }
+
+    let last = |id: &str| id.rsplit('.').next().unwrap_or(id).to_lowercase();
+    last(desktop_id) == last(app_id)
+}
+
+static INTERFACE: &str = "com.canonical.Unity.LauncherEntry";
+static SIGNAL: &str = "Update";
+
+#[tracing::instrument(level = "TRACE", skip_all, err)]
+async fn listen(tx: Sender<Update>) -> anyhow::Result<()> {
+    let conn = Connection::session().await?;
+    let rule = MatchRule::builder()
+        .msg_type(MessageType::Signal)
+        .interface(INTERFACE)?
+        .member(SIGNAL)?
+        .build();
+
+    let mut stream = MessageStream::for_match_rule(rule, &conn, None).await?;
+    while let Some(msg) = stream.try_next().await? {
+        match msg.body().deserialize::<(String, Properties)>() {
+            Ok((app_uri, properties)) => {
+                tx.send(Update {
+                    app_uri,
+                    properties,
+                })
+                .await?;
+            }
+            Err(e) => tracing::warn!(%e, ?msg, "cannot parse launcher entry update"),
+        }
+    }
+
+    Ok(())
+}
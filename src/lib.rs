@@ -1,26 +1,34 @@
 use std::{
+    cell::RefCell,
     cmp::Ordering,
-    collections::{BTreeMap, BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    rc::Rc,
     sync::{Arc, LazyLock, Mutex},
 };
 
-use button::Button;
-use config::Config;
+use button::{Button, Carried};
+use config::{Config, Layout, MatchStrategy, OverflowMode, Sort};
+use css::UserCss;
 use error::Error;
 use futures::StreamExt;
 use itertools::Itertools;
-use niri::{Snapshot, Window};
+use niri::{Niri, Snapshot, Window};
 use niri_ipc::Workspace;
-use notify::EnrichedNotification;
+use notify::{EnrichedNotification, Urgency};
 use output::Matcher;
-use process::Process;
-use state::{Event, State};
+use pinned::Pinned;
+use state::{DisplayedWindow, Event, State, WindowOrder};
 use tracing_subscriber::{EnvFilter, fmt::format::FmtSpan};
 use waybar_cffi::{
     Module,
     gtk::{
-        self, Orientation, gio,
-        glib::MainContext,
+        self, IconSize, Orientation, PolicyType, ReliefStyle,
+        gdk::{EventMask, ScrollDirection, keys::constants as keys},
+        glib::{MainContext, Propagation},
+        prelude::{
+            BinExt, ButtonExt, Cast, EventBoxExt, ObjectExt, OrientableExt, ScrolledWindowExt,
+            ToggleButtonExt, WidgetExtManual,
+        },
         traits::{BoxExt, ContainerExt, LabelExt, StyleContextExt, WidgetExt},
     },
     waybar_module,
@@ -28,13 +36,23 @@ use waybar_cffi::{
 
 mod button;
 mod config;
+mod css;
+mod desktop;
+mod dnd;
 mod error;
+mod exec;
 mod icon;
+mod launcher;
+mod menu;
 mod niri;
 mod notify;
 mod output;
+mod pinned;
 mod process;
 mod state;
+mod template;
+mod thumbnail;
+mod tray;
 
 static TRACING: LazyLock<()> = LazyLock::new(|| {
     if let Err(e) = tracing_subscriber::fmt()
@@ -49,17 +67,44 @@ static TRACING: LazyLock<()> = LazyLock::new(|| {
 struct TaskbarModule {}
 
 impl Module for TaskbarModule {
-    type Config = Config;
+    type Config = serde_json::Value;
 
-    fn init(info: &waybar_cffi::InitInfo, config: Config) -> Self {
+    fn init(info: &waybar_cffi::InitInfo, config: serde_json::Value) -> Self {
         // Ensure tracing-subscriber is initialised.
         *TRACING;
 
         let module = Self {};
-        let state = State::new(config);
+
+        // An invalid configuration is reported in the taskbar, and otherwise replaced by the
+        // defaults, rather than preventing the module from loading at all.
+        let mut error = None;
+        let config = Config::from_value(config).unwrap_or_else(|e| {
+            tracing::error!(%e, "invalid module configuration; using defaults");
+            error = Some(format!("invalid module configuration: {e}"));
+            Config::default()
+        });
+
+        // If the configuration points to a separate file, that file provides the configuration
+        // instead, and is watched for changes.
+        let path = config.file();
+        let config = match &path {
+            Some(path) => match config::file::load(path) {
+                Ok(config) => config,
+                Err(e) => {
+                    tracing::error!(%e, "cannot load configuration file; using module configuration");
+                    error = Some(e.to_string());
+                    config
+                }
+            },
+            None => config,
+        };
+        if error.is_none() {
+            error = unknown_options_warning(&config);
+        }
+        let state = State::new(config, path);
 
         let context = MainContext::default();
-        if let Err(e) = context.block_on(init(info, state)) {
+        if let Err(e) = context.block_on(init(info, state, error)) {
             tracing::error!(%e, "Niri taskbar module init failed");
         }
 
@@ -70,48 +115,555 @@ impl Module for TaskbarModule {
 waybar_module!(TaskbarModule);
 
 #[tracing::instrument(level = "DEBUG", skip_all, err)]
-async fn init(info: &waybar_cffi::InitInfo, state: State) -> Result<(), Error> {
-    // Set up the box that we'll use to contain the actual window buttons.
-    let root = info.get_root_widget();
-    let container = gtk::Box::new(
-        match state.config().orientation() {
-            config::Orientation::Vertical => Orientation::Vertical,
-            config::Orientation::Horizontal => Orientation::Horizontal,
-        },
-        0,
-    );
+async fn init(
+    info: &waybar_cffi::InitInfo,
+    state: State,
+    error: Option<String>,
+) -> Result<(), Error> {
+    let root: gtk::Container = (*info.get_root_widget()).clone();
 
-    container.style_context().add_class("niri-taskbar");
-    root.add(&container);
+    // Configuration errors are shown before the taskbar itself.
+    let mut error_indicator = ErrorIndicator::new();
+    error_indicator.set_config_error(error);
+    root.add(&error_indicator.image);
 
-    // We need to spawn a task to receive the window snapshots and update the container.
+    let do_not_disturb = DoNotDisturbToggle::new(state.config().notifications_do_not_disturb());
+    do_not_disturb.set_visible(state.config().notifications_do_not_disturb_button());
+    root.add(&do_not_disturb.button);
+
+    // We need to spawn a task to build the taskbar and then receive the window snapshots to update
+    // it. The taskbar is only built once the main loop is running, since the module hasn't been
+    // added to the bar yet, and we need the bar to know which orientation to use.
     let context = MainContext::default();
-    context.spawn_local(async move { Instance::new(state, container).task().await });
+    context.spawn_local(async move {
+        if state.config().orientation().is_none() {
+            match detect_orientation(&root) {
+                Some(orientation) => state.set_detected_orientation(orientation),
+                None => tracing::info!("cannot detect bar orientation; assuming horizontal"),
+            }
+        }
+
+        let (pinned, container) = build(&root, &state);
+        Instance::new(state, container, pinned, error_indicator, do_not_disturb)
+            .task()
+            .await
+    });
 
     Ok(())
 }
 
+/// Builds the taskbar widgets within the root widget, returning the pinned launchers and the
+/// container for the workspaces and buttons.
+fn build(root: &gtk::Container, state: &State) -> (Pinned, gtk::Box) {
+    let orientation = match state.orientation() {
+        config::Orientation::Vertical => Orientation::Vertical,
+        config::Orientation::Horizontal => Orientation::Horizontal,
+    };
+
+    // Pinned launchers are also outside the taskbar container, so that they're always visible,
+    // regardless of overflow.
+    let mut pinned = Pinned::new(orientation);
+    pinned.rebuild(state);
+    root.add(pinned.widget());
+
+    // Set up the box that we'll use to contain the actual window buttons.
+    let container = gtk::Box::new(orientation, state.config().spacing().workspaces());
+
+    container.style_context().add_class("niri-taskbar");
+
+    // Boxes don't receive events of their own, so we need to wrap the container in an event box
+    // to handle scrolling over the bar background.
+    let event_box = gtk::EventBox::new();
+    event_box.add_events(EventMask::SCROLL_MASK | EventMask::SMOOTH_SCROLL_MASK);
+
+    // In scroll mode, the container is wrapped in a scrolled window that grows until it reaches
+    // its maximum size.
+    let config = state.config();
+    let overflow = config.overflow(state.orientation());
+    if overflow.mode() == OverflowMode::Scroll {
+        let scrolled = gtk::ScrolledWindow::new(None::<&gtk::Adjustment>, None::<&gtk::Adjustment>);
+        match state.orientation() {
+            config::Orientation::Horizontal => {
+                scrolled.set_policy(PolicyType::Automatic, PolicyType::Never);
+                scrolled.set_max_content_width(overflow.max_size());
+                scrolled.set_propagate_natural_width(true);
+            }
+            config::Orientation::Vertical => {
+                scrolled.set_policy(PolicyType::Never, PolicyType::Automatic);
+                scrolled.set_max_content_height(overflow.max_size());
+                scrolled.set_propagate_natural_height(true);
+            }
+        }
+        scrolled.add(&container);
+        scrolled.show();
+        event_box.add(&scrolled);
+    } else {
+        event_box.add(&container);
+    }
+    connect_workspace_scroll(&event_box, state);
+    connect_keyboard_navigation(&event_box, &container);
+    root.add(&event_box);
+    event_box.show();
+
+    (pinned, container)
+}
+
+/// Detects the orientation of the bar from the box the module has been added to.
+///
+/// Waybar lays modules out in boxes that match the orientation of the bar, so the first box above
+/// the root widget tells us which way the bar runs.
+fn detect_orientation(root: &gtk::Container) -> Option<config::Orientation> {
+    let mut parent = root.parent();
+    while let Some(widget) = parent {
+        if let Some(container) = widget.downcast_ref::<gtk::Box>() {
+            return Some(match container.orientation() {
+                Orientation::Vertical => config::Orientation::Vertical,
+                _ => config::Orientation::Horizontal,
+            });
+        }
+        parent = widget.parent();
+    }
+
+    None
+}
+
+/// Returns a warning listing any unrecognised options in the configuration, which are otherwise
+/// silently ignored.
+fn unknown_options_warning(config: &Config) -> Option<String> {
+    let unknown = config.unknown_options();
+    if unknown.is_empty() {
+        return None;
+    }
+
+    tracing::warn!(?unknown, "unknown configuration options");
+    Some(format!(
+        "unknown configuration options: {}",
+        unknown
+            .iter()
+            .map(|option| format!("`{option}`"))
+            .join(", ")
+    ))
+}
+
+/// The indicator shown when there's a problem with the configuration, with the errors in its
+/// tooltip.
+///
+/// Problems with the configuration as a whole and with the overrides for the bar's output are
+/// tracked separately, since they're found at different times, and fixing one shouldn't hide the
+/// other.
+struct ErrorIndicator {
+    image: gtk::Image,
+    config: Option<String>,
+    output: Option<String>,
+}
+
+impl ErrorIndicator {
+    fn new() -> Self {
+        let image = gtk::Image::from_icon_name(Some("dialog-warning-symbolic"), IconSize::Button);
+        image.style_context().add_class("niri-taskbar-error");
+        image.set_no_show_all(true);
+        Self {
+            image,
+            config: None,
+            output: None,
+        }
+    }
+
+    /// Sets the error with the configuration as a whole, if there is one.
+    fn set_config_error(&mut self, error: Option<String>) {
+        self.config = error;
+        self.update();
+    }
+
+    /// Sets the error with the overrides for the bar's output, if there is one.
+    fn set_output_error(&mut self, error: Option<String>) {
+        self.output = error;
+        self.update();
+    }
+
+    /// Shows the current errors, or hides the indicator if there aren't any.
+    fn update(&self) {
+        let errors = [&self.config, &self.output]
+            .into_iter()
+            .flatten()
+            .join("\n");
+        self.image
+            .set_tooltip_text(Some(errors.as_str()).filter(|errors| !errors.is_empty()));
+        self.image.set_visible(!errors.is_empty());
+    }
+}
+
+/// The button that toggles do not disturb, which is only shown if it's enabled in the
+/// configuration.
+struct DoNotDisturbToggle {
+    button: gtk::ToggleButton,
+    toggled: async_channel::Receiver<bool>,
+}
+
+impl DoNotDisturbToggle {
+    fn new(active: bool) -> Self {
+        let image =
+            gtk::Image::from_icon_name(Some("notifications-disabled-symbolic"), IconSize::Button);
+        image.show();
+
+        let button = gtk::ToggleButton::new();
+        button.set_relief(ReliefStyle::None);
+        button.set_focus_on_click(false);
+        button.set_tooltip_text(Some("Do not disturb"));
+        button
+            .style_context()
+            .add_class("niri-taskbar-do-not-disturb");
+        button.set_no_show_all(true);
+        button.set_active(active);
+        button.add(&image);
+
+        let (tx, toggled) = async_channel::unbounded();
+        button.connect_toggled(move |button| {
+            if let Err(e) = tx.try_send(button.is_active()) {
+                tracing::error!(%e, "error sending do not disturb toggle");
+            }
+        });
+
+        Self { button, toggled }
+    }
+
+    fn set_visible(&self, visible: bool) {
+        self.button.set_visible(visible);
+    }
+}
+
+/// Switches workspaces when scrolling over the taskbar background.
+///
+/// Scrolling over a window button is handled by the button itself, and doesn't propagate here.
+fn connect_workspace_scroll(event_box: &gtk::EventBox, state: &State) {
+    let state = state.clone();
+    let steps = button::ScrollSteps::default();
+
+    event_box.connect_scroll_event(move |_, event| {
+        let Some(forward) = steps.step(event) else {
+            return Propagation::Proceed;
+        };
+        if button::navigate_on_scroll(&state, event, forward) {
+            return Propagation::Stop;
+        }
+
+        let result = if forward {
+            Niri::focus_workspace_down
+        } else {
+            Niri::focus_workspace_up
+        };
+
+        state
+            .niri()
+            .spawn("error trying to switch workspace", result);
+        Propagation::Stop
+    });
+}
+
+/// Cycles through the windows on a workspace when scrolling over its container.
+///
+/// As with the taskbar background, scrolling over a window button is handled by the button itself.
+fn connect_window_scroll(event_box: &gtk::EventBox, state: &State, workspace_id: u64) {
+    let state = state.clone();
+
+    let steps = button::ScrollSteps::default();
+
+    event_box.add_events(EventMask::SCROLL_MASK | EventMask::SMOOTH_SCROLL_MASK);
+    event_box.connect_scroll_event(move |_, event| {
+        let Some(forward) = steps.step(event) else {
+            // As with buttons, smooth scrolling that hasn't made up a step yet isn't passed on to
+            // the taskbar background.
+            return if event.direction() == ScrollDirection::Smooth {
+                Propagation::Stop
+            } else {
+                Propagation::Proceed
+            };
+        };
+        if button::navigate_on_scroll(&state, event, forward) {
+            return Propagation::Stop;
+        }
+
+        if let Some(target) = state.window_order().cycle_workspace(workspace_id, forward) {
+            state
+                .niri()
+                .spawn("error trying to cycle workspace windows", move |niri| {
+                    niri.activate_window(target)
+                });
+        }
+        Propagation::Stop
+    });
+}
+
+/// Moves the keyboard focus between window buttons with the arrow keys.
+///
+/// Gtk can move focus with the arrow keys on its own, but only within a single container, and the
+/// buttons are split across the workspace containers. Instead, we walk the container hierarchy,
+/// which is kept in display order. Each workspace container is wrapped in an event box, while the
+/// buttons are direct children of the taskbar in the other layouts.
+fn connect_keyboard_navigation(event_box: &gtk::EventBox, container: &gtk::Box) {
+    let container = container.clone();
+
+    event_box.connect_key_press_event(move |_, event| {
+        let buttons: Vec<gtk::Widget> = container
+            .children()
+            .into_iter()
+            .flat_map(|child| match child.downcast::<gtk::EventBox>() {
+                Ok(event_box) => event_box
+                    .child()
+                    .and_then(|workspace| workspace.downcast::<gtk::Container>().ok())
+                    .map(|workspace| workspace.children())
+                    .unwrap_or_default(),
+                Err(child) => vec![child],
+            })
+            .filter(|child| child.is::<gtk::Button>() && child.is_visible())
+            .collect();
+        if buttons.is_empty() {
+            return Propagation::Proceed;
+        }
+
+        let current = buttons.iter().position(|button| button.has_focus());
+        let last = buttons.len() - 1;
+        let target = match (event.keyval(), current) {
+            (keys::Left | keys::Up, Some(idx)) => idx.saturating_sub(1),
+            (keys::Right | keys::Down, Some(idx)) => (idx + 1).min(last),
+            (keys::Left | keys::Up | keys::Right | keys::Down, None) => 0,
+            (keys::Home, _) => 0,
+            (keys::End, _) => last,
+            _ => return Propagation::Proceed,
+        };
+
+        buttons[target].grab_focus();
+        Propagation::Stop
+    });
+}
+
 #[derive(Debug)]
 struct WorkspaceDisplay {
     state: Workspace,
+    event_box: gtk::EventBox,
     container: gtk::Box,
     label: gtk::Label,
+    icon: gtk::Label,
+    label_box: gtk::EventBox,
+    separator: Option<gtk::Separator>,
     buttons: BTreeMap<u64, Button>, // Key: widnow id
 }
 
+impl WorkspaceDisplay {
+    /// Sets or clears the `urgent` class on the workspace container and label.
+    fn set_urgent(&self, urgent: bool) {
+        for context in [
+            self.container.style_context(),
+            self.label_box.style_context(),
+        ] {
+            if urgent {
+                context.add_class("urgent");
+            } else {
+                context.remove_class("urgent");
+            }
+        }
+    }
+
+    /// Renders the workspace label from the configured format.
+    ///
+    /// This needs to happen after the buttons have been updated, since the format can include the
+    /// number of windows.
+    fn update_label(&self, config: &Config) {
+        let format = if self.state.is_focused {
+            config.workspace_format_focused()
+        } else {
+            config.workspace_format()
+        };
+
+        // When the format is markup, the values need to be escaped.
+        let escape = |value: String| {
+            if config.label_markup() {
+                gtk::glib::markup_escape_text(&value).to_string()
+            } else {
+                value
+            }
+        };
+        let name = || workspace_name(&self.state);
+        let mut text = format.render_lines(|key| {
+            match key {
+                "idx" => Some(self.state.idx.to_string()),
+                "name" => Some(name()),
+                "output" => self.state.output.clone(),
+                "windows" => Some(
+                    self.buttons
+                        .values()
+                        .map(|button| button.windows().len())
+                        .sum::<usize>()
+                        .to_string(),
+                ),
+                _ => None,
+            }
+            .map(escape)
+        });
+
+        // The workspace name, or index if it doesn't have one, follows the format.
+        if config.workspace_names() {
+            text.push_str(&escape(name()));
+        }
+
+        if config.label_markup() {
+            self.label.set_markup(&text);
+        } else {
+            self.label.set_text(&text);
+        }
+
+        match config.workspace_icon(&self.state) {
+            Some(icon) => {
+                self.icon.set_text(icon);
+                self.icon.show();
+            }
+            None => self.icon.hide(),
+        }
+    }
+}
+
+/// Returns the configured sort, unless it relies on window positions that Niri doesn't report, in
+/// which case windows are left in the order they were opened.
+fn sort(state: &State) -> Sort {
+    match state.config().sort() {
+        Sort::Position if !state.niri().capabilities().window_layouts() => Sort::Insertion,
+        sort => sort,
+    }
+}
+
+/// Compares two buttons on the same workspace for display order.
+///
+/// Whatever the sort, ties fall back to the layout position, so that the order is stable.
+fn compare_buttons(sort: Sort, a: &Button, b: &Button) -> Ordering {
+    compare_by_sort(sort, a, b).then_with(|| compare_positions(a, b))
+}
+
+/// Compares two buttons by the given sort alone, without the layout position as a tie-breaker.
+fn compare_by_sort(sort: Sort, a: &Button, b: &Button) -> Ordering {
+    match sort {
+        Sort::Position => compare_positions(a, b),
+        Sort::Insertion => a.created().cmp(&b.created()),
+        Sort::AppId => a.app_id().cmp(&b.app_id()),
+        // Most recent first, with windows that have never been focused at the end.
+        Sort::Focus => b.last_focused().cmp(&a.last_focused()),
+    }
+}
+
+/// Compares the positions of two buttons' windows within a workspace.
+fn compare_positions(a: &Button, b: &Button) -> Ordering {
+    match (a.pos(), b.pos()) {
+        (Some((row1, col1)), Some((row2, col2))) => match row1.cmp(row2) {
+            Ordering::Equal => col1.cmp(col2),
+            ord => ord,
+        },
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// Returns the name of the workspace, or its index if it doesn't have one.
+fn workspace_name(workspace: &Workspace) -> String {
+    match &workspace.name {
+        Some(name) => name.clone(),
+        None => workspace.idx.to_string(),
+    }
+}
+
+/// Returns the workspaces in display order, which is by output and then by index.
+fn ordered_workspaces(
+    workspaces: &BTreeMap<u64, WorkspaceDisplay>,
+) -> impl Iterator<Item = &WorkspaceDisplay> {
+    workspaces.values().sorted_unstable_by(|a, b| {
+        (&a.state.output, a.state.idx).cmp(&(&b.state.output, b.state.idx))
+    })
+}
+
 struct Instance {
     workspaces: BTreeMap<u64, WorkspaceDisplay>, // Key: workspace id
+    flat: BTreeMap<FlatKey, Button>,             // Only used without workspace containers
+    output_headers: BTreeMap<String, gtk::Label>, // Key: output name
     container: gtk::Box,
     last_snapshot: Option<Snapshot>,
+    // The Niri outputs in the last snapshot, so that outputs being added or removed can be noticed.
+    outputs: BTreeSet<String>,
+    // The Niri output the taskbar is on, if it could be worked out, whose overrides apply.
+    output: Option<String>,
+    launcher_entries: HashMap<String, launcher::Entry>, // Key: desktop entry ID
+    notification_windows: HashMap<u32, Vec<u64>>,       // Key: notification ID
+    carried: HashMap<u64, Carried>,                     // Key: window ID
+    overflow_chip: Option<OverflowChip>,
+    pinned: Pinned,
+    user_css: UserCss,
+    error_indicator: ErrorIndicator,
+    // Whether notifications are currently only counted, rather than making buttons urgent.
+    do_not_disturb: bool,
+    do_not_disturb_toggle: DoNotDisturbToggle,
+    // The configuration without any output overrides, which are applied again if the bar turns out
+    // to be on a different output.
+    base_config: Arc<Config>,
     state: State,
 }
 
+/// The key of a button in the layouts without workspace containers.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum FlatKey {
+    App(String),
+    WorkspaceApp(u64, String),
+    // Windows without an app ID can't be grouped, so they get their own buttons.
+    Window(u64),
+}
+
+/// The chip shown in place of the buttons that don't fit when the overflow mode is chip.
+struct OverflowChip {
+    button: gtk::Button,
+    hidden: Rc<RefCell<Vec<button::Member>>>,
+}
+
+impl OverflowChip {
+    fn new(state: &State) -> Self {
+        let button = gtk::Button::new();
+        button.set_relief(ReliefStyle::None);
+        button.style_context().add_class("niri-taskbar-overflow");
+        button.set_no_show_all(true);
+
+        let hidden: Rc<RefCell<Vec<button::Member>>> = Rc::default();
+        button.connect_clicked({
+            let state = state.clone();
+            let hidden = hidden.clone();
+            move |button| button::popup_window_list(&state, &hidden.borrow(), button)
+        });
+
+        Self { button, hidden }
+    }
+}
+
 impl Instance {
-    pub fn new(state: State, container: gtk::Box) -> Self {
+    pub fn new(
+        state: State,
+        container: gtk::Box,
+        pinned: Pinned,
+        error_indicator: ErrorIndicator,
+        do_not_disturb_toggle: DoNotDisturbToggle,
+    ) -> Self {
         Self {
             workspaces: Default::default(),
+            flat: BTreeMap::new(),
+            output_headers: BTreeMap::new(),
             container,
             last_snapshot: None,
+            outputs: BTreeSet::new(),
+            output: None,
+            launcher_entries: HashMap::new(),
+            notification_windows: HashMap::new(),
+            carried: HashMap::new(),
+            overflow_chip: None,
+            pinned,
+            user_css: UserCss::default(),
+            error_indicator,
+            do_not_disturb: state.config().notifications_do_not_disturb(),
+            do_not_disturb_toggle,
+            base_config: state.config(),
             state,
         }
     }
@@ -119,10 +671,22 @@ impl Instance {
     pub async fn task(&mut self) {
         // We have to build the output filter here, because until the Glib event loop has run the
         // container hasn't been realised, which means we can't figure out which output we're on.
-        let output_filter = Arc::new(Mutex::new(self.build_output_filter().await));
+        let output_filter = self.build_output_filter().await;
+        self.apply_output_config();
+        // The user CSS has to be loaded before any widgets are created, since they only pick up
+        // the file that's configured when they're created.
+        self.user_css.set(self.state.config().css());
+        self.pinned.rebuild(&self.state);
+        let output_filter = Arc::new(Mutex::new(output_filter));
 
         let mut stream = match self.state.event_stream() {
-            Ok(stream) => Box::pin(stream),
+            Ok(stream) => Box::pin(futures::stream::select(
+                stream,
+                self.do_not_disturb_toggle
+                    .toggled
+                    .clone()
+                    .map(Event::DoNotDisturb),
+            )),
             Err(e) => {
                 tracing::error!(%e, "error starting event stream");
                 return;
@@ -130,30 +694,318 @@ impl Instance {
         };
         while let Some(event) = stream.next().await {
             match event {
-                Event::Notification(notification) => self.process_notification(notification).await,
+                Event::Notification(notification) => {
+                    self.process_notification(notification).await;
+                    self.update_urgency();
+                }
+                Event::NotificationClosed(id) => {
+                    for window_id in self.notification_windows.remove(&id).unwrap_or_default() {
+                        if let Some(button) = self.button_for_window(window_id) {
+                            button.notification_closed(id);
+                        }
+                    }
+                    self.update_urgency();
+                }
+                Event::TrayAttention(attention) => {
+                    self.process_tray_attention(attention).await;
+                    self.update_urgency();
+                }
+                Event::LauncherUpdate(update) => {
+                    self.process_launcher_update(update);
+                    self.update_urgency();
+                }
                 Event::WindowSnapshot(windows) => {
+                    // Niri doesn't send an event when outputs change, but every output has at
+                    // least one workspace, so the workspaces show it.
+                    let outputs: BTreeSet<String> = windows
+                        .workspaces
+                        .iter()
+                        .filter_map(|workspace| workspace.output.clone())
+                        .collect();
+                    if outputs != self.outputs {
+                        let first = self.outputs.is_empty();
+                        self.outputs = outputs;
+                        if !first {
+                            self.refresh_output_filter(&output_filter).await;
+                        }
+                    }
+
                     self.update_window_placement(&windows);
-                    self.process_workspace_update(&windows.workspaces, output_filter.clone())
-                        .await;
-                    self.process_window_snapshot(windows, output_filter.clone())
-                        .await;
-                    self.container.show_all();
+                    self.pinned.update(&windows);
+                    self.process_snapshot(windows, output_filter.clone()).await;
+                }
+                Event::ConfigReloaded(Err(e)) => {
+                    tracing::warn!(%e, "cannot reload configuration");
+                    self.error_indicator.set_config_error(Some(e.to_string()));
+                }
+                Event::ConfigReloaded(Ok(config)) => {
+                    tracing::info!("reloaded configuration");
+                    self.error_indicator
+                        .set_config_error(unknown_options_warning(&config));
+                    self.base_config = Arc::from(config);
+
+                    // The output options may have changed which outputs are shown, so the filter
+                    // is built again before everything else is.
+                    let new = self.build_output_filter().await;
+                    *output_filter.lock().expect("output filter lock") = new;
+
+                    // Most options are applied when buttons and workspaces are created, so
+                    // everything is rebuilt from the last snapshot rather than waiting for the
+                    // windows to change.
+                    self.reapply_config(&output_filter).await;
+                }
+                Event::IconThemeChanged(theme) => {
+                    tracing::info!(?theme, "icon theme changed");
+                    self.state.icon_cache().set_system_theme(theme);
+                    icon::clear_loaded();
+                    for button in self.buttons() {
+                        button.load_icon();
+                    }
+                    self.pinned.rebuild(&self.state);
+                }
+                Event::IconAdopted(app_id) => {
+                    // The button that adopted the icon already shows it, but the app's other
+                    // buttons are still showing the fallback.
+                    for button in self.buttons() {
+                        if button.app_id() == Some(app_id.as_str()) {
+                            button.load_icon();
+                        }
+                    }
+                }
+                Event::WindowActivated(window_id) => {
+                    if let Some(button) = self.button_for_window(window_id) {
+                        button.dismiss_notifications();
+                    }
+                }
+                Event::UrgentTimedOut(window_id) => {
+                    if let Some(button) = self.button_for_window(window_id) {
+                        button.clear_notifications();
+                    }
+                    self.update_urgency();
+                }
+                Event::MonitorsChanged => {
+                    self.refresh_output_filter(&output_filter).await;
+                }
+                Event::DoNotDisturb(enabled) => {
+                    tracing::info!(enabled, "toggled do not disturb");
+                    self.do_not_disturb = enabled;
+                    if !enabled {
+                        for button in self.buttons() {
+                            button.release_held();
+                        }
+                        self.update_urgency();
+                    }
                 }
-                Event::FloatingClosed => {
-                    if self.state.config().close_to_tiling() {
-                        let _ = self.state.niri().focus_tiling();
+                Event::WindowFocused(id) => {
+                    // Niri has told us about the focus change, so the notifications can be
+                    // cleared straight away, rather than waiting for the snapshot to be
+                    // processed.
+                    if let Some(button) = self.button_for_window(id) {
+                        button.clear_notifications();
                     }
+                    self.update_urgency();
+                }
+                Event::NotificationsDisconnected => {
+                    self.container
+                        .style_context()
+                        .add_class("notifications-disconnected");
+                }
+                Event::NotificationsReconnected => {
+                    // Anything closed during the outage was missed, but the notifications sent
+                    // before it are still attributed to their windows.
+                    self.container
+                        .style_context()
+                        .remove_class("notifications-disconnected");
+                }
+                Event::WindowStreamDisconnected => {
+                    // The buttons are left in place, but themes can show that they're stale.
+                    self.container.style_context().add_class("disconnected");
+                }
+                Event::WindowStreamReset => {
+                    // Window IDs start again from scratch when Niri restarts, so buttons can't be
+                    // reused.
+                    self.container.style_context().remove_class("disconnected");
+                    self.clear();
+                    self.last_snapshot = None;
+                    self.notification_windows.clear();
                 }
             }
         }
     }
 
-    #[tracing::instrument(level = "DEBUG", skip(self))]
-    async fn build_output_filter(&self) -> output::Filter {
-        if self.state.config().show_all_outputs() {
-            return output::Filter::ShowAll;
+    /// Starts or stops the notification, launcher entry, and tray attention streams after the
+    /// configuration has changed, and forgets whatever the stopped streams had shown.
+    fn update_services(&mut self) {
+        self.state.update_services();
+
+        let config = self.state.config();
+        if !config.notifications_enabled() {
+            self.notification_windows.clear();
+            self.container
+                .style_context()
+                .remove_class("notifications-disconnected");
+            for button in self.buttons() {
+                button.clear_notifications();
+            }
+        }
+        if !config.tray_attention_enabled() {
+            for button in self.buttons() {
+                button.set_tray_attention(false, self.do_not_disturb);
+            }
+        }
+        if !config.launcher_entries_enabled() {
+            self.launcher_entries.clear();
+        }
+    }
+
+    /// Applies the configuration overrides for the output the taskbar is on, if there are any.
+    ///
+    /// Options that are used to build the taskbar itself, such as the orientation, can't be
+    /// overridden, since the taskbar has already been built by the time we know the output.
+    fn apply_output_config(&mut self) {
+        let base = self.base_config.clone();
+        self.error_indicator.set_output_error(None);
+        let config = match self
+            .output
+            .as_deref()
+            .and_then(|output| base.for_output(output).map(|config| (output, config)))
+        {
+            Some((_, Ok(config))) => Arc::new(config),
+            Some((output, Err(e))) => {
+                tracing::error!(%e, output, "invalid output configuration; ignoring overrides");
+                self.error_indicator.set_output_error(Some(format!(
+                    "invalid configuration for output {output}: {e}"
+                )));
+                base
+            }
+            None => base,
+        };
+
+        // The icon cache starts out with the theme from the base configuration, and the theme can
+        // also change on reload.
+        if self
+            .state
+            .icon_cache()
+            .set_theme(config.icon_theme().map(String::from))
+        {
+            icon::clear_loaded();
+        }
+        self.state.set_config(config);
+    }
+
+    /// Works out which output the taskbar is on again, after outputs have been added, removed, or
+    /// rearranged, and rebuilds the taskbar if it has changed.
+    async fn refresh_output_filter(&mut self, filter: &Arc<Mutex<output::Filter>>) {
+        let old_output = self.output.clone();
+        let new = self.build_output_filter().await;
+        if *filter.lock().expect("output filter lock") == new && old_output == self.output {
+            return;
+        }
+
+        tracing::info!(filter = ?new, output = ?self.output, "output changed");
+        *filter.lock().expect("output filter lock") = new;
+        self.reapply_config(filter).await;
+    }
+
+    /// Applies the configuration for the current output filter to the whole taskbar.
+    async fn reapply_config(&mut self, filter: &Arc<Mutex<output::Filter>>) {
+        self.apply_output_config();
+        self.update_services();
+        self.do_not_disturb_toggle
+            .set_visible(self.state.config().notifications_do_not_disturb_button());
+        self.user_css.set(self.state.config().css());
+        self.pinned.rebuild(&self.state);
+        self.rebuild(filter).await;
+    }
+
+    /// Rebuilds the taskbar from the last snapshot, such as after the configuration has changed.
+    ///
+    /// The notifications, urgency, and sorting history of the buttons are carried over to the
+    /// buttons that replace them.
+    async fn rebuild(&mut self, filter: &Arc<Mutex<output::Filter>>) {
+        self.carried = self
+            .buttons()
+            .flat_map(|button| {
+                let carried = button.carry();
+                button
+                    .windows()
+                    .members()
+                    .into_iter()
+                    .map(move |member| (member.id, carried.clone()))
+            })
+            .collect();
+
+        self.clear();
+        if let Some(snapshot) = self.last_snapshot.clone() {
+            self.process_snapshot(snapshot, filter.clone()).await;
         }
+        self.carried.clear();
+    }
+
+    /// Restores the state carried over for any of the given windows to a new button.
+    fn restore(carried: &mut HashMap<u64, Carried>, button: &Button, windows: &[&Window]) {
+        if let Some(state) = windows.iter().find_map(|window| carried.remove(&window.id)) {
+            button.restore(state);
+        }
+    }
+
+    /// Removes every workspace and button from the taskbar.
+    fn clear(&mut self) {
+        for workspace in std::mem::take(&mut self.workspaces).into_values() {
+            self.container.remove(&workspace.event_box);
+            if let Some(separator) = &workspace.separator {
+                self.container.remove(separator);
+            }
+        }
+        for button in std::mem::take(&mut self.flat).into_values() {
+            self.container.remove(button.widget());
+        }
+        for header in std::mem::take(&mut self.output_headers).into_values() {
+            self.container.remove(&header);
+        }
+    }
+
+    /// Updates the taskbar from a window snapshot.
+    async fn process_snapshot(&mut self, snapshot: Snapshot, filter: Arc<Mutex<output::Filter>>) {
+        if self.state.config().layout().workspaces() {
+            self.process_workspace_update(&snapshot.workspaces, filter.clone())
+                .await;
+            self.process_window_snapshot(snapshot, filter).await;
+            self.update_urgency();
+        } else {
+            self.process_flat_snapshot(snapshot, filter);
+        }
+        self.container.show_all();
+        self.apply_overflow();
+    }
+
+    /// Works out which output the taskbar is on, and builds the filter for the outputs whose
+    /// windows are shown.
+    #[tracing::instrument(level = "DEBUG", skip(self))]
+    async fn build_output_filter(&mut self) -> output::Filter {
+        // The taskbar's own output is needed for its overrides, even if the configuration says
+        // which outputs to show.
+        self.output = self.detect_output().await;
 
+        // The output overrides can't change which outputs are shown, so only the base
+        // configuration is consulted.
+        let configured = self.base_config.output();
+        let filter = if self.base_config.show_all_outputs() {
+            output::Filter::ShowAll
+        } else if !configured.is_empty() {
+            output::Filter::Only(configured.to_vec())
+        } else {
+            match &self.output {
+                Some(output) => output::Filter::Only(vec![output.clone()]),
+                None => output::Filter::ShowAll,
+            }
+        };
+
+        filter.excluding(self.base_config.exclude_outputs())
+    }
+
+    /// Returns the name of the Niri output the taskbar is on, if it can be worked out.
+    async fn detect_output(&self) -> Option<String> {
         // OK, so we need to figure out what output we're on. Easy, right?
         //
         // Not so fast!
@@ -172,221 +1024,343 @@ impl Instance {
         // Now, this wouldn't be so bad on its own, because we _can_ get to the `wl_output` via
         // `gdkwayland`, and version 4 of the core Wayland protocol includes the output name.
         // Unfortunately, we have no way of accessing Gdk's Wayland connection, and Wayland
-        // identifiers aren't stable across connections, so we can't just connect to Wayland
-        // ourselves and enumerate the outputs. (Trust me, I tried.)
+        // identifiers aren't stable across connections, so the outputs on a connection of our own
+        // can't be matched to Gdk's by ID.
         //
-        // So, until Waybar migrates to Gtk 4, that leaves us without a truly reliable solution.
+        // They can be matched by position, physical size, make, and model, though, which Gdk
+        // reads from the same events, so we'll try that first. It can't tell mirrored monitors
+        // apart, but unlike Niri's logical layout, it isn't thrown off by fractional scales.
         //
-        // What we'll do instead is match up what we can. Niri can tell us everything we want to
-        // know about the output, and Gdk 3 does include things like the output geometry, make, and
-        // model. So we'll match on those and hope for the best.
-        let niri = *self.state.niri();
-        let outputs = match gio::spawn_blocking(move || niri.outputs()).await {
-            Ok(Ok(outputs)) => outputs,
-            Ok(Err(e)) => {
+        // If that doesn't work out, we'll match up what we can. Niri can tell us everything we
+        // want to know about the output, and Gdk 3 does include things like the output geometry,
+        // make, and model. So we'll match on those and hope for the best.
+        let outputs = match self.state.niri().run(Niri::outputs).await {
+            Ok(outputs) => outputs,
+            Err(e) => {
                 tracing::warn!(%e, "cannot get Niri outputs");
-                return output::Filter::ShowAll;
-            }
-            Err(_) => {
-                tracing::error!("error received from gio while waiting for task");
-                return output::Filter::ShowAll;
+                return None;
             }
         };
 
         // If there's only one output, then none of this matching stuff matters anyway.
         if outputs.len() == 1 {
-            return output::Filter::ShowAll;
+            return outputs.into_keys().next();
         }
 
         let Some(window) = self.container.window() else {
             tracing::warn!("cannot get Gdk window for container");
-            return output::Filter::ShowAll;
+            return None;
         };
 
         let display = window.display();
         let Some(monitor) = display.monitor_at_window(&window) else {
             tracing::warn!(display = ?window.display(), geometry = ?window.geometry(), "cannot get monitor for window");
-            return output::Filter::ShowAll;
+            return None;
         };
 
+        if let Some(name) = output::identify(&monitor).await {
+            if outputs.contains_key(&name) {
+                return Some(name);
+            }
+            tracing::warn!(name, "Wayland output is unknown to Niri");
+        }
+
         for (name, output) in outputs.into_iter() {
             let matches = output::Matcher::new(&monitor, &output);
             if matches == Matcher::all() {
-                return output::Filter::Only(name);
+                return Some(name);
             }
         }
 
         tracing::warn!(?monitor, "no Niri output matched the Gdk monitor");
-        output::Filter::ShowAll
+        None
     }
 
     #[tracing::instrument(level = "TRACE", skip(self))]
     async fn process_notification(&mut self, notification: Box<EnrichedNotification>) {
+        let windows = self.attribute_notification(&notification).await;
+
+        // The windows are remembered so that they can be cleared if the notification is closed.
+        // A notification that replaces an earlier one has the same ID, and may have been
+        // attributed to different windows, so they're all kept.
+        if let Some(id) = notification.id()
+            && !windows.is_empty()
+        {
+            let known = self.notification_windows.entry(id).or_default();
+            known.extend(windows);
+            known.sort_unstable();
+            known.dedup();
+        }
+    }
+
+    /// Marks the windows the notification came from as urgent, returning their IDs.
+    ///
+    /// The configured matching strategies are tried in order, stopping at the first one that
+    /// matches any windows.
+    async fn attribute_notification(&self, notification: &EnrichedNotification) -> Vec<u64> {
         // We'll try to set the urgent class on the relevant window if we can
         // figure out which toplevel is associated with the notification.
         //
         // Obviously, for that, we need toplevels.
         let Some(toplevels) = &self.last_snapshot else {
-            return;
+            return Vec::new();
         };
 
-        if let Some(mut pid) = notification.pid() {
-            tracing::trace!(
-                pid,
-                "got notification with PID; trying to match it to a toplevel"
-            );
+        let config = self.state.config();
+        let urgency = notification.notification().urgency();
+        if urgency < config.notifications_min_urgency() {
+            tracing::trace!(?urgency, "notification is below the minimum urgency");
+            return Vec::new();
+        }
 
-            // If we have the sender PID — either from the notification itself,
-            // or D-Bus — then the heuristic we'll use is to walk up from the
-            // sender PID and see if any of the parents are toplevels.
-            //
-            // The easiest way to do that is with a map, which we can build from
-            // the toplevels.
-            let pids = PidWindowMap::new(toplevels.windows.iter());
-
-            // We'll track if we found anything, since we might fall back to
-            // some fuzzy matching.
-            let mut found = false;
-
-            loop {
-                if let Some(window) = pids.get(pid) {
-                    // If the window is already focused, there isn't really much
-                    // to do.
-                    if !window.is_focused {
-                        if let Some(button) = self
-                            .workspaces
-                            .values_mut()
-                            .find_map(|workspace| workspace.buttons.get(&window.id))
-                        {
-                            tracing::trace!(
-                                ?button,
-                                ?window,
-                                pid,
-                                "found matching window; setting urgent"
-                            );
-                            button.set_urgent();
-                            found = true;
-                        }
-                    }
-                }
+        // Apps can opt out of urgency entirely, which we'll check against the app ID of each
+        // window we match, as well as the desktop entry, since that's all we know about
+        // notifications that don't match a window at all.
+        //
+        // XXX: do we still need the app map with fuzzy matching?
+        let desktop_entry = notification
+            .notification()
+            .hints
+            .desktop_entry
+            .as_deref()
+            .map(|desktop_entry| {
+                config
+                    .notifications_app_map(desktop_entry)
+                    .unwrap_or(desktop_entry)
+            });
+        if let Some(mapped) = desktop_entry
+            && !config.notifications_enabled_for(mapped)
+        {
+            tracing::trace!(mapped, "notifications are disabled for app");
+            return Vec::new();
+        }
+        let enabled_for = |window: &niri_ipc::Window| {
+            window
+                .app_id
+                .as_deref()
+                .is_none_or(|app_id| config.notifications_enabled_for(app_id))
+        };
 
-                match Process::new(pid).await {
-                    Ok(Process { ppid }) => {
-                        if let Some(ppid) = ppid {
-                            // Keep walking up.
-                            pid = ppid;
-                        } else {
-                            // There are no more parents.
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        // On error, we'll log but do nothing else: this
-                        // shouldn't be fatal for the bar, since it's possible
-                        // the process has simply already exited.
-                        tracing::info!(pid, %e, "error walking up process tree");
-                        break;
-                    }
-                }
-            }
+        let app_name = notification
+            .notification()
+            .app_name
+            .as_deref()
+            .filter(|app_name| !app_name.is_empty());
 
-            // If we marked one or more toplevels as urgent, then we're done.
-            if found {
-                return;
+        for strategy in config.notifications_strategies() {
+            let windows = match strategy {
+                MatchStrategy::Pid => match notification.pid() {
+                    Some(pid) => match_pid(toplevels, pid, self.state.process_cache()).await,
+                    None => Vec::new(),
+                },
+                MatchStrategy::Flatpak => match notification.pid() {
+                    Some(pid) => match_flatpak(toplevels, pid).await,
+                    None => Vec::new(),
+                },
+                MatchStrategy::DesktopEntry => desktop_entry
+                    .map(|desktop_entry| match_desktop_entry(toplevels, desktop_entry))
+                    .unwrap_or_default(),
+                MatchStrategy::Fuzzy => desktop_entry
+                    .map(|desktop_entry| match_fuzzy(toplevels, desktop_entry))
+                    .unwrap_or_default(),
+                MatchStrategy::AppName => app_name
+                    .map(|app_name| match_app_name(toplevels, app_name, self.state.desktop_cache()))
+                    .unwrap_or_default(),
+            };
+
+            let attributed: Vec<u64> = windows
+                .into_iter()
+                .filter(|window| enabled_for(window))
+                .filter_map(|window| {
+                    let button = self.button_for_window(window.id)?;
+                    tracing::trace!(?strategy, ?button, ?window, "found matching window");
+                    button.notify(notification, self.do_not_disturb);
+                    Some(window.id)
+                })
+                .collect();
+            if !attributed.is_empty() {
+                return attributed;
             }
         }
 
-        tracing::trace!("no PID in notification, or no match found");
+        tracing::trace!("no matching window found for notification");
+        Vec::new()
+    }
 
-        // Otherwise, we'll fall back to the desktop entry if we got one, and
-        // see what we can find.
-        //
-        // There are a bunch of things that can get in the way here.
-        // Applications don't necessarily know the application ID they're
-        // registered under on the system: Flatpaks, for instance, have no idea
-        // what the Flatpak actually called them when installed. So we'll do our
-        // best and make some educated guesses, but that's really what it is.
-        if !self.state.config().notifications_use_desktop_entry() {
-            tracing::trace!("use of desktop entries is disabled; no match found");
-            return;
-        }
-        let Some(desktop_entry) = &notification.notification().hints.desktop_entry else {
-            tracing::trace!("no desktop entry found in notification; nothing more to be done");
+    /// Marks the windows of a tray item that needs attention as urgent, or clears them once it no
+    /// longer does.
+    ///
+    /// Tray attention is treated like a notification of normal urgency, so do not disturb, the
+    /// minimum urgency, and apps with notifications disabled all apply to it too.
+    #[tracing::instrument(level = "TRACE", skip(self))]
+    async fn process_tray_attention(&self, attention: tray::Attention) {
+        let Some(toplevels) = &self.last_snapshot else {
             return;
         };
 
-        // So we only have to walk the window list once, we'll keep track of the
-        // fuzzy matches we find, even if we don't use them.
-        let use_fuzzy = self.state.config().notifications_use_fuzzy_matching();
-        let mut fuzzy = Vec::new();
+        // As with notifications, the process that owns the item is the best guide, but tray items
+        // are often owned by a helper process, so we'll fall back to the item's ID, which is
+        // usually the app's name.
+        let mut windows = match attention.pid() {
+            Some(pid) => {
+                PidWindowMap::new(toplevels.windows.iter())
+                    .get_with_ancestors(self.state.process_cache(), pid)
+                    .await
+            }
+            None => Vec::new(),
+        };
+        if windows.is_empty()
+            && let Some(id) = attention.id()
+        {
+            windows = toplevels
+                .windows
+                .iter()
+                .filter(|window| {
+                    window
+                        .app_id
+                        .as_deref()
+                        .is_some_and(|app_id| launcher::matches(id, app_id))
+                })
+                .collect();
+        }
+
+        let config = self.state.config();
+        let needs_attention = attention.needs_attention();
+        if needs_attention && Urgency::Normal < config.notifications_min_urgency() {
+            tracing::trace!("tray attention is below the minimum urgency");
+            return;
+        }
 
-        // XXX: do we still need this with fuzzy matching?
-        let mapped = self
-            .state
-            .config()
-            .notifications_app_map(desktop_entry)
-            .unwrap_or(desktop_entry);
-        let mapped_lower = mapped.to_lowercase();
-        let mapped_last_lower = mapped
-            .split('.')
-            .next_back()
-            .unwrap_or_default()
-            .to_lowercase();
-
-        let mut found = false;
-        for window in toplevels.windows.iter() {
-            let Some(app_id) = window.app_id.as_deref() else {
+        for window in windows {
+            if needs_attention && window.is_focused {
                 continue;
-            };
+            }
+            if let Some(app_id) = &window.app_id
+                && !config.notifications_enabled_for(app_id)
+            {
+                continue;
+            }
+            if let Some(button) = self.button_for_window(window.id) {
+                tracing::trace!(
+                    ?button,
+                    ?window,
+                    needs_attention,
+                    "tray item status changed"
+                );
+                button.set_tray_attention(needs_attention, self.do_not_disturb);
+            }
+        }
+    }
 
-            if app_id == mapped {
-                if let Some(button) = self
-                    .workspaces
-                    .values()
-                    .find_map(|workspace| workspace.buttons.get(&window.id))
-                {
-                    tracing::trace!(app_id, ?button, ?window, "toplevel match found via app ID");
-                    button.set_urgent();
-                    found = true;
-                }
-            } else if use_fuzzy {
-                // See if we have a fuzzy match, which we'll basically specify
-                // as "does the app ID match case insensitively, or does the
-                // last component of the app ID match the last component of the
-                // desktop entry?".
-                if app_id.to_lowercase() == mapped_lower {
-                    tracing::trace!(
-                        app_id,
-                        ?window,
-                        "toplevel match found via case-transformed app ID"
-                    );
-                    fuzzy.push(window.id);
-                } else if app_id.contains('.') {
-                    tracing::trace!(
-                        app_id,
-                        ?window,
-                        "toplevel match found via last element of app ID"
-                    );
-                    if let Some(last) = app_id.split('.').next_back() {
-                        if last.to_lowercase() == mapped_last_lower {
-                            fuzzy.push(window.id);
-                        }
-                    }
-                }
+    #[tracing::instrument(level = "DEBUG", skip(self))]
+    fn process_launcher_update(&mut self, update: launcher::Update) {
+        let desktop_id = update.desktop_id().to_string();
+        let entry = self.launcher_entries.entry(desktop_id.clone()).or_default();
+        let was_urgent = entry.urgent();
+        entry.apply(&update);
+        let urgent = entry.urgent() && !was_urgent;
+
+        for button in self.buttons().filter(|button| {
+            button
+                .app_id()
+                .is_some_and(|app_id| launcher::matches(&desktop_id, app_id))
+        }) {
+            button.set_launcher_entry(self.launcher_entries.get(&desktop_id));
+            if urgent {
+                button.set_urgent();
             }
         }
+    }
 
-        if !found {
-            for id in fuzzy.into_iter() {
-                if let Some(button) = self
-                    .workspaces
-                    .values()
-                    .find_map(|workspace| workspace.buttons.get(&id))
-                {
-                    button.set_urgent();
+    /// Marks the workspaces containing urgent windows as urgent themselves.
+    fn update_urgency(&self) {
+        for workspace in self.workspaces.values() {
+            workspace.set_urgent(workspace.buttons.values().any(Button::is_urgent));
+        }
+    }
+
+    /// Hides the buttons beyond the maximum when the overflow mode is chip, and shows the chip in
+    /// their place.
+    ///
+    /// This has to happen after the container is shown, since showing it would show every button.
+    fn apply_overflow(&mut self) {
+        let config = self.state.config();
+        let overflow = config.overflow(self.state.orientation());
+        if overflow.mode() != OverflowMode::Chip {
+            // A reload or override can switch away from chip mode.
+            if let Some(chip) = self.overflow_chip.take() {
+                self.container.remove(&chip.button);
+            }
+            return;
+        }
+
+        let chip = self.overflow_chip.get_or_insert_with(|| {
+            let chip = OverflowChip::new(&self.state);
+            self.container.add(&chip.button);
+            chip
+        });
+
+        // Buttons are counted in the order they're displayed, which is the order of the children
+        // within each workspace container, or within the taskbar itself in the other layouts.
+        let sections: Vec<(&gtk::Box, Vec<&Button>)> = if self.state.config().layout().workspaces()
+        {
+            ordered_workspaces(&self.workspaces)
+                .map(|workspace| (&workspace.container, workspace.buttons.values().collect()))
+                .collect()
+        } else {
+            vec![(&self.container, self.flat.values().collect())]
+        };
+
+        let mut shown = 0;
+        let mut hidden = Vec::new();
+        for (container, buttons) in sections {
+            for child in container.children() {
+                let Some(button) = buttons
+                    .iter()
+                    .find(|button| button.widget().upcast_ref::<gtk::Widget>() == &child)
+                else {
+                    continue;
+                };
+
+                if shown < overflow.max_buttons() {
+                    shown += 1;
+                } else {
+                    child.hide();
+                    hidden.extend(button.windows().members());
                 }
             }
         }
+
+        chip.button.set_label(&format!("+{}", hidden.len()));
+        chip.button.set_visible(!hidden.is_empty());
+        chip.hidden.replace(hidden);
+        self.container.reorder_child(&chip.button, -1);
+    }
+
+    /// Shows the launcher entry state on every button, which is needed for new buttons.
+    fn apply_launcher_entries(&self) {
+        for button in self.buttons() {
+            button.set_launcher_entry(button.app_id().and_then(|app_id| {
+                self.launcher_entries
+                    .iter()
+                    .find(|(desktop_id, _)| launcher::matches(desktop_id, app_id))
+                    .map(|(_, entry)| entry)
+            }));
+        }
+    }
+
+    /// Returns the button representing the given window, if any.
+    fn button_for_window(&self, window_id: u64) -> Option<&Button> {
+        self.buttons()
+            .find(|button| button.windows().contains(window_id))
+    }
+
+    /// Returns every button, in no particular order.
+    fn buttons(&self) -> impl Iterator<Item = &Button> {
+        self.workspaces
+            .values()
+            .flat_map(|workspace| workspace.buttons.values())
+            .chain(self.flat.values())
     }
 
     #[tracing::instrument(level = "DEBUG", skip(self))]
@@ -399,6 +1373,8 @@ impl Instance {
         let workspaces: Vec<_> = workspaces
             .iter()
             .filter(|wsp| filter_value.should_show(&wsp.output.clone().unwrap_or_default()))
+            .filter(|wsp| !self.state.config().workspace_hidden(wsp))
+            .filter(|wsp| !self.state.config().active_workspace_only() || wsp.is_active)
             .collect();
         drop(filter_value);
 
@@ -408,19 +1384,91 @@ impl Instance {
         for workspace in workspaces {
             known_workspace.insert(workspace.id);
             let entry = self.workspaces.entry(workspace.id).or_insert_with(|| {
+                // The separator goes before the workspace container, and is shown or hidden once
+                // we know which workspaces have anything in them.
+                let separator = self.state.config().workspace_separators().then(|| {
+                    let separator = gtk::Separator::new(match self.state.orientation() {
+                        config::Orientation::Vertical => Orientation::Horizontal,
+                        config::Orientation::Horizontal => Orientation::Vertical,
+                    });
+                    separator
+                        .style_context()
+                        .add_class("niri-taskbar-separator");
+                    separator.set_no_show_all(true);
+                    self.container.add(&separator);
+                    separator
+                });
+
                 let container = gtk::Box::new(
-                    match self.state.config().orientation() {
+                    match self.state.orientation() {
                         config::Orientation::Vertical => Orientation::Vertical,
                         config::Orientation::Horizontal => Orientation::Horizontal,
                     },
-                    0,
+                    self.state.config().spacing().buttons(),
                 );
-                self.container.add(&container);
+
+                // Like the taskbar itself, the container is wrapped in an event box to handle
+                // scrolling over it.
+                let event_box = gtk::EventBox::new();
+                event_box.set_visible_window(false);
+                event_box.add(&container);
+                connect_window_scroll(&event_box, &self.state, workspace.id);
+                self.container.add(&event_box);
+
+                // Dropping a window button onto another workspace moves the window there.
+                let workspace_id = workspace.id;
+                dnd::connect_workspace_target(&container, &self.state, workspace_id);
+
+                // The workspace icon, if one is configured, goes before the label.
+                let icon = gtk::Label::new(None);
+                icon.style_context().add_class("niri-workspace-icon");
+                icon.set_no_show_all(true);
                 let label = gtk::Label::new(None);
+                let label_content = gtk::Box::new(
+                    match self.state.orientation() {
+                        config::Orientation::Vertical => Orientation::Vertical,
+                        config::Orientation::Horizontal => Orientation::Horizontal,
+                    },
+                    self.state.config().spacing().button_content(),
+                );
+                label_content.add(&icon);
+                label_content.add(&label);
+
+                // Labels don't receive events of their own, so the label is wrapped in an event box
+                // to make it clickable.
+                let label_box = gtk::EventBox::new();
+                label_box.style_context().add_class("niri-workspace-label");
+                label_box.add(&label_content);
+                dnd::connect_workspace_target(&label_box, &self.state, workspace_id);
+                label_box.connect_button_press_event({
+                    let state = self.state.clone();
+                    move |label_box, event| {
+                        match event.button() {
+                            1 => {
+                                state.niri().spawn("error focusing workspace", move |niri| {
+                                    niri.focus_workspace(workspace_id)
+                                });
+                            }
+                            3 => menu::popup_workspace_menu(
+                                &state,
+                                workspace_id,
+                                label_box,
+                                Some(event),
+                            ),
+                            _ => return Propagation::Proceed,
+                        }
+                        Propagation::Stop
+                    }
+                });
+
                 WorkspaceDisplay {
                     state: workspace.clone(),
+                    event_box,
                     container,
                     label,
+                    icon,
+                    label_box,
+                    separator,
                     buttons: BTreeMap::new(),
                 }
             });
@@ -429,36 +1477,72 @@ impl Instance {
         }
 
         self.workspaces.retain(|workspace_id, workspace| {
-            if !known_workspace.contains(&(*workspace_id as u64)) {
-                self.container.remove(&workspace.container);
+            if !known_workspace.contains(workspace_id) {
+                self.container.remove(&workspace.event_box);
+                if let Some(separator) = &workspace.separator {
+                    self.container.remove(separator);
+                }
                 return false;
             }
             true
         });
 
-        //reorder in parent
-        self.workspaces
-            .iter()
-            .sorted_unstable_by(|(_, wsp1), (_, wsp2)| wsp1.state.idx.cmp(&wsp2.state.idx))
-            .for_each(|(_, workspace)| {
-                let context = workspace.container.style_context();
-                if workspace.state.is_focused {
-                    context.remove_class("niri-workspace");
-                    context.add_class("niri-workspace-focused");
-
-                    workspace
-                        .label
-                        .set_text(&self.state.config().workspace_format_focused());
-                } else {
-                    context.add_class("niri-workspace");
-                    context.remove_class("niri-workspace-focused");
+        // Output headers are only shown when there's more than one output to tell apart.
+        let config = self.state.config();
+        let outputs: BTreeSet<String> = if config.output_headers()
+            && (config.show_all_outputs() || config.output().len() > 1)
+        {
+            self.workspaces
+                .values()
+                .filter_map(|workspace| workspace.state.output.clone())
+                .collect()
+        } else {
+            BTreeSet::new()
+        };
+        self.output_headers.retain(|output, header| {
+            if !outputs.contains(output) {
+                self.container.remove(header);
+                return false;
+            }
+            true
+        });
+        for output in outputs {
+            self.output_headers
+                .entry(output)
+                .or_insert_with_key(|output| {
+                    let header = gtk::Label::new(Some(output));
+                    header.style_context().add_class("niri-taskbar-output");
+                    self.container.add(&header);
+                    header
+                });
+        }
 
-                    workspace
-                        .label
-                        .set_text(&self.state.config().workspace_format());
+        //reorder in parent
+        let mut last_output = None;
+        for workspace in ordered_workspaces(&self.workspaces) {
+            if workspace.state.output != last_output {
+                last_output = workspace.state.output.clone();
+                if let Some(header) = last_output
+                    .as_ref()
+                    .and_then(|output| self.output_headers.get(output))
+                {
+                    self.container.reorder_child(header, -1);
                 }
-                self.container.reorder_child(&workspace.container, -1);
-            });
+            }
+
+            let context = workspace.container.style_context();
+            if workspace.state.is_focused {
+                context.remove_class("niri-workspace");
+                context.add_class("niri-workspace-focused");
+            } else {
+                context.add_class("niri-workspace");
+                context.remove_class("niri-workspace-focused");
+            }
+            if let Some(separator) = &workspace.separator {
+                self.container.reorder_child(separator, -1);
+            }
+            self.container.reorder_child(&workspace.event_box, -1);
+        }
     }
 
     #[tracing::instrument(level = "DEBUG", skip(self))]
@@ -470,78 +1554,346 @@ impl Instance {
         // Get the filter for showing windows
         let filter_value = filter.lock().expect("output filter lock").clone();
 
+        // Windows on hidden workspaces are hidden, too.
+        let hidden_workspaces: HashSet<u64> = snapshot
+            .workspaces
+            .iter()
+            .filter(|wsp| self.state.config().workspace_hidden(wsp))
+            .map(|wsp| wsp.id)
+            .collect();
+
         // Filter windows based on output
         let filtered_windows: Vec<_> = snapshot
             .windows
             .iter()
             .filter(|window| filter_value.should_show(window.output().unwrap_or_default()))
+            .filter(|window| {
+                window
+                    .workspace_id
+                    .is_none_or(|id| !hidden_workspaces.contains(&id))
+            })
+            .filter(|window| !self.state.config().window_ignored(window))
             .collect();
 
+        // Group the windows by the button that will represent them. Normally this is one button
+        // per window, but when grouping by app, each app gets one button per workspace, which is
+        // keyed by the first window it was created for.
+        let group_by_app = self.state.config().group_by_app();
+        let mut groups: BTreeMap<(u64, u64), Vec<&niri::Window>> = BTreeMap::new();
+        for window in filtered_windows {
+            let workspace_id = window.workspace_id.unwrap_or(0);
+            let Some(wsp) = self.workspaces.get(&workspace_id) else {
+                continue;
+            };
+
+            let key = match window.app_id.as_deref() {
+                Some(app_id) if group_by_app => wsp
+                    .buttons
+                    .iter()
+                    .find(|(_, button)| button.app_id() == Some(app_id))
+                    .map(|(key, _)| *key)
+                    .or_else(|| {
+                        groups.iter().find_map(|((ws, key), windows)| {
+                            (*ws == workspace_id && windows[0].app_id.as_deref() == Some(app_id))
+                                .then_some(*key)
+                        })
+                    })
+                    .unwrap_or(window.id),
+                _ => window.id,
+            };
+            groups.entry((workspace_id, key)).or_default().push(window);
+        }
+
         // Add new windows
         let mut known_windows = BTreeSet::new();
-        let mut focused_workspace_id = None;
-        for window in filtered_windows {
-            known_windows.insert((window.workspace_id.unwrap_or(0), window.id));
-            self.workspaces
-                .entry(window.workspace_id.unwrap_or(0))
-                .and_modify(|wsp| {
-                    let button = wsp.buttons.entry(window.id).or_insert_with(|| {
-                        let button = Button::new(&self.state, &window);
-                        wsp.container.add(button.widget());
-                        button
-                    });
-                    // Update the window properties.
-                    button.set_focus(window.is_focused);
-                    button.set_title(window.title.as_deref());
-                    button.set_layout(window.layout.clone());
-                    if window.is_focused {
-                        focused_workspace_id = window.workspace_id;
-                    }
-                });
+        for ((workspace_id, key), mut windows) in groups {
+            let Some(wsp) = self.workspaces.get_mut(&workspace_id) else {
+                continue;
+            };
+            windows.sort_by_key(|window| {
+                window
+                    .layout
+                    .pos_in_scrolling_layout
+                    .unwrap_or((usize::MAX, usize::MAX))
+            });
+
+            known_windows.insert((workspace_id, key));
+            let button = wsp.buttons.entry(key).or_insert_with(|| {
+                let button = Button::new(&self.state, windows[0]);
+                Self::restore(&mut self.carried, &button, &windows);
+                wsp.container.add(button.widget());
+                button
+            });
+
+            // Update the window properties.
+            button.set_windows(&windows);
+            let focused = windows.iter().find(|window| window.is_focused);
+            button.set_focus(focused.is_some());
+            if self.state.niri().capabilities().window_urgency() {
+                button.set_compositor_urgent(windows.iter().any(|window| window.is_urgent));
+            }
+            let current = focused.copied().unwrap_or(windows[0]);
+            button.set_workspaces(vec![workspace_name(&wsp.state)]);
+            button.set_title(current, Some(&wsp.state));
+            button.set_layout(windows[0].layout.clone());
         }
 
+        // Track the display order of the windows so that buttons can cycle through them.
+        let mut displayed = Vec::new();
+
         for (workspace_id, workspace) in &mut self.workspaces {
             // Remove unknown windows
-            workspace.buttons.retain(|window_id, button| {
-                if !known_windows.contains(&(*workspace_id, *window_id)) {
+            workspace.buttons.retain(|key, button| {
+                if !known_windows.contains(&(*workspace_id, *key)) {
                     workspace.container.remove(button.widget());
                     return false;
                 }
                 true
             });
 
-            // Order windows based on layout
-            workspace
+            // Order windows based on the configured sort, which defaults to the layout
+            let sort = sort(&self.state);
+            let buttons: Vec<_> = workspace
                 .buttons
-                .iter()
-                .sorted_unstable_by(|(_, button1), (_, button2)| {
-                    match (button1.pos(), button2.pos()) {
-                        (Some((row1, col1)), Some((row2, col2))) => match row1.cmp(row2) {
-                            Ordering::Equal => col1.cmp(col2),
-                            ord => ord,
-                        },
-                        (Some(_), None) => Ordering::Less,
-                        (None, Some(_)) => Ordering::Greater,
-                        (None, None) => Ordering::Equal,
-                    }
-                })
-                .for_each(|(_, button)| {
+                .values()
+                .sorted_by(|button1, button2| compare_buttons(sort, button1, button2))
+                .collect();
+
+            // Moving a button interrupts any click or drag in progress on it, so buttons are only
+            // reordered when the order has actually changed.
+            let current: Vec<_> = workspace
+                .container
+                .children()
+                .into_iter()
+                .filter(|child| child.is::<gtk::Button>())
+                .collect();
+            if current.len() != buttons.len()
+                || current
+                    .iter()
+                    .zip(buttons.iter())
+                    .any(|(child, button)| child != button.widget())
+            {
+                for button in buttons.iter() {
                     workspace.container.reorder_child(button.widget(), -1);
-                });
+                }
+            }
+
+            let ordered: Vec<_> = buttons
+                .into_iter()
+                .flat_map(|button| {
+                    button
+                        .windows()
+                        .members()
+                        .into_iter()
+                        .map(|member| DisplayedWindow {
+                            id: member.id,
+                            app_id: button.app_id().map(String::from),
+                            workspace_id: Some(*workspace_id),
+                            pos: member.pos,
+                            active: workspace.state.active_window_id == Some(member.id),
+                        })
+                })
+                .collect();
+            displayed.push((
+                (workspace.state.output.clone(), workspace.state.idx),
+                ordered,
+            ));
 
-            // hide empty workspaces, unless focused
-            if !workspace.state.is_focused && workspace.buttons.is_empty() {
-                workspace.container.remove(&workspace.label);
+            workspace.update_label(&self.state.config());
+
+            // hide empty workspaces, unless focused or configured to show them; labels are never
+            // shown when only the active workspace is displayed
+            if self.state.config().active_workspace_only()
+                || (!self.state.config().show_empty_workspaces()
+                    && !workspace.state.is_focused
+                    && workspace.buttons.is_empty())
+            {
+                workspace.container.remove(&workspace.label_box);
             } else {
-                if workspace.label.parent().is_none() {
-                    workspace.container.add(&workspace.label);
+                if workspace.label_box.parent().is_none() {
+                    workspace.container.add(&workspace.label_box);
                 }
             }
         }
 
+        self.state.set_window_order(WindowOrder::new(
+            displayed
+                .into_iter()
+                .sorted_by(|(a, _), (b, _)| a.cmp(b))
+                .flat_map(|(_, windows)| windows)
+                .collect(),
+        ));
+
+        // Thumbnails are tracked by window rather than by button, so they have to be cleaned up
+        // separately.
+        thumbnail::retain(|id| snapshot.windows.iter().any(|window| window.id == id));
+
+        self.apply_launcher_entries();
+        self.update_separators();
+        self.last_snapshot = Some(snapshot);
+    }
+
+    /// Updates the buttons from a window snapshot in the layouts without workspace containers,
+    /// where the buttons are direct children of the taskbar.
+    ///
+    /// In the apps layout, each app has a single button regardless of which workspaces its windows
+    /// are on. In the flat layout, buttons are the same as in the workspaces layout.
+    #[tracing::instrument(level = "DEBUG", skip(self))]
+    fn process_flat_snapshot(&mut self, snapshot: Snapshot, filter: Arc<Mutex<output::Filter>>) {
+        let filter_value = filter.lock().expect("output filter lock").clone();
+        let config = self.state.config();
+
+        let workspaces: HashMap<u64, &Workspace> = snapshot
+            .workspaces
+            .iter()
+            .filter(|wsp| !config.workspace_hidden(wsp))
+            .filter(|wsp| !config.active_workspace_only() || wsp.is_active)
+            .map(|wsp| (wsp.id, wsp))
+            .collect();
+
+        let layout = config.layout();
+        let group_by_app = config.group_by_app();
+        let mut groups: BTreeMap<FlatKey, Vec<(&Workspace, &niri::Window)>> = BTreeMap::new();
+        for window in snapshot
+            .windows
+            .iter()
+            .filter(|window| filter_value.should_show(window.output().unwrap_or_default()))
+            .filter(|window| !config.window_ignored(window))
+        {
+            let Some(workspace) = window.workspace_id.and_then(|id| workspaces.get(&id)) else {
+                continue;
+            };
+
+            let key = match &window.app_id {
+                Some(app_id) if layout == Layout::Apps => FlatKey::App(app_id.clone()),
+                Some(app_id) if group_by_app => FlatKey::WorkspaceApp(workspace.id, app_id.clone()),
+                _ => FlatKey::Window(window.id),
+            };
+            groups.entry(key).or_default().push((workspace, window));
+        }
+
+        // Windows are ordered by workspace and then by their position on the workspace. In the
+        // apps layout, buttons start out ordered by app ID; otherwise, they're ordered by their
+        // first window. The configured sort is applied once the buttons are up to date.
+        let display_order = |(workspace, window): &(&Workspace, &niri::Window)| {
+            (
+                workspace.output.clone(),
+                workspace.idx,
+                window
+                    .layout
+                    .pos_in_scrolling_layout
+                    .unwrap_or((usize::MAX, usize::MAX)),
+            )
+        };
+        let known: BTreeSet<_> = groups.keys().cloned().collect();
+        let mut groups: Vec<_> = groups
+            .into_iter()
+            .map(|(key, mut windows)| {
+                windows.sort_by_key(display_order);
+                (key, windows)
+            })
+            .collect();
+        if layout != Layout::Apps {
+            groups.sort_by_key(|(_, windows)| display_order(&windows[0]));
+        }
+
+        let mut shown = Vec::new();
+        for (key, windows) in groups {
+            let button = self.flat.entry(key.clone()).or_insert_with(|| {
+                let button = Button::new(&self.state, windows[0].1);
+                let members: Vec<_> = windows.iter().map(|(_, window)| *window).collect();
+                Self::restore(&mut self.carried, &button, &members);
+                self.container.add(button.widget());
+                button
+            });
+
+            let members: Vec<_> = windows.iter().map(|(_, window)| *window).collect();
+            button.set_windows(&members);
+            let focused = windows.iter().find(|(_, window)| window.is_focused);
+            button.set_focus(focused.is_some());
+            if self.state.niri().capabilities().window_urgency() {
+                button.set_compositor_urgent(windows.iter().any(|(_, window)| window.is_urgent));
+            }
+            let (workspace, current) = focused.copied().unwrap_or(windows[0]);
+            button.set_workspaces(
+                windows
+                    .iter()
+                    .map(|(workspace, _)| workspace_name(workspace))
+                    .dedup()
+                    .collect(),
+            );
+            button.set_title(current, Some(workspace));
+            button.set_layout(current.layout.clone());
+
+            let windows: Vec<_> = windows
+                .iter()
+                .map(|(workspace, window)| DisplayedWindow {
+                    id: window.id,
+                    app_id: window.app_id.clone(),
+                    workspace_id: Some(workspace.id),
+                    pos: window.layout.pos_in_scrolling_layout,
+                    active: workspace.active_window_id == Some(window.id),
+                })
+                .collect();
+            shown.push((key, windows));
+        }
+
+        // Positions are only meaningful within a workspace, so the position sort keeps the display
+        // order above. The other sorts are stable, so ties keep that order too.
+        let sort = sort(&self.state);
+        if sort != Sort::Position {
+            shown.sort_by(|(key1, _), (key2, _)| {
+                compare_by_sort(sort, &self.flat[key1], &self.flat[key2])
+            });
+        }
+        let order: Vec<gtk::Widget> = shown
+            .iter()
+            .map(|(key, _)| self.flat[key].widget().clone().upcast())
+            .collect();
+        let displayed: Vec<_> = shown.into_iter().flat_map(|(_, windows)| windows).collect();
+
+        self.flat.retain(|key, button| {
+            if !known.contains(key) {
+                self.container.remove(button.widget());
+                return false;
+            }
+            true
+        });
+
+        // As in the workspaces layout, buttons are only reordered when the order has changed, so
+        // that clicks and drags aren't interrupted. The chip, separators and headers don't count.
+        let current: Vec<_> = self
+            .container
+            .children()
+            .into_iter()
+            .filter(|child| order.contains(child))
+            .collect();
+        if current != order {
+            for widget in &order {
+                self.container.reorder_child(widget, -1);
+            }
+        }
+
+        self.state.set_window_order(WindowOrder::new(displayed));
+        thumbnail::retain(|id| snapshot.windows.iter().any(|window| window.id == id));
+        self.apply_launcher_entries();
         self.last_snapshot = Some(snapshot);
     }
 
+    /// Shows the separators between workspaces that have something in them.
+    fn update_separators(&self) {
+        let mut first = true;
+        for workspace in ordered_workspaces(&self.workspaces) {
+            let shown = !workspace.container.children().is_empty();
+            if let Some(separator) = &workspace.separator {
+                separator.set_visible(shown && !first);
+            }
+            if shown {
+                first = false;
+            }
+        }
+    }
+
     // Auto-move floating windows to focused workspace
     fn update_window_placement(&self, windows: &Snapshot) {
         // Get focused wordspace for each output
@@ -565,15 +1917,150 @@ impl Instance {
                 && let Some(focused_ws_id) = focused_ws_id.get(window_output)
                 && window.workspace_id != Some(*focused_ws_id)
             {
-                let _ = self
-                    .state
+                let (window_id, workspace_id) = (window.id, *focused_ws_id);
+                self.state
                     .niri()
-                    .move_window_to_workspace(window.id, *focused_ws_id);
+                    .spawn("error keeping window on active workspace", move |niri| {
+                        niri.move_window_to_workspace(window_id, workspace_id, false)
+                    });
             }
         }
     }
 }
 
+/// Matches a notification to windows by walking up the process tree from its sender.
+async fn match_pid<'a>(
+    toplevels: &'a Snapshot,
+    pid: i64,
+    cache: &process::Cache,
+) -> Vec<&'a Window> {
+    tracing::trace!(
+        pid,
+        "got notification with PID; trying to match it to a toplevel"
+    );
+
+    // If we have the sender PID — either from the notification itself,
+    // or D-Bus — then the heuristic we'll use is to walk up from the
+    // sender PID and see if any of the parents are toplevels.
+    //
+    // The easiest way to do that is with a map, which we can build from
+    // the toplevels.
+    let pids = PidWindowMap::new(toplevels.windows.iter());
+
+    // If the window is already focused, there isn't really much to do.
+    pids.get_with_ancestors(cache, pid)
+        .await
+        .into_iter()
+        .filter(|window| !window.is_focused)
+        .collect()
+}
+
+/// Matches a notification to windows whose app ID is the ID of the Flatpak app that sent it.
+///
+/// Flatpak apps send notifications through a D-Bus proxy, which isn't an ancestor of the app's
+/// windows, so the process tree is no help, but the proxy is in the app's cgroup.
+async fn match_flatpak(toplevels: &Snapshot, pid: i64) -> Vec<&Window> {
+    let app_id = match process::flatpak_app_id(pid).await {
+        Ok(Some(app_id)) => app_id,
+        Ok(None) => return Vec::new(),
+        Err(e) => {
+            tracing::info!(pid, %e, "error reading cgroup of notification sender");
+            return Vec::new();
+        }
+    };
+
+    toplevels
+        .windows
+        .iter()
+        .filter(|window| {
+            window
+                .app_id
+                .as_deref()
+                .is_some_and(|window_app_id| window_app_id.eq_ignore_ascii_case(&app_id))
+        })
+        .collect()
+}
+
+/// Matches a notification to windows whose app ID is the desktop entry it names.
+///
+/// There are a bunch of things that can get in the way here. Applications don't necessarily know
+/// the application ID they're registered under on the system: Flatpaks, for instance, have no idea
+/// what the Flatpak actually called them when installed. So we'll do our best, and the fuzzy
+/// matching below makes some educated guesses, but that's really what it is.
+fn match_desktop_entry<'a>(toplevels: &'a Snapshot, desktop_entry: &str) -> Vec<&'a Window> {
+    toplevels
+        .windows
+        .iter()
+        .filter(|window| window.app_id.as_deref() == Some(desktop_entry))
+        .collect()
+}
+
+/// Matches a notification to windows whose app ID matches the desktop entry it names case
+/// insensitively, or whose last component matches the last component of the desktop entry.
+fn match_fuzzy<'a>(toplevels: &'a Snapshot, desktop_entry: &str) -> Vec<&'a Window> {
+    let lower = desktop_entry.to_lowercase();
+    let last_lower = desktop_entry
+        .split('.')
+        .next_back()
+        .unwrap_or_default()
+        .to_lowercase();
+
+    toplevels
+        .windows
+        .iter()
+        .filter(|window| {
+            let Some(app_id) = window.app_id.as_deref() else {
+                return false;
+            };
+
+            if app_id.to_lowercase() == lower {
+                tracing::trace!(
+                    app_id,
+                    ?window,
+                    "toplevel match found via case-transformed app ID"
+                );
+                true
+            } else if app_id.contains('.')
+                && app_id
+                    .split('.')
+                    .next_back()
+                    .is_some_and(|last| last.to_lowercase() == last_lower)
+            {
+                tracing::trace!(
+                    app_id,
+                    ?window,
+                    "toplevel match found via last element of app ID"
+                );
+                true
+            } else {
+                false
+            }
+        })
+        .collect()
+}
+
+/// Matches a notification to windows by the app name it was sent with, which is compared against
+/// the app IDs of the windows and the names in their desktop entries, since many apps only fill in
+/// their name.
+fn match_app_name<'a>(
+    toplevels: &'a Snapshot,
+    app_name: &str,
+    desktop_cache: &desktop::Cache,
+) -> Vec<&'a Window> {
+    toplevels
+        .windows
+        .iter()
+        .filter(|window| {
+            window.app_id.as_deref().is_some_and(|app_id| {
+                app_id.eq_ignore_ascii_case(app_name)
+                    || desktop_cache
+                        .name(app_id)
+                        .is_some_and(|name| name.eq_ignore_ascii_case(app_name))
+            })
+        })
+        .collect()
+}
+
 /// A basic map of PIDs to windows.
 ///
 /// Windows that don't have a PID are ignored, since we can't match on them
@@ -591,4 +2078,14 @@ impl<'a> PidWindowMap<'a> {
     fn get(&self, pid: i64) -> Option<&'a Window> {
         self.0.get(&pid).copied()
     }
+
+    /// Returns the windows belonging to the given process or any of its ancestors, nearest first.
+    async fn get_with_ancestors(&self, cache: &process::Cache, pid: i64) -> Vec<&'a Window> {
+        cache
+            .ancestors(pid)
+            .await
+            .into_iter()
+            .filter_map(|pid| self.get(pid))
+            .collect()
+    }
 }
@@ -0,0 +1,289 @@
+use std::{collections::HashMap, sync::Arc};
+
+use itertools::Itertools;
+use niri_ipc::{Output, Workspace};
+use waybar_cffi::gtk::{
+    self as gtk,
+    gdk::Event,
+    glib,
+    prelude::{
+        ContainerExt, EntryExt, GtkMenuExt, GtkMenuItemExt, IsA, MenuShellExt, PopoverExt,
+        WidgetExt,
+    },
+};
+
+use crate::{error::Error, niri::Niri, state::State};
+
+/// Pops up the context menu for the given window, attached to the given widget.
+///
+/// The menu is built fresh each time it's shown, since the set of workspaces and outputs we can
+/// move the window to may have changed since the last time.
+pub fn popup_window_menu(
+    state: &State,
+    window_id: u64,
+    widget: &impl IsA<gtk::Widget>,
+    trigger: Option<&Event>,
+) {
+    // The workspace and output lists are fetched off the main thread, so the menu appears once
+    // Niri has replied.
+    let state = state.clone();
+    let widget = widget.as_ref().clone();
+    let trigger = trigger.cloned();
+    glib::spawn_future_local(async move {
+        let workspaces = state.niri().run(Niri::workspaces).await;
+        let outputs = state.niri().run(Niri::outputs).await;
+        build_window_menu(
+            &state,
+            window_id,
+            workspaces,
+            outputs,
+            &widget,
+            trigger.as_ref(),
+        );
+    });
+}
+
+fn build_window_menu(
+    state: &State,
+    window_id: u64,
+    workspaces: Result<Vec<Workspace>, Error>,
+    outputs: Result<HashMap<String, Output>, Error>,
+    widget: &gtk::Widget,
+    trigger: Option<&Event>,
+) {
+    let menu = gtk::Menu::new();
+
+    menu.append(&action_item("Close", state, move |niri| {
+        niri.close_window(window_id)
+    }));
+    menu.append(&action_item("Toggle fullscreen", state, move |niri| {
+        niri.fullscreen_window(window_id)
+    }));
+    if state.niri().capabilities().floating() {
+        menu.append(&action_item("Toggle floating", state, move |niri| {
+            niri.toggle_window_floating(window_id)
+        }));
+    }
+
+    menu.append(&gtk::SeparatorMenuItem::new());
+
+    match workspaces {
+        Ok(workspaces) => {
+            let submenu = gtk::Menu::new();
+            for workspace in workspaces
+                .into_iter()
+                .sorted_by(|a, b| a.output.cmp(&b.output).then(a.idx.cmp(&b.idx)))
+            {
+                let label = match (&workspace.name, &workspace.output) {
+                    (Some(name), Some(output)) => format!("{output}: {name}"),
+                    (None, Some(output)) => format!("{output}: {}", workspace.idx),
+                    (Some(name), None) => name.clone(),
+                    (None, None) => workspace.idx.to_string(),
+                };
+
+                let workspace_id = workspace.id;
+                submenu.append(&action_item(&label, state, move |niri| {
+                    niri.move_window_to_workspace(window_id, workspace_id, false)
+                }));
+            }
+
+            menu.append(&submenu_item("Move to workspace", &submenu));
+        }
+        Err(e) => tracing::warn!(%e, "cannot get Niri workspaces for window menu"),
+    }
+
+    match outputs {
+        Ok(outputs) if outputs.len() > 1 => {
+            let submenu = gtk::Menu::new();
+            for name in outputs.into_keys().sorted() {
+                let output = name.clone();
+                submenu.append(&action_item(&name, state, move |niri| {
+                    niri.move_window_to_monitor(window_id, &output)
+                }));
+            }
+
+            menu.append(&submenu_item("Move to output", &submenu));
+        }
+        Ok(_) => {
+            // There's nowhere to move the window to, so we won't bother showing the submenu.
+        }
+        Err(e) => tracing::warn!(%e, "cannot get Niri outputs for window menu"),
+    }
+
+    popup(menu, widget, trigger);
+}
+
+/// Pops up the context menu for the given workspace, attached to the given widget.
+///
+/// Like the window menu, this is built fresh each time, using the current workspace state.
+pub fn popup_workspace_menu(
+    state: &State,
+    workspace_id: u64,
+    widget: &impl IsA<gtk::Widget>,
+    trigger: Option<&Event>,
+) {
+    // As with the window menu, Niri is queried off the main thread.
+    let state = state.clone();
+    let widget = widget.as_ref().clone();
+    let trigger = trigger.cloned();
+    glib::spawn_future_local(async move {
+        let workspaces = state.niri().run(Niri::workspaces).await;
+        let outputs = state.niri().run(Niri::outputs).await;
+        build_workspace_menu(
+            &state,
+            workspace_id,
+            workspaces,
+            outputs,
+            &widget,
+            trigger.as_ref(),
+        );
+    });
+}
+
+fn build_workspace_menu(
+    state: &State,
+    workspace_id: u64,
+    workspaces: Result<Vec<Workspace>, Error>,
+    outputs: Result<HashMap<String, Output>, Error>,
+    widget: &gtk::Widget,
+    trigger: Option<&Event>,
+) {
+    let workspaces = match workspaces {
+        Ok(workspaces) => workspaces,
+        Err(e) => {
+            tracing::warn!(%e, "cannot get Niri workspaces for workspace menu");
+            return;
+        }
+    };
+    let Some(workspace) = workspaces
+        .iter()
+        .find(|workspace| workspace.id == workspace_id)
+    else {
+        return;
+    };
+
+    let menu = gtk::Menu::new();
+
+    let rename = gtk::MenuItem::with_label("Rename…");
+    rename.connect_activate({
+        let state = state.clone();
+        let widget = widget.clone();
+        let name = workspace.name.clone().unwrap_or_default();
+        move |_| popup_rename(&state, workspace_id, &name, &widget)
+    });
+    menu.append(&rename);
+
+    // Workspace indices are 1-based, and Niri clamps indices past the end of the output.
+    let idx = usize::from(workspace.idx);
+    let up = action_item("Move up", state, move |niri| {
+        niri.move_workspace_to_index(workspace_id, idx - 1)
+    });
+    up.set_sensitive(idx > 1);
+    menu.append(&up);
+
+    let last = workspaces
+        .iter()
+        .filter(|other| other.output == workspace.output)
+        .map(|other| usize::from(other.idx))
+        .max()
+        .unwrap_or(idx);
+    let down = action_item("Move down", state, move |niri| {
+        niri.move_workspace_to_index(workspace_id, idx + 1)
+    });
+    down.set_sensitive(idx < last);
+    menu.append(&down);
+
+    match outputs {
+        Ok(outputs) if outputs.len() > 1 => {
+            let submenu = gtk::Menu::new();
+            for name in outputs
+                .into_keys()
+                .filter(|name| Some(name) != workspace.output.as_ref())
+                .sorted()
+            {
+                let output = name.clone();
+                submenu.append(&action_item(&name, state, move |niri| {
+                    niri.move_workspace_to_monitor(workspace_id, &output)
+                }));
+            }
+
+            menu.append(&gtk::SeparatorMenuItem::new());
+            menu.append(&submenu_item("Move to output", &submenu));
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!(%e, "cannot get Niri outputs for workspace menu"),
+    }
+
+    popup(menu, widget, trigger);
+}
+
+/// Pops up an entry to rename the given workspace. Entering an empty name unsets the name.
+fn popup_rename(state: &State, workspace_id: u64, name: &str, widget: &gtk::Widget) {
+    let popover = gtk::Popover::new(Some(widget));
+    let entry = gtk::Entry::new();
+    entry.set_text(name);
+    entry.set_placeholder_text(Some("Workspace name"));
+    popover.add(&entry);
+
+    entry.connect_activate({
+        let state = state.clone();
+        let popover = popover.clone();
+        move |entry| {
+            let name = entry.text().trim().to_string();
+            state.niri().spawn("error renaming workspace", move |niri| {
+                niri.set_workspace_name(workspace_id, &name)
+            });
+            popover.popdown();
+        }
+    });
+
+    // As with the window list, the popover is detached once it's closed so that stale popovers
+    // don't accumulate on the widget.
+    popover.connect_closed(|popover| {
+        let popover = popover.clone();
+        glib::idle_add_local_once(move || popover.set_relative_to(None::<&gtk::Widget>));
+    });
+
+    popover.show_all();
+    popover.popup();
+}
+
+/// Attaches and pops up the given menu.
+fn popup(menu: gtk::Menu, widget: &impl IsA<gtk::Widget>, trigger: Option<&Event>) {
+    // Attaching the menu keeps it alive while it's shown; we then detach it once it's closed so
+    // that we don't accumulate stale menus on the widget.
+    menu.set_attach_widget(Some(widget));
+    menu.connect_deactivate(|menu| {
+        // Deactivation happens before the activate signal on the selected item is emitted, so we
+        // have to defer the detach until the main loop is idle.
+        let menu = menu.clone();
+        glib::idle_add_local_once(move || menu.detach());
+    });
+
+    menu.show_all();
+    menu.popup_at_pointer(trigger);
+}
+
+fn action_item<F>(label: &str, state: &State, f: F) -> gtk::MenuItem
+where
+    F: Fn(&Niri) -> Result<(), Error> + Send + Sync + 'static,
+{
+    let item = gtk::MenuItem::with_label(label);
+    let state = state.clone();
+    let f = Arc::new(f);
+
+    item.connect_activate(move |_| {
+        let f = f.clone();
+        state
+            .niri()
+            .spawn("error handling menu action", move |niri| f(niri));
+    });
+
+    item
+}
+
+fn submenu_item(label: &str, submenu: &gtk::Menu) -> gtk::MenuItem {
+    let item = gtk::MenuItem::with_label(label);
+    item.set_submenu(Some(submenu));
+    item
+}
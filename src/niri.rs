@@ -1,74 +1,360 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use niri_ipc::{Action, Output, Reply, Request, socket::Socket};
+pub use capabilities::Capabilities;
+use niri_ipc::{Action, Output, Reply, Request, SizeChange, Workspace, WorkspaceReferenceArg};
 pub use state::{LayoutEvent, Snapshot, Window};
 pub use window_stream::WindowStream;
 
+use waybar_cffi::gtk::{gio, glib};
+
 use crate::error::Error;
+use socket::Socket;
 
+mod capabilities;
 mod reply;
+mod socket;
 mod state;
 mod window_stream;
 
 /// The top level client for Niri.
-#[derive(Debug, Clone, Copy)]
-pub struct Niri {}
+#[derive(Debug, Clone)]
+pub struct Niri {
+    capabilities: Capabilities,
+    connection: Arc<Mutex<Connection>>,
+}
 
 impl Niri {
     pub fn new() -> Self {
-        // Since niri_ipc is essentially stateless, we don't maintain anything much here.
-        Self {}
+        // Since niri_ipc is essentially stateless, we don't maintain anything much here beyond
+        // the request connection and what the running Niri supports.
+        Self {
+            capabilities: Capabilities::detect(),
+            connection: Arc::new(Mutex::new(Connection::default())),
+        }
+    }
+
+    /// Runs the given requests on a worker thread, so that the bar doesn't freeze while waiting
+    /// for a slow or hung Niri to reply.
+    pub async fn run<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Niri) -> Result<T, Error> + Send + 'static,
+    {
+        let niri = self.clone();
+        gio::spawn_blocking(move || f(&niri))
+            .await
+            .map_err(|_| Error::Task)?
+    }
+
+    /// Runs the given requests in the background, logging any error with the given message.
+    pub fn spawn<F>(&self, message: &'static str, f: F)
+    where
+        F: FnOnce(&Niri) -> Result<(), Error> + Send + 'static,
+    {
+        let niri = self.clone();
+        glib::spawn_future_local(async move {
+            if let Err(e) = niri.run(f).await {
+                tracing::warn!(%e, "{message}");
+            }
+        });
+    }
+
+    /// Returns the features supported by the running Niri.
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
     }
 
     /// Requests that the given window ID should be activated.
     #[tracing::instrument(level = "TRACE", err)]
     pub fn activate_window(&self, id: u64) -> Result<(), Error> {
-        let reply = request(Request::Action(Action::FocusWindow { id }))?;
+        let reply = self.request(Request::Action(Action::FocusWindow { id }))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Requests that the previously focused window should be focused.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn focus_window_previous(&self) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::FocusWindowPrevious {}))?;
         reply::typed!(Handled, reply)
     }
 
     #[tracing::instrument(level = "TRACE", err)]
     pub fn close_window(&self, id: u64) -> Result<(), Error> {
-        let reply = request(Request::Action(Action::CloseWindow { id: Some(id) }))?;
+        let reply = self.request(Request::Action(Action::CloseWindow { id: Some(id) }))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Takes a screenshot of the given window ID, placing it on the clipboard.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn screenshot_window(&self, id: u64) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::ScreenshotWindow {
+            id: Some(id),
+            write_to_disk: false,
+        }))?;
         reply::typed!(Handled, reply)
     }
 
     /// Returns the current outputs.
     pub fn outputs(&self) -> Result<HashMap<String, Output>, Error> {
-        let reply = request(Request::Outputs)?;
+        let reply = self.request(Request::Outputs)?;
         reply::typed!(Outputs, reply)
     }
 
     /// Returns a stream of window snapshots.
-    pub fn window_stream(&self) -> WindowStream {
-        WindowStream::new()
+    ///
+    /// If Niri doesn't provide an event stream, it's polled at the given interval instead.
+    pub fn window_stream(&self, poll_interval: Duration) -> WindowStream {
+        WindowStream::new(poll_interval)
     }
 
-    pub fn focus_tiling(&self) -> Result<HashMap<String, Output>, Error> {
-        let reply = request(Request::Action(Action::FocusTiling {}))?;
-        reply::typed!(Outputs, reply)
+    /// Returns the current workspaces.
+    pub fn workspaces(&self) -> Result<Vec<Workspace>, Error> {
+        let reply = self.request(Request::Workspaces)?;
+        reply::typed!(Workspaces, reply)
+    }
+
+    /// Focuses the workspace with the given ID.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn focus_workspace(&self, id: u64) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::FocusWorkspace {
+            reference: WorkspaceReferenceArg::Id(id),
+        }))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Sets the name of the workspace with the given ID, or unsets it if the name is empty.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn set_workspace_name(&self, id: u64, name: &str) -> Result<(), Error> {
+        let reference = Some(WorkspaceReferenceArg::Id(id));
+        let action = if name.is_empty() {
+            Action::UnsetWorkspaceName { reference }
+        } else {
+            Action::SetWorkspaceName {
+                name: name.to_string(),
+                workspace: reference,
+            }
+        };
+
+        let reply = self.request(Request::Action(action))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Moves the workspace with the given ID to the given 1-based index on its output.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn move_workspace_to_index(&self, id: u64, index: usize) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::MoveWorkspaceToIndex {
+            index,
+            reference: Some(WorkspaceReferenceArg::Id(id)),
+        }))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Moves the workspace with the given ID to the output with the given name.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn move_workspace_to_monitor(&self, id: u64, output: &str) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::MoveWorkspaceToMonitor {
+            output: output.to_string(),
+            reference: Some(WorkspaceReferenceArg::Id(id)),
+        }))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Focuses the workspace above the current one.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn focus_workspace_up(&self) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::FocusWorkspaceUp {}))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Focuses the workspace below the current one.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn focus_workspace_down(&self) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::FocusWorkspaceDown {}))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Moves the focused column to the given 1-based index on its workspace.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn move_column_to_index(&self, index: usize) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::MoveColumnToIndex { index }))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Moves the focused window up within its column.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn move_window_up(&self) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::MoveWindowUp {}))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Moves the focused window down within its column.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn move_window_down(&self) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::MoveWindowDown {}))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Focuses the column to the left of the focused column.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn focus_column_left(&self) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::FocusColumnLeft {}))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Focuses the column to the right of the focused column.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn focus_column_right(&self) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::FocusColumnRight {}))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Focuses the window above the focused window in its column.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn focus_window_up(&self) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::FocusWindowUp {}))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Focuses the window below the focused window in its column.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn focus_window_down(&self) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::FocusWindowDown {}))?;
+        reply::typed!(Handled, reply)
     }
 
+    pub fn focus_tiling(&self) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::FocusTiling {}))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Toggles fullscreen on the given window ID.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn fullscreen_window(&self, id: u64) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::FullscreenWindow { id: Some(id) }))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Toggles maximising the focused column.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn maximize_column(&self) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::MaximizeColumn {}))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Changes the width of the given window ID.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn set_window_width(&self, id: u64, change: SizeChange) -> Result<(), Error> {
+        if !self.capabilities.set_window_width() {
+            return Err(Error::Unsupported("setting window widths by ID"));
+        }
+
+        let reply = self.request(Request::Action(Action::SetWindowWidth {
+            id: Some(id),
+            change,
+        }))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Moves the given window ID between the floating and tiling layouts.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn toggle_window_floating(&self, id: u64) -> Result<(), Error> {
+        if !self.capabilities.floating() {
+            return Err(Error::Unsupported("floating windows"));
+        }
+
+        let reply = self.request(Request::Action(Action::ToggleWindowFloating {
+            id: Some(id),
+        }))?;
+        reply::typed!(Handled, reply)
+    }
+
+    /// Moves the given window ID to the given workspace ID, optionally following it with focus.
+    #[tracing::instrument(level = "TRACE", err)]
     pub fn move_window_to_workspace(
         &self,
         window_id: u64,
         workspace_id: u64,
-    ) -> Result<HashMap<String, Output>, Error> {
-        let reply = request(Request::Action(Action::MoveWindowToWorkspace {
+        focus: bool,
+    ) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::MoveWindowToWorkspace {
             window_id: Some(window_id),
-            reference: niri_ipc::WorkspaceReferenceArg::Id(workspace_id),
-            focus: false,
+            reference: WorkspaceReferenceArg::Id(workspace_id),
+            focus,
         }))?;
-        reply::typed!(Outputs, reply)
+        reply::typed!(Handled, reply)
+    }
+
+    /// Moves the given window ID to the output with the given name.
+    #[tracing::instrument(level = "TRACE", err)]
+    pub fn move_window_to_monitor(&self, id: u64, output: &str) -> Result<(), Error> {
+        let reply = self.request(Request::Action(Action::MoveWindowToMonitor {
+            id: Some(id),
+            output: output.to_string(),
+        }))?;
+        reply::typed!(Handled, reply)
     }
 }
 
-// Helper to marshal request errors into our own type system.
-//
-// This can't be used for event streams, since the stream callback is thrown away in this function.
-#[tracing::instrument(level = "TRACE", err)]
-fn request(request: Request) -> Result<Reply, Error> {
-    socket()?.send(request).map_err(Error::NiriIpc)
+impl Niri {
+    // Helper to send a request over the shared connection.
+    fn request(&self, request: Request) -> Result<Reply, Error> {
+        self.connection
+            .lock()
+            .expect("Niri connection lock")
+            .request(request)
+    }
+}
+
+/// A connection to Niri for requests, which is reused between requests so that rapid actions
+/// don't each have to connect, and reopened when it fails.
+///
+/// This can't be used for event streams, since Niri only sends events once a connection has
+/// requested them.
+#[derive(Default)]
+struct Connection(Option<Socket>);
+
+impl Connection {
+    // Helper to marshal request errors into our own type system.
+    #[tracing::instrument(level = "TRACE", skip(self), err)]
+    fn request(&mut self, request: Request) -> Result<Reply, Error> {
+        // Niri may have restarted or dropped the connection since the last request, so a request
+        // that can't be written to an existing connection gets one retry on a new one.
+        //
+        // Once the request has been written, though, Niri may have acted on it, so it's never sent
+        // again: actions such as closing a window or toggling floating aren't idempotent.
+        if let Some(socket) = &mut self.0 {
+            match socket.write(&request) {
+                Ok(()) => {
+                    let reply = socket.read_reply();
+                    return reply.map_err(|e| self.failed(e));
+                }
+                Err(e) => tracing::debug!(%e, "Niri request connection failed; reconnecting"),
+            }
+        }
+
+        let socket = self.0.insert(socket()?);
+        let reply = socket.send(&request);
+        reply.map_err(|e| self.failed(e))
+    }
+
+    // Drops the connection after an error, since a reply may be left unread on it.
+    fn failed(&mut self, e: std::io::Error) -> Error {
+        self.0 = None;
+        Error::NiriIpc(e)
+    }
+}
+
+impl Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Connection")
+            .field(&self.0.as_ref().map(|_| "connected"))
+            .finish()
+    }
 }
 
 // Helper to connect to the Niri socket.
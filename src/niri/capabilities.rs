@@ -0,0 +1,141 @@
+//! Detection of the features supported by the running Niri, based on the version it reports.
+
+use std::sync::{Arc, OnceLock};
+
+use niri_ipc::Request;
+
+use super::{Connection, reply};
+
+/// A Niri version, as `(major, minor)`.
+type Version = (u32, u32);
+
+/// The version that added window layouts to IPC, which are needed to order windows by position.
+const WINDOW_LAYOUTS: Version = (25, 5);
+
+/// The version that added window urgency to IPC.
+const WINDOW_URGENCY: Version = (25, 5);
+
+/// The version that added floating windows.
+const FLOATING: Version = (25, 1);
+
+/// The version that added setting the width of a window by its ID.
+const SET_WINDOW_WIDTH: Version = (25, 1);
+
+/// The features supported by the running Niri.
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    // Until Niri has replied, or if the version can't be determined, we'll assume Niri is new
+    // enough for everything.
+    version: Arc<OnceLock<Option<Version>>>,
+}
+
+impl Capabilities {
+    /// Asks Niri for its version on a worker thread, so that a slow Niri doesn't hold up the bar,
+    /// and logs any features that aren't supported by it.
+    pub(super) fn detect() -> Self {
+        let capabilities = Self::default();
+        std::thread::spawn({
+            let capabilities = capabilities.clone();
+            move || {
+                let _ = capabilities
+                    .version
+                    .set(version(&mut Connection::default()));
+                capabilities.log_unsupported();
+            }
+        });
+
+        capabilities
+    }
+
+    fn log_unsupported(&self) {
+        for (feature, required, supported) in [
+            (
+                "ordering by window position",
+                WINDOW_LAYOUTS,
+                self.window_layouts(),
+            ),
+            ("window urgency", WINDOW_URGENCY, self.window_urgency()),
+            ("floating actions", FLOATING, self.floating()),
+            (
+                "setting window widths",
+                SET_WINDOW_WIDTH,
+                self.set_window_width(),
+            ),
+        ] {
+            if !supported {
+                tracing::info!(
+                    feature,
+                    required = format!("{}.{:02}", required.0, required.1),
+                    "disabled because Niri is too old"
+                );
+            }
+        }
+    }
+
+    /// Returns true if Niri reports window positions.
+    pub fn window_layouts(&self) -> bool {
+        self.at_least(WINDOW_LAYOUTS)
+    }
+
+    /// Returns true if Niri reports urgent windows.
+    pub fn window_urgency(&self) -> bool {
+        self.at_least(WINDOW_URGENCY)
+    }
+
+    /// Returns true if Niri supports floating windows.
+    pub fn floating(&self) -> bool {
+        self.at_least(FLOATING)
+    }
+
+    /// Returns true if Niri can set the width of a window by its ID.
+    pub fn set_window_width(&self) -> bool {
+        self.at_least(SET_WINDOW_WIDTH)
+    }
+
+    fn at_least(&self, required: Version) -> bool {
+        self.version
+            .get()
+            .copied()
+            .flatten()
+            .is_none_or(|version| version >= required)
+    }
+}
+
+/// Asks Niri for its version.
+fn version(connection: &mut Connection) -> Option<Version> {
+    match connection
+        .request(Request::Version)
+        .and_then(|reply| reply::typed!(Version, reply))
+    {
+        Ok(version) => {
+            let parsed = parse_version(&version);
+            if parsed.is_none() {
+                tracing::warn!(version, "cannot parse Niri version; assuming all features");
+            } else {
+                tracing::info!(version, "detected Niri version");
+            }
+            parsed
+        }
+        Err(e) => {
+            tracing::warn!(%e, "cannot get Niri version; assuming all features");
+            None
+        }
+    }
+}
+
+/// Parses the major and minor version from a version string such as `25.08 (b9f8d1a)`.
+fn parse_version(version: &str) -> Option<Version> {
+    let version = version.split_whitespace().next()?;
+    let version = version.strip_prefix('v').unwrap_or(version);
+    let mut parts = version.split('.');
+
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts
+        .next()?
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()?;
+
+    Some((major, minor))
+}
@@ -0,0 +1,132 @@
+//! Blocking communication over the Niri socket.
+//!
+//! This is much like [`niri_ipc::socket::Socket`], except that sending a request and reading its
+//! reply are separate steps, so that callers can tell whether Niri could have acted on a request
+//! that failed.
+
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, Write},
+    net::Shutdown,
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    time::SystemTime,
+};
+
+use niri_ipc::{Event, Reply, Request, socket::SOCKET_PATH_ENV};
+use serde_json::error::Category;
+
+/// A connection to the Niri socket.
+pub struct Socket {
+    stream: BufReader<UnixStream>,
+}
+
+impl Socket {
+    /// Connects to the Niri socket.
+    ///
+    /// The socket Waybar was started with is tried first, but the path includes Niri's PID, so if
+    /// Niri has been restarted since, the newest socket in the runtime directory is used instead.
+    pub fn connect() -> io::Result<Self> {
+        let inherited = env::var_os(SOCKET_PATH_ENV).map(PathBuf::from);
+        let stream = match &inherited {
+            Some(path) => UnixStream::connect(path).or_else(|e| {
+                let found = find().filter(|found| found != path).ok_or(e)?;
+                tracing::info!(?found, "inherited Niri socket is gone; using newest socket");
+                UnixStream::connect(found)
+            }),
+            None => {
+                let found = find().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{SOCKET_PATH_ENV} is not set, are you running this within niri?"),
+                    )
+                })?;
+                UnixStream::connect(found)
+            }
+        }?;
+
+        Ok(Self {
+            stream: BufReader::new(stream),
+        })
+    }
+
+    /// Writes a request to the socket.
+    ///
+    /// Niri only acts on complete requests, so if this fails, the request wasn't handled.
+    pub fn write(&mut self, request: &Request) -> io::Result<()> {
+        let mut buf = serde_json::to_string(request)?;
+        buf.push('\n');
+        self.stream.get_mut().write_all(buf.as_bytes())
+    }
+
+    /// Reads the reply to the last request.
+    pub fn read_reply(&mut self) -> io::Result<Reply> {
+        let mut buf = String::new();
+        self.stream.read_line(&mut buf)?;
+        Ok(serde_json::from_str(&buf)?)
+    }
+
+    /// Sends a request and reads its reply.
+    pub fn send(&mut self, request: &Request) -> io::Result<Reply> {
+        self.write(request)?;
+        self.read_reply()
+    }
+
+    /// Turns the connection into a reader of events, once an event stream has been requested.
+    ///
+    /// Events that don't match any we know about, such as those added by newer versions of Niri,
+    /// and events that can't be parsed at all are read as `None`, rather than failing the stream.
+    pub fn read_events(self) -> impl FnMut() -> io::Result<Option<Event>> {
+        let Self { mut stream } = self;
+        let _ = stream.get_mut().shutdown(Shutdown::Write);
+
+        let mut buf = String::new();
+        move || {
+            buf.clear();
+            if stream.read_line(&mut buf)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+
+            // Each event is on its own line, so a bad one can be skipped without losing track of
+            // the stream; reconnecting would throw away every button for the sake of one event.
+            match serde_json::from_str(&buf) {
+                Ok(event) => Ok(Some(event)),
+                Err(e) if e.classify() == Category::Data => {
+                    tracing::debug!(%e, event = buf.trim_end(), "ignoring unknown Niri event");
+                    Ok(None)
+                }
+                Err(e) if e.is_io() => Err(e.into()),
+                Err(e) => {
+                    tracing::warn!(%e, event = buf.trim_end(), "ignoring malformed Niri event");
+                    Ok(None)
+                }
+            }
+        }
+    }
+}
+
+/// Finds the newest Niri socket in the runtime directory for the current Wayland display.
+fn find() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_RUNTIME_DIR")?;
+    let prefix = match env::var("WAYLAND_DISPLAY") {
+        Ok(display) => format!("niri.{display}."),
+        Err(_) => String::from("niri."),
+    };
+
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".sock"))
+        })
+        .max_by_key(|entry| {
+            entry
+                .metadata()
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH)
+        })
+        .map(|entry| entry.path())
+}
@@ -61,6 +61,7 @@ impl WindowSet {
             Event::WindowFocusChanged { id } => {
                 if let Some(Inner::Ready(state)) = &mut self.0 {
                     state.set_focus(id);
+                    events.extend(id.map(LayoutEvent::Focused));
                 } else {
                     tracing::warn!(%self, "unexpected state for WindowFocusChanged event");
                 }
@@ -72,10 +73,27 @@ impl WindowSet {
                     }
                 }
             }
+            Event::WindowUrgencyChanged { id, urgent } => {
+                if let Some(Inner::Ready(state)) = &mut self.0 {
+                    state.set_urgent(id, urgent);
+                }
+            }
             Event::WorkspaceActivated { id, focused } => {
                 if let Some(Inner::Ready(state)) = &mut self.0 {
-                    for (_, workspace) in &mut state.workspaces {
-                        workspace.is_focused = focused && id == workspace.id;
+                    // Activation is per output, while focus is global and only moves when the
+                    // activation also focused the workspace.
+                    let output = state.workspaces.get(&id).map(|ws| ws.output.clone());
+                    if output.is_none() {
+                        tracing::warn!(id, "activated workspace not found");
+                    }
+
+                    for workspace in state.workspaces.values_mut() {
+                        if output.as_ref() == Some(&workspace.output) {
+                            workspace.is_active = workspace.id == id;
+                        }
+                        if focused {
+                            workspace.is_focused = workspace.id == id;
+                        }
                     }
                 }
             }
@@ -88,6 +106,31 @@ impl WindowSet {
 
         events
     }
+
+    /// Replaces the window set with the windows and workspaces polled from Niri, for when the
+    /// event stream isn't available.
+    pub fn with_poll(
+        &mut self,
+        windows: Vec<NiriWindow>,
+        workspaces: Vec<Workspace>,
+    ) -> Vec<LayoutEvent> {
+        let mut events = vec![];
+
+        // Without events, the only way to notice a floating window closing is that it's gone.
+        if let Some(Inner::Ready(state)) = &self.0 {
+            if state.windows.values().any(|window| {
+                window.is_floating && !windows.iter().any(|polled| polled.id == window.id)
+            }) {
+                events.push(LayoutEvent::FloatingClosed);
+            }
+        }
+
+        let state = Niri::new(windows, workspaces);
+        events.push(LayoutEvent::Change(state.snapshot()));
+        self.0 = Some(Inner::Ready(state));
+
+        events
+    }
 }
 
 impl Display for WindowSet {
@@ -165,6 +208,12 @@ impl Niri {
         }
     }
 
+    fn set_urgent(&mut self, window_id: u64, urgent: bool) {
+        self.windows.entry(window_id).and_modify(|window| {
+            window.is_urgent = urgent;
+        });
+    }
+
     fn update_window_layout(&mut self, window_id: u64, layout: WindowLayout) {
         self.windows.entry(window_id).and_modify(|window| {
             window.layout = layout;
@@ -209,13 +258,21 @@ impl Niri {
     }
 }
 
+#[derive(Debug, Clone)]
 pub enum LayoutEvent {
     Change(Snapshot),
     FloatingClosed,
+    /// Niri focused the given window. This is sent before the snapshot including the change.
+    Focused(u64),
+    /// The connection to Niri was lost, so the taskbar is stale until it's re-established.
+    Disconnected,
+    /// The connection to Niri was lost and has been re-established, so every window should be
+    /// forgotten before the next snapshot.
+    Reset,
 }
 
 /// A snapshot of current toplevel windows, ordered by workspace index.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Snapshot {
     pub windows: Vec<Window>,
     pub workspaces: Vec<Workspace>,
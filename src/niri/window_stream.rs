@@ -1,9 +1,11 @@
+use std::{convert::Infallible, time::Duration};
+
 use async_channel::{Receiver, Sender};
 use niri_ipc::Request;
 
 use crate::{error::Error, niri::state::LayoutEvent};
 
-use super::{reply, socket, state::WindowSet};
+use super::{Connection, reply, socket, state::WindowSet};
 
 /// A stream that receives events from Niri and produces a stream of window [`Snapshot`]s.
 pub struct WindowStream {
@@ -11,10 +13,10 @@ pub struct WindowStream {
 }
 
 impl WindowStream {
-    pub(super) fn new() -> Self {
+    pub(super) fn new(poll_interval: Duration) -> Self {
         let (tx, rx) = async_channel::unbounded();
         std::thread::spawn(move || {
-            if let Err(e) = window_stream(tx) {
+            if let Err(e) = window_stream(tx, poll_interval) {
                 tracing::error!(%e, "Niri taskbar window stream error");
             }
         });
@@ -28,18 +30,60 @@ impl WindowStream {
     }
 }
 
-fn window_stream(tx: Sender<LayoutEvent>) -> Result<(), Error> {
+/// The delay before the first attempt to reconnect to Niri, which doubles with each failed attempt.
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
+
+/// The longest delay between attempts to reconnect to Niri.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+fn window_stream(tx: Sender<LayoutEvent>, poll_interval: Duration) -> Result<(), Error> {
+    let mut backoff = MIN_BACKOFF;
+    let mut disconnected = false;
+
+    loop {
+        match event_stream(&tx, poll_interval, &mut disconnected, &mut backoff) {
+            // If nothing is listening any more, there's no point reconnecting.
+            Err(Error::WindowStreamSend) => return Err(Error::WindowStreamSend),
+            Err(e) => tracing::warn!(%e, ?backoff, "Niri event stream failed; reconnecting"),
+        }
+
+        // The taskbar is only told once per outage, rather than on every failed attempt.
+        if !disconnected {
+            disconnected = true;
+            tx.send_blocking(LayoutEvent::Disconnected)
+                .map_err(|_| Error::WindowStreamSend)?;
+        }
+
+        std::thread::sleep(backoff);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Streams events from a single connection to Niri until it fails.
+///
+/// If Niri refuses to provide an event stream, it's polled instead.
+fn event_stream(
+    tx: &Sender<LayoutEvent>,
+    poll_interval: Duration,
+    disconnected: &mut bool,
+    backoff: &mut Duration,
+) -> Result<Infallible, Error> {
     let mut socket = socket()?;
-    let reply = socket.send(Request::EventStream).map_err(Error::NiriIpc)?;
-    reply::typed!(Handled, reply)?;
+    let reply = socket.send(&Request::EventStream).map_err(Error::NiriIpc)?;
+    if let Err(e) = reply::typed!(Handled, reply) {
+        tracing::warn!(%e, ?poll_interval, "Niri event stream unavailable; polling instead");
+        reset(tx, disconnected, backoff)?;
+        return poll(tx, poll_interval);
+    }
     let mut next = socket.read_events();
+    reset(tx, disconnected, backoff)?;
 
     let mut state = WindowSet::new();
     loop {
-        // There appears to be no EOF state, presumably on the assumption that if Niri goes away it
-        // doesn't matter what happens to this process.
+        // If Niri goes away, reading the next event fails, at which point we'll try to reconnect.
         match next() {
-            Ok(event) => {
+            Ok(None) => {}
+            Ok(Some(event)) => {
                 for layout_event in state.with_event(event) {
                     tx.send_blocking(layout_event)
                         .map_err(|_| Error::WindowStreamSend)?;
@@ -52,3 +96,39 @@ fn window_stream(tx: Sender<LayoutEvent>) -> Result<(), Error> {
         }
     }
 }
+
+/// Polls Niri for its windows and workspaces until a request fails.
+fn poll(tx: &Sender<LayoutEvent>, interval: Duration) -> Result<Infallible, Error> {
+    let mut connection = Connection::default();
+    let mut state = WindowSet::new();
+    loop {
+        let windows = reply::typed!(Windows, connection.request(Request::Windows)?)?;
+        let workspaces = reply::typed!(Workspaces, connection.request(Request::Workspaces)?)?;
+        for layout_event in state.with_poll(windows, workspaces) {
+            tx.send_blocking(layout_event)
+                .map_err(|_| Error::WindowStreamSend)?;
+        }
+
+        std::thread::sleep(interval);
+    }
+}
+
+/// Records that a connection to Niri has succeeded, resetting the taskbar if this is a
+/// reconnection.
+fn reset(
+    tx: &Sender<LayoutEvent>,
+    disconnected: &mut bool,
+    backoff: &mut Duration,
+) -> Result<(), Error> {
+    // The full state arrives at the start of each stream or poll, so on reconnection everything
+    // is thrown away and rebuilt: window IDs aren't preserved across Niri restarts.
+    if *disconnected {
+        tracing::info!("reconnected to Niri");
+        tx.send_blocking(LayoutEvent::Reset)
+            .map_err(|_| Error::WindowStreamSend)?;
+    }
+    *disconnected = false;
+    *backoff = MIN_BACKOFF;
+
+    Ok(())
+}
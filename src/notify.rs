@@ -1,4 +1,4 @@
-use std::{ops::Deref, time::Duration};
+use std::{cell::RefCell, collections::HashMap, ops::Deref, rc::Rc, time::Duration};
 
 use async_channel::Sender;
 use cache::ConnectionCache;
@@ -8,27 +8,57 @@ use serde::{Deserialize, Deserializer};
 use waybar_cffi::gtk::glib::{self};
 use zbus::{
     Connection, MatchRule, Message, MessageStream,
-    fdo::MonitoringProxy,
+    fdo::{DBusProxy, MonitoringProxy},
+    message::Type as MessageType,
     names::{InterfaceName, MemberName},
-    zvariant::{DeserializeDict, Optional, Type},
+    zvariant::{DeserializeDict, Optional, OwnedValue, Type, Value},
 };
 
+use crate::config::NotificationCache;
+
 mod cache;
 
-/// Starts a stream of notifications.
+/// Starts a stream of notifications, and of notifications being closed.
 ///
 /// Under the hood, this sets up a monitor on the D-Bus session bus and grabs
 /// any method call to the `Notify` method on the
-/// `org.freedesktop.Notifications` interface.
-pub fn stream() -> impl Stream<Item = EnrichedNotification> {
+/// `org.freedesktop.Notifications` interface, along with the daemon's replies
+/// and `NotificationClosed` signals.
+///
+/// If the monitor connection drops, it's re-established, with the outage
+/// reported through [`NotifyEvent::Disconnected`] and
+/// [`NotifyEvent::Reconnected`].
+pub fn stream(cache: NotificationCache) -> impl Stream<Item = NotifyEvent> {
     // For lifetime reasons, it's easier to have an async channel extract the
     // data out of the GLib event loop than it is to return the stream directly.
     let (tx, rx) = async_channel::unbounded();
     glib::spawn_future_local(async move {
-        match monitor_dbus(tx).await {
-            Ok(()) => tracing::info!("no longer monitoring D-Bus"),
-            Err(e) => tracing::error!(%e, "D-Bus error"),
+        let mut backoff = MIN_BACKOFF;
+        let mut disconnected = false;
+
+        loop {
+            match monitor_dbus(&tx, &cache, &mut disconnected, &mut backoff).await {
+                Ok(()) => tracing::warn!(?backoff, "D-Bus monitor closed; reconnecting"),
+                Err(e) => tracing::warn!(%e, ?backoff, "D-Bus monitor error; reconnecting"),
+            }
+
+            // The bus may have gone away altogether, so the shared connection is opened again
+            // too.
+            CONNECTION.take();
+
+            // As with Niri, the taskbar is only told once per outage. If nothing is listening
+            // any more, there's no point reconnecting.
+            if !disconnected {
+                disconnected = true;
+                if tx.send(NotifyEvent::Disconnected).await.is_err() {
+                    break;
+                }
+            }
+
+            glib::timeout_future(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
         }
+        tracing::info!("no longer monitoring D-Bus");
     });
 
     async_stream::stream! {
@@ -38,15 +68,77 @@ pub fn stream() -> impl Stream<Item = EnrichedNotification> {
     }
 }
 
+/// Asks the notification daemon to close the notifications with the given IDs.
+pub fn close(ids: Vec<u32>) {
+    glib::spawn_future_local(async move {
+        if let Err(e) = close_notifications(&ids).await {
+            tracing::warn!(%e, ?ids, "error closing notifications");
+        }
+    });
+}
+
+async fn close_notifications(ids: &[u32]) -> zbus::Result<()> {
+    let conn = connection().await?;
+    for id in ids {
+        conn.call_method(
+            Some(INTERFACE),
+            PATH,
+            Some(INTERFACE),
+            CLOSE_METHOD,
+            &(*id,),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+thread_local! {
+    // The connection used to ask D-Bus about senders and to close notifications, which is shared
+    // rather than opened for every click. The monitor needs a connection of its own, since a
+    // monitoring connection can't send messages.
+    static CONNECTION: RefCell<Option<Connection>> = const { RefCell::new(None) };
+}
+
+/// Returns the shared session bus connection, connecting if there isn't one yet.
+async fn connection() -> zbus::Result<Connection> {
+    if let Some(conn) = CONNECTION.with_borrow(Clone::clone) {
+        return Ok(conn);
+    }
+
+    let conn = Connection::session().await?;
+    CONNECTION.set(Some(conn.clone()));
+    Ok(conn)
+}
+
+/// An event seen in the traffic to and from the notification daemon.
+#[derive(Debug, Clone)]
+pub enum NotifyEvent {
+    /// A notification was sent.
+    Notification(Box<EnrichedNotification>),
+    /// The notification with the given ID was closed, whether it expired or was dismissed.
+    Closed(u32),
+    /// The monitor connection was lost, and notifications won't be seen until it's re-established.
+    Disconnected,
+    /// The monitor connection was re-established after being lost.
+    Reconnected,
+}
+
 /// A FDO notification with the PID of the connection that sent it, if
 /// available.
 #[derive(Debug, Clone)]
 pub struct EnrichedNotification {
     notification: Notification,
     pid: Option<u32>,
+    id: Option<u32>,
 }
 
 impl EnrichedNotification {
+    /// Returns the ID the daemon assigned to the notification, if we saw its reply.
+    pub fn id(&self) -> Option<u32> {
+        self.id
+    }
+
     /// Returns a reference to the notification.
     pub fn notification(&self) -> &Notification {
         &self.notification
@@ -79,6 +171,35 @@ pub struct Notification {
     pub expire_timeout: i32,
 }
 
+impl Notification {
+    /// Returns the urgency of the notification, defaulting to normal if the hint is missing or
+    /// can't be understood.
+    pub fn urgency(&self) -> Urgency {
+        let level = match self.hints.urgency.as_deref() {
+            Some(Value::U8(level)) => u32::from(*level),
+            Some(Value::U32(level)) => *level,
+            Some(Value::I32(level)) => u32::try_from(*level).unwrap_or_default(),
+            _ => return Urgency::default(),
+        };
+
+        match level {
+            0 => Urgency::Low,
+            2 => Urgency::Critical,
+            _ => Urgency::Normal,
+        }
+    }
+}
+
+/// The urgency levels defined by the notification specification.
+#[derive(Debug, Default, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Urgency {
+    Low,
+    #[default]
+    Normal,
+    Critical,
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Type)]
 #[zvariant(signature = "as")]
@@ -128,33 +249,84 @@ pub struct Hints {
     // pub transient: Option<bool>,
     pub sender_pid: Option<i64>,
     // This is specified as a BYTE, but in practice is sometimes sent as a u32.
-    // pub urgency: Option<zvariant::OwnedValue>,
+    pub urgency: Option<OwnedValue>,
     // pub x: Option<i32>,
     // pub y: Option<i32>,
 }
 
 static INTERFACE: &str = "org.freedesktop.Notifications";
 static METHOD: &str = "Notify";
+static CLOSED_SIGNAL: &str = "NotificationClosed";
+static CLOSE_METHOD: &str = "CloseNotification";
+static PATH: &str = "/org/freedesktop/Notifications";
+
+/// How long to wait for the daemon to reply to a notification with its ID before passing the
+/// notification on without one.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Notifications waiting for the daemon's reply, keyed by the unique name of the sender and the
+/// serial of its `Notify` call.
+type Pending = Rc<RefCell<HashMap<(String, u32), EnrichedNotification>>>;
+
+/// The delay before the first attempt to reconnect the monitor, which doubles with each failed
+/// attempt.
+const MIN_BACKOFF: Duration = Duration::from_millis(500);
 
+/// The longest delay between attempts to reconnect the monitor.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Monitors the bus until the connection fails or is closed.
 #[tracing::instrument(level = "TRACE", skip_all, err)]
-async fn monitor_dbus(tx: Sender<EnrichedNotification>) -> anyhow::Result<()> {
-    let cache = cache::ConnectionCache::new(Duration::from_secs(86400));
+async fn monitor_dbus(
+    tx: &Sender<NotifyEvent>,
+    settings: &NotificationCache,
+    disconnected: &mut bool,
+    backoff: &mut Duration,
+) -> anyhow::Result<()> {
+    // With the cache disabled, we'll ask D-Bus for the PID of every notification's sender, which
+    // is slower, but useful when debugging a mismatch.
+    let cache = settings
+        .enabled()
+        .then(|| ConnectionCache::new(settings.expiry(), settings.cleanup_interval()));
+    let dbus_conn = connection().await?;
+    let dbus_proxy = DBusProxy::new(&dbus_conn).await?;
 
     let conn = Connection::session().await?;
     let proxy = MonitoringProxy::new(&conn).await?;
     proxy
         .become_monitor(
-            &[MatchRule::builder()
-                .interface(INTERFACE)?
-                .member(METHOD)?
-                .build()],
+            &[
+                MatchRule::builder()
+                    .interface(INTERFACE)?
+                    .member(METHOD)?
+                    .build(),
+                MatchRule::builder()
+                    .msg_type(MessageType::Signal)
+                    .interface(INTERFACE)?
+                    .member(CLOSED_SIGNAL)?
+                    .build(),
+                // Replies don't have an interface or member, so the best we can do is to look at
+                // the daemon's replies, and pick out the ones to the Notify calls we've seen.
+                MatchRule::builder()
+                    .msg_type(MessageType::MethodReturn)
+                    .sender(INTERFACE)?
+                    .build(),
+            ],
             0,
         )
         .await?;
 
+    if *disconnected {
+        tracing::info!("reconnected D-Bus monitor");
+        tx.send(NotifyEvent::Reconnected).await?;
+    }
+    *disconnected = false;
+    *backoff = MIN_BACKOFF;
+
+    let pending = Pending::default();
     let mut stream = MessageStream::from(conn);
     while let Some(msg) = stream.try_next().await? {
-        if let Err(e) = process_message(&tx, &cache, &msg).await {
+        if let Err(e) = process_message(tx, cache.as_ref(), &dbus_proxy, &pending, &msg).await {
             tracing::error!(%e, ?msg, "error processing notification message");
         }
     }
@@ -163,29 +335,84 @@ async fn monitor_dbus(tx: Sender<EnrichedNotification>) -> anyhow::Result<()> {
 }
 
 async fn process_message(
-    tx: &Sender<EnrichedNotification>,
-    cache: &ConnectionCache,
+    tx: &Sender<NotifyEvent>,
+    cache: Option<&ConnectionCache>,
+    dbus_proxy: &DBusProxy<'_>,
+    pending: &Pending,
     msg: &Message,
 ) -> anyhow::Result<()> {
-    if msg.header().interface() == Some(&InterfaceName::from_static_str(INTERFACE)?)
-        && msg.header().member() == Some(&MemberName::from_static_str(METHOD)?)
-    {
+    let header = msg.header();
+
+    if msg.message_type() == MessageType::MethodReturn {
+        // The reply to a notification carries the ID the daemon assigned to it, which is what
+        // NotificationClosed refers to.
+        let (Some(destination), Some(serial)) = (header.destination(), header.reply_serial())
+        else {
+            return Ok(());
+        };
+        let notification = pending
+            .borrow_mut()
+            .remove(&(destination.to_string(), serial.get()));
+        if let Some(mut notification) = notification {
+            notification.id = msg.body().deserialize().ok();
+            tx.send(NotifyEvent::Notification(Box::new(notification)))
+                .await?;
+        }
+        return Ok(());
+    }
+
+    if header.interface() != Some(&InterfaceName::from_static_str(INTERFACE)?) {
+        return Ok(());
+    }
+
+    if header.member() == Some(&MemberName::from_static_str(CLOSED_SIGNAL)?) {
+        let (id, _reason): (u32, u32) = msg.body().deserialize()?;
+        tx.send(NotifyEvent::Closed(id)).await?;
+    } else if header.member() == Some(&MemberName::from_static_str(METHOD)?) {
         // Pull the PID out of the connection cache, if we can.
         //
         // This isn't always useful: anything in a Flatpak is going to use
         // the portal's connection, which won't map to a toplevel, but it's
         // better than nothing.
-        let pid = if let Some(sender) = msg.header().sender() {
-            cache.get(sender).await
-        } else {
-            None
+        let pid = match (msg.header().sender(), cache) {
+            (Some(sender), Some(cache)) => cache.get(sender).await,
+            (Some(sender), None) => dbus_proxy
+                .get_connection_unix_process_id(sender.clone().into())
+                .await
+                .ok(),
+            (None, _) => None,
         };
 
-        tx.send(EnrichedNotification {
+        let notification = EnrichedNotification {
             notification: msg.body().deserialize()?,
             pid,
-        })
-        .await?;
+            id: None,
+        };
+
+        // The notification is held until the daemon replies with its ID, unless the reply never
+        // comes: the sender may not have wanted one.
+        let Some(sender) = header.sender() else {
+            tx.send(NotifyEvent::Notification(Box::new(notification)))
+                .await?;
+            return Ok(());
+        };
+        let key = (sender.to_string(), header.primary().serial_num().get());
+        pending.borrow_mut().insert(key.clone(), notification);
+
+        let pending = pending.clone();
+        let tx = tx.clone();
+        glib::spawn_future_local(async move {
+            glib::timeout_future(REPLY_TIMEOUT).await;
+            let notification = pending.borrow_mut().remove(&key);
+            if let Some(notification) = notification {
+                if let Err(e) = tx
+                    .send(NotifyEvent::Notification(Box::new(notification)))
+                    .await
+                {
+                    tracing::error!(%e, "error sending notification");
+                }
+            }
+        });
     }
 
     Ok(())
@@ -25,12 +25,12 @@ pub struct ConnectionCache {
 impl ConnectionCache {
     /// Instantiates a new cache.
     ///
-    /// The expiry is best effort. Values below 5 minutes are unlikely to be
-    /// very effective.
-    pub fn new(expiry: Duration) -> Self {
+    /// The expiry is best effort: it's only enforced each cleanup interval.
+    /// Values below 5 minutes are unlikely to be very effective.
+    pub fn new(expiry: Duration, cleanup_interval: Duration) -> Self {
         let (tx, rx) = async_channel::unbounded();
         glib::spawn_future_local(async move {
-            if let Err(e) = worker(rx, expiry).await {
+            if let Err(e) = worker(rx, expiry, cleanup_interval).await {
                 tracing::error!(%e, "connection cache worker error");
             }
         });
@@ -77,15 +77,18 @@ struct Entry {
 
 static DBUS_INTERFACE: &str = "org.freedesktop.DBus";
 
-async fn worker(rx: Receiver<Request>, expiry: Duration) -> anyhow::Result<()> {
+async fn worker(
+    rx: Receiver<Request>,
+    expiry: Duration,
+    cleanup_interval: Duration,
+) -> anyhow::Result<()> {
     // The actual cache implementation here is extremely straightforward: we'll
     // maintain a HashMap on this task that we add to as we see new connections
     // to D-Bus, and also as we get requests for D-Bus connections that may
     // predate the taskbar starting up.
     //
-    // We expire connections every minute. (This may be too aggressive, but it's
-    // a reasonable starting point.) Each time a connection is looked up, the
-    // expiry resets.
+    // We expire connections each cleanup interval, which defaults to every
+    // minute. Each time a connection is looked up, the expiry resets.
     //
     // We'll also remove connections if we get notified by D-Bus that they are
     // no longer in use.
@@ -107,7 +110,7 @@ async fn worker(rx: Receiver<Request>, expiry: Duration) -> anyhow::Result<()> {
         )
         .await?;
 
-    let mut cleanup = glib::interval_stream(Duration::from_secs(60)).fuse();
+    let mut cleanup = glib::interval_stream(cleanup_interval).fuse();
 
     let mut stream = MessageStream::from(monitor_conn);
     loop {
@@ -1,11 +1,19 @@
+use std::cell::RefCell;
+
 use niri_ipc::{LogicalOutput, Output};
-use waybar_cffi::gtk::gdk::{Monitor, traits::MonitorExt};
+use waybar_cffi::gtk::{
+    gdk::{Monitor, traits::MonitorExt},
+    gio,
+};
+
+pub mod wayland;
 
 /// A filter to check if we should include a window button.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Filter {
     ShowAll,
-    Only(String),
+    Only(Vec<String>),
+    Except(Vec<String>),
 }
 
 impl Filter {
@@ -13,11 +21,100 @@ impl Filter {
     pub fn should_show(&self, output: &str) -> bool {
         match self {
             Self::ShowAll => true,
-            Self::Only(only) => only == output,
+            Self::Only(only) => only.iter().any(|only| only == output),
+            Self::Except(except) => !except.iter().any(|except| except == output),
+        }
+    }
+
+    /// Returns the filter with the given outputs left out.
+    pub fn excluding(self, excluded: &[String]) -> Self {
+        if excluded.is_empty() {
+            return self;
+        }
+
+        match self {
+            Self::ShowAll => Self::Except(excluded.to_vec()),
+            Self::Only(only) => Self::Only(
+                only.into_iter()
+                    .filter(|output| !excluded.contains(output))
+                    .collect(),
+            ),
+            Self::Except(mut except) => {
+                except.extend(excluded.iter().cloned());
+                Self::Except(except)
+            }
         }
     }
 }
 
+/// The layout of the Gdk monitors: the position, size, make, and model of each.
+type Layout = Vec<(i32, i32, i32, i32, Option<String>, Option<String>)>;
+
+thread_local! {
+    // The outputs last described by the compositor, and the monitor layout at the time, so that a
+    // burst of monitor changes, or several bars, don't each open a Wayland connection.
+    static OUTPUTS: RefCell<Option<(Layout, Vec<wayland::Output>)>> = const { RefCell::new(None) };
+}
+
+/// Returns the name of the output the Gdk monitor is, as the compositor describes it over a Wayland
+/// connection of our own, or `None` if it can't be identified that way.
+///
+/// The compositor is only asked again once monitors have been added, removed, or rearranged.
+pub async fn identify(monitor: &Monitor) -> Option<String> {
+    let layout = layout(monitor);
+    let cached = OUTPUTS.with_borrow(|cached| {
+        cached
+            .as_ref()
+            .filter(|(cached, _)| *cached == layout)
+            .map(|(_, outputs)| outputs.clone())
+    });
+    let outputs = match cached {
+        Some(outputs) => outputs,
+        None => match gio::spawn_blocking(wayland::outputs).await {
+            Ok(Ok(outputs)) => {
+                OUTPUTS.set(Some((layout, outputs.clone())));
+                outputs
+            }
+            Ok(Err(e)) => {
+                tracing::info!(%e, "cannot identify output over Wayland");
+                return None;
+            }
+            Err(_) => {
+                tracing::error!("Wayland output lookup panicked");
+                return None;
+            }
+        },
+    };
+
+    // If more than one output matches, then we can't do any better than the Gdk matcher.
+    let mut matching = outputs.into_iter().filter(|output| output.matches(monitor));
+    match (matching.next(), matching.next()) {
+        (Some(output), None) => output.name,
+        _ => None,
+    }
+}
+
+/// Returns the layout of the monitors on the monitor's display.
+fn layout(monitor: &Monitor) -> Layout {
+    let Some(display) = monitor.display() else {
+        return Layout::new();
+    };
+    (0..display.n_monitors())
+        .filter_map(|i| display.monitor(i))
+        .map(|monitor| {
+            let geometry = monitor.geometry();
+            (
+                geometry.x(),
+                geometry.y(),
+                geometry.width(),
+                geometry.height(),
+                monitor.manufacturer().map(String::from),
+                monitor.model().map(String::from),
+            )
+        })
+        .collect()
+}
+
 bitflags::bitflags! {
     /// A simple matcher to try to figure out if a Gdk 3 monitor and a Niri output are referring to
     /// the same output.
@@ -0,0 +1,145 @@
+//! Output identification over a Wayland connection of our own.
+//!
+//! Gdk 3 doesn't expose the connector names of its monitors, but version 4 of `wl_output` does.
+//! Object IDs aren't shared between connections, so the outputs on our connection are matched up
+//! with the Gdk monitor by their position, physical size, make, and model instead.
+//!
+//! That's the same sort of information [`super::Matcher`] compares against Niri's outputs, so it
+//! can't tell apart monitors that Niri's description can't either: identical monitors are only
+//! told apart by their position, and mirrored ones not at all. What it avoids is comparing Gdk's
+//! geometry with Niri's logical layout, which only agree approximately with fractional scales,
+//! since Gdk reads the very same `wl_output` events that we do here.
+
+use thiserror::Error;
+use waybar_cffi::gtk::gdk::{Monitor, traits::MonitorExt};
+use wayland_client::{
+    ConnectError, Connection, Dispatch, DispatchError, QueueHandle,
+    globals::{GlobalError, GlobalListContents, registry_queue_init},
+    protocol::{wl_output, wl_registry},
+};
+
+/// The first version of `wl_output` with the name event.
+const NAME_VERSION: u32 = 4;
+
+/// An output as described by the compositor.
+#[derive(Debug, Clone, Default)]
+pub struct Output {
+    pub name: Option<String>,
+    x: i32,
+    y: i32,
+    width_mm: i32,
+    height_mm: i32,
+    make: String,
+    model: String,
+}
+
+impl Output {
+    /// Returns true if the output is the given Gdk monitor.
+    pub fn matches(&self, monitor: &Monitor) -> bool {
+        let geometry = monitor.geometry();
+        geometry.x() == self.x
+            && geometry.y() == self.y
+            && monitor.width_mm() == self.width_mm
+            && monitor.height_mm() == self.height_mm
+            && monitor.manufacturer().unwrap_or_default() == self.make
+            && monitor.model().unwrap_or_default() == self.model
+    }
+}
+
+/// Connects to the compositor and returns the outputs it advertises.
+///
+/// This blocks until the compositor has described every output, so should be called from a worker
+/// thread.
+pub fn outputs() -> Result<Vec<Output>, Error> {
+    let conn = Connection::connect_to_env()?;
+    let (globals, mut queue) = registry_queue_init::<Outputs>(&conn)?;
+    let qh = queue.handle();
+
+    let mut outputs = Outputs::default();
+    for global in globals.contents().clone_list() {
+        if global.interface != "wl_output" {
+            continue;
+        }
+        if global.version < NAME_VERSION {
+            return Err(Error::Version(global.version));
+        }
+
+        let idx = outputs.0.len();
+        outputs.0.push(Output::default());
+        globals
+            .registry()
+            .bind::<wl_output::WlOutput, _, _>(global.name, NAME_VERSION, &qh, idx);
+    }
+
+    // The outputs send their description as soon as they're bound, so a single roundtrip is
+    // enough to receive all of it.
+    queue.roundtrip(&mut outputs)?;
+    Ok(outputs.0)
+}
+
+#[derive(Default)]
+struct Outputs(Vec<Output>);
+
+impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for Outputs {
+    fn event(
+        _state: &mut Self,
+        _registry: &wl_registry::WlRegistry,
+        _event: wl_registry::Event,
+        _data: &GlobalListContents,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // Outputs plugged in while we're connected are picked up the next time we look.
+    }
+}
+
+impl Dispatch<wl_output::WlOutput, usize> for Outputs {
+    fn event(
+        state: &mut Self,
+        _output: &wl_output::WlOutput,
+        event: wl_output::Event,
+        idx: &usize,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let Some(output) = state.0.get_mut(*idx) else {
+            return;
+        };
+
+        match event {
+            wl_output::Event::Geometry {
+                x,
+                y,
+                physical_width,
+                physical_height,
+                make,
+                model,
+                ..
+            } => {
+                output.x = x;
+                output.y = y;
+                output.width_mm = physical_width;
+                output.height_mm = physical_height;
+                output.make = make;
+                output.model = model;
+            }
+            wl_output::Event::Name { name } => output.name = Some(name),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("cannot connect to Wayland: {0}")]
+    Connect(#[from] ConnectError),
+
+    #[error("cannot list Wayland globals: {0}")]
+    Globals(#[from] GlobalError),
+
+    #[error("cannot receive Wayland output descriptions: {0}")]
+    Dispatch(#[from] DispatchError),
+
+    #[error("the compositor only supports wl_output version {0}, which doesn't include names")]
+    Version(u32),
+}
@@ -0,0 +1,348 @@
+//! Pinned launchers, which are shown before the window buttons and start their app if it isn't
+//! running, or focus its windows if it is.
+
+use std::{
+    cell::{Cell, RefCell},
+    path::PathBuf,
+    rc::Rc,
+    time::Duration,
+};
+
+use waybar_cffi::gtk::{
+    self as gtk, ReliefStyle, gdk,
+    gio::DesktopAppInfo,
+    glib,
+    prelude::{
+        AppInfoExt, ButtonExt, ContainerExt, GdkPixbufExt, ImageExt, SpinnerExt, StyleContextExt,
+        WidgetExt,
+    },
+};
+
+use crate::{button, css, desktop, icon, launcher, niri::Snapshot, state::State};
+
+/// The container of pinned launchers.
+pub struct Pinned {
+    container: gtk::Box,
+    launchers: Vec<Launcher>,
+    // The focused window, which is needed to know where to cycle from.
+    focused: Rc<Cell<Option<u64>>>,
+    placeholders: Placeholders,
+}
+
+impl Pinned {
+    pub fn new(orientation: gtk::Orientation) -> Self {
+        let container = gtk::Box::new(orientation, 0);
+        container.style_context().add_class("niri-taskbar-pinned");
+        container.set_no_show_all(true);
+
+        Self {
+            placeholders: Placeholders::new(&container),
+            container,
+            launchers: Vec::new(),
+            focused: Rc::default(),
+        }
+    }
+
+    pub fn widget(&self) -> &gtk::Box {
+        &self.container
+    }
+
+    /// Replaces the launchers with those in the configuration.
+    pub fn rebuild(&mut self, state: &State) {
+        for launcher in self.launchers.drain(..) {
+            self.container.remove(&launcher.button);
+        }
+
+        let config = state.config();
+        for desktop_id in config.pinned() {
+            match Launcher::new(
+                state,
+                desktop_id,
+                self.focused.clone(),
+                self.placeholders.clone(),
+            ) {
+                Some(launcher) => {
+                    self.container.add(&launcher.button);
+                    launcher.button.show_all();
+                    self.launchers.push(launcher);
+                }
+                None => tracing::warn!(desktop_id, "cannot find desktop entry for pinned app"),
+            }
+        }
+
+        // The container isn't shown by show_all, so that it stays hidden when nothing is pinned.
+        self.container.set_visible(!self.launchers.is_empty());
+    }
+
+    /// Marks the launchers of apps with open windows as running.
+    pub fn update(&self, snapshot: &Snapshot) {
+        self.focused.set(
+            snapshot
+                .windows
+                .iter()
+                .find(|window| window.is_focused)
+                .map(|window| window.id),
+        );
+
+        self.placeholders.update(snapshot);
+
+        for launcher in &self.launchers {
+            let running = snapshot.windows.iter().any(|window| {
+                window
+                    .app_id
+                    .as_deref()
+                    .is_some_and(|app_id| launcher::matches(&launcher.desktop_id, app_id))
+            });
+
+            let context = launcher.button.style_context();
+            if running {
+                context.add_class("running");
+            } else {
+                context.remove_class("running");
+            }
+        }
+    }
+}
+
+struct Launcher {
+    desktop_id: String,
+    button: gtk::Button,
+}
+
+impl Launcher {
+    fn new(
+        state: &State,
+        desktop_id: &str,
+        focused: Rc<Cell<Option<u64>>>,
+        placeholders: Placeholders,
+    ) -> Option<Self> {
+        let info = DesktopAppInfo::new(&format!("{desktop_id}.desktop"))
+            .or_else(|| desktop::candidates(desktop_id).next())?;
+
+        let button = gtk::Button::new();
+        button.set_relief(ReliefStyle::None);
+        button.set_focus_on_click(false);
+        button.set_tooltip_text(Some(&info.name()));
+        css::add_provider(state, &button);
+
+        button.add(&icon(state, desktop_id));
+
+        button.connect_clicked({
+            let state = state.clone();
+            let desktop_id = desktop_id.to_string();
+            move |_| activate(&state, &desktop_id, &info, focused.get(), &placeholders)
+        });
+
+        Some(Self {
+            desktop_id: desktop_id.to_string(),
+            button,
+        })
+    }
+}
+
+/// How long a placeholder is shown for if the app never opens a matching window.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Placeholder buttons for apps that have been launched, but haven't opened a window yet.
+#[derive(Clone)]
+struct Placeholders {
+    container: gtk::Box,
+    pending: Rc<RefCell<Vec<Placeholder>>>,
+}
+
+struct Placeholder {
+    desktop_id: String,
+    button: gtk::Button,
+    timeout: Option<glib::SourceId>,
+}
+
+impl Placeholders {
+    fn new(container: &gtk::Box) -> Self {
+        Self {
+            container: container.clone(),
+            pending: Rc::default(),
+        }
+    }
+
+    /// Shows a placeholder for the given app, until it opens a window or the timeout elapses.
+    fn add(&self, state: &State, desktop_id: &str, info: &DesktopAppInfo) {
+        if self
+            .pending
+            .borrow()
+            .iter()
+            .any(|placeholder| placeholder.desktop_id == desktop_id)
+        {
+            return;
+        }
+
+        let button = gtk::Button::new();
+        button.set_relief(ReliefStyle::None);
+        button.set_focus_on_click(false);
+        button.set_sensitive(false);
+        button.set_tooltip_text(Some(&format!("Starting {}…", info.name())));
+        button.style_context().add_class("niri-taskbar-starting");
+        css::add_provider(state, &button);
+
+        let content = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+        content.add(&icon(state, desktop_id));
+        let spinner = gtk::Spinner::new();
+        spinner.start();
+        content.add(&spinner);
+        button.add(&content);
+
+        self.container.add(&button);
+        button.show_all();
+
+        let timeout = glib::timeout_add_local_once(STARTUP_TIMEOUT, {
+            let placeholders = self.clone();
+            let desktop_id = desktop_id.to_string();
+            move || placeholders.remove(|placeholder| placeholder.desktop_id == desktop_id, true)
+        });
+        self.pending.borrow_mut().push(Placeholder {
+            desktop_id: desktop_id.to_string(),
+            button,
+            timeout: Some(timeout),
+        });
+    }
+
+    /// Removes the placeholders of apps that now have a window.
+    fn update(&self, snapshot: &Snapshot) {
+        self.remove(
+            |placeholder| {
+                snapshot.windows.iter().any(|window| {
+                    window
+                        .app_id
+                        .as_deref()
+                        .is_some_and(|app_id| launcher::matches(&placeholder.desktop_id, app_id))
+                })
+            },
+            false,
+        );
+    }
+
+    fn remove(&self, predicate: impl Fn(&Placeholder) -> bool, timed_out: bool) {
+        self.pending.borrow_mut().retain_mut(|placeholder| {
+            if !predicate(placeholder) {
+                return true;
+            }
+
+            // The timeout source has already been removed if it's the one that fired.
+            if let Some(timeout) = placeholder.timeout.take()
+                && !timed_out
+            {
+                timeout.remove();
+            }
+            self.container.remove(&placeholder.button);
+            false
+        });
+    }
+}
+
+/// Returns the icon for the given app, at the configured size.
+///
+/// The image starts out empty, and is filled in once the icon has been looked up in the
+/// background. It's drawn at the scale of the output the taskbar is on, and redrawn if that
+/// changes.
+fn icon(state: &State, desktop_id: &str) -> gtk::Image {
+    let size = state
+        .config()
+        .icon_size()
+        .unwrap_or(button::DEFAULT_ICON_SIZE);
+    let image = gtk::Image::new();
+    image.set_pixel_size(size);
+
+    let path = Rc::new(RefCell::new(None));
+    let draw = {
+        let path = path.clone();
+        let desktop_id = desktop_id.to_string();
+        move |image: &gtk::Image| {
+            let surface = path
+                .borrow()
+                .as_ref()
+                .and_then(|path: &PathBuf| {
+                    icon::load(path, size, image.scale_factor())
+                        .ok()?
+                        .create_surface(0, image.window().as_ref())
+                })
+                .or_else(|| icon::avatar::draw(&desktop_id, size, image.scale_factor(), None));
+            match surface {
+                Some(surface) => image.set_from_surface(Some(&surface)),
+                None => {
+                    image.set_from_icon_name(Some(button::FALLBACK_ICON), gtk::IconSize::Button)
+                }
+            }
+        }
+    };
+
+    image.connect_scale_factor_notify({
+        let draw = draw.clone();
+        move |image| {
+            if image.storage_type() != gtk::ImageType::Empty {
+                draw(image);
+            }
+        }
+    });
+
+    glib::spawn_future_local({
+        let state = state.clone();
+        let desktop_id = desktop_id.to_string();
+        let image = image.clone();
+        async move {
+            path.replace(
+                state
+                    .icon_cache()
+                    .lookup_in_background(&desktop_id, false)
+                    .await,
+            );
+            draw(&image);
+        }
+    });
+
+    image
+}
+
+/// Launches the app if it has no windows, or otherwise focuses its next window in taskbar order.
+fn activate(
+    state: &State,
+    desktop_id: &str,
+    info: &DesktopAppInfo,
+    focused: Option<u64>,
+    placeholders: &Placeholders,
+) {
+    let windows: Vec<u64> = state
+        .window_order()
+        .iter()
+        .filter(|window| {
+            window
+                .app_id
+                .as_deref()
+                .is_some_and(|app_id| launcher::matches(desktop_id, app_id))
+        })
+        .map(|window| window.id)
+        .collect();
+
+    let target = match focused.and_then(|id| windows.iter().position(|window| *window == id)) {
+        Some(idx) => windows[(idx + 1) % windows.len()],
+        None => match windows.first() {
+            Some(window) => *window,
+            None => {
+                // Gdk's launch context passes a startup notification token to the app, so that
+                // Niri knows to focus its window when it appears.
+                let context =
+                    gdk::Display::default().and_then(|display| display.app_launch_context());
+                match info.launch(&[], context.as_ref()) {
+                    Ok(()) => placeholders.add(state, desktop_id, info),
+                    Err(e) => tracing::warn!(%e, desktop_id, "error launching pinned app"),
+                }
+                return;
+            }
+        },
+    };
+
+    state
+        .niri()
+        .spawn("error activating pinned app window", move |niri| {
+            niri.activate_window(target)
+        });
+    state.window_activated(target);
+}
@@ -1,3 +1,9 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
 use futures::AsyncReadExt;
 use thiserror::Error;
 use waybar_cffi::gtk::{
@@ -17,30 +23,9 @@ impl Process {
     /// which is all we care about right now.
     #[tracing::instrument(level = "TRACE", err)]
     pub async fn new(pid: i64) -> Result<Self, Error> {
-        // Implementation note: there are any number of crates that can do this,
-        // but honestly, most of them are either buggy, introduce a new build
-        // dependency, or way heavier than we need.
-        //
-        // Implementing this ourselves also has the benefit that we can use GIO,
-        // which means that we integrate nicely with GLib's event loop for free.
-        let stat = File::for_path(format!("/proc/{pid}/stat"));
-
-        // The GIO InputStream interface is fairly byzantine, so we'll use the
-        // provided extension trait to turn it into an `AsyncBufRead`, which is
-        // much nicer to deal with.
-        let mut stream = stat
-            .read_future(Priority::DEFAULT)
-            .await
-            .map_err(|e| Error::Open { e, pid })?
-            .into_async_buf_read(4096);
-
         // It's probably technically possible for the `comm` field to be invalid
         // UTF-8 and break this, but I don't think I care very much, honestly.
-        let mut buffer = String::new();
-        stream
-            .read_to_string(&mut buffer)
-            .await
-            .map_err(|e| Error::Read { e, pid })?;
+        let buffer = read(pid, "stat").await?;
 
         // Per proc_pid_stat(5), the parent PID is the fourth element.
         let ppid = buffer
@@ -63,6 +48,142 @@ impl Process {
     }
 }
 
+/// How long a walk up the process tree is remembered for.
+///
+/// This only has to cover a burst of notifications, such as a chat app syncing its backlog: PIDs
+/// are reused, so the chain can't be trusted for long.
+const ANCESTORS_TTL: Duration = Duration::from_secs(10);
+
+/// A cache of the ancestors of processes, so that bursts of notifications from the same process
+/// don't each walk up the process tree through `/proc`.
+#[derive(Debug, Clone, Default)]
+pub struct Cache(Arc<Mutex<HashMap<i64, Ancestors>>>);
+
+#[derive(Debug)]
+struct Ancestors {
+    pids: Vec<i64>,
+    expiry: Instant,
+}
+
+impl Cache {
+    /// Returns the given PID followed by its ancestors, nearest first.
+    ///
+    /// If the process tree can't be read, the ancestors found so far are returned.
+    pub async fn ancestors(&self, pid: i64) -> Vec<i64> {
+        let mut pids = Vec::new();
+        let mut next = Some(pid);
+        while let Some(current) = next.take() {
+            // A cached chain for any process along the way can be reused for the rest of the
+            // walk, since siblings share their ancestors.
+            if let Some(cached) = self.get(current) {
+                pids.extend(cached);
+                break;
+            }
+
+            pids.push(current);
+            match Process::new(current).await {
+                // Keep walking up until there are no more parents.
+                Ok(Process { ppid }) => next = ppid,
+                Err(e) => {
+                    // On error, we'll log but do nothing else: this
+                    // shouldn't be fatal for the bar, since it's possible
+                    // the process has simply already exited.
+                    tracing::info!(pid = current, %e, "error walking up process tree");
+                }
+            }
+        }
+
+        let now = Instant::now();
+        let mut cache = self.0.lock().expect("process cache lock");
+        cache.retain(|_, ancestors| ancestors.expiry > now);
+        cache.insert(
+            pid,
+            Ancestors {
+                pids: pids.clone(),
+                expiry: now + ANCESTORS_TTL,
+            },
+        );
+
+        pids
+    }
+
+    fn get(&self, pid: i64) -> Option<Vec<i64>> {
+        self.0
+            .lock()
+            .expect("process cache lock")
+            .get(&pid)
+            .filter(|ancestors| ancestors.expiry > Instant::now())
+            .map(|ancestors| ancestors.pids.clone())
+    }
+}
+
+/// Returns the ID of the Flatpak app the process belongs to, if any.
+///
+/// Flatpak runs each app instance in its own systemd scope, named
+/// `app-flatpak-{app_id}-{instance}.scope`, which includes the D-Bus proxy
+/// that the app's messages are sent through, so we can find the app ID in
+/// `/proc/{pid}/cgroup`.
+#[tracing::instrument(level = "TRACE", ret, err)]
+pub async fn flatpak_app_id(pid: i64) -> Result<Option<String>, Error> {
+    let buffer = read(pid, "cgroup").await?;
+
+    Ok(buffer.lines().find_map(|line| {
+        let scope = line.rsplit('/').next()?;
+        let unit = scope.strip_prefix("app-flatpak-")?.strip_suffix(".scope")?;
+
+        // The instance is a number, but app IDs can contain dashes too.
+        let (app_id, instance) = unit.rsplit_once('-')?;
+        instance
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| app_id.to_string())
+    }))
+}
+
+/// Returns the name of the executable the process is running, without its directory.
+///
+/// This is taken from the first argument, rather than `/proc/{pid}/exe`, since programs running
+/// under Wine report the Windows executable there, with a Windows path.
+#[tracing::instrument(level = "TRACE", ret, err)]
+pub async fn exe_name(pid: i64) -> Result<Option<String>, Error> {
+    let cmdline = read(pid, "cmdline").await?;
+
+    Ok(cmdline
+        .split('\0')
+        .next()
+        .and_then(|arg| arg.rsplit(['/', '\\']).next())
+        .filter(|name| !name.is_empty())
+        .map(String::from))
+}
+
+/// Reads the given file in `/proc/{pid}`.
+async fn read(pid: i64, file: &'static str) -> Result<String, Error> {
+    // Implementation note: there are any number of crates that can do this,
+    // but honestly, most of them are either buggy, introduce a new build
+    // dependency, or way heavier than we need.
+    //
+    // Implementing this ourselves also has the benefit that we can use GIO,
+    // which means that we integrate nicely with GLib's event loop for free.
+    let path = File::for_path(format!("/proc/{pid}/{file}"));
+
+    // The GIO InputStream interface is fairly byzantine, so we'll use the
+    // provided extension trait to turn it into an `AsyncBufRead`, which is
+    // much nicer to deal with.
+    let mut stream = path
+        .read_future(Priority::DEFAULT)
+        .await
+        .map_err(|e| Error::Open { e, pid, file })?
+        .into_async_buf_read(4096);
+
+    let mut buffer = String::new();
+    stream
+        .read_to_string(&mut buffer)
+        .await
+        .map_err(|e| Error::Read { e, pid, file })?;
+
+    Ok(buffer)
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("malformed /proc/{pid}/stat: insufficient fields")]
@@ -71,17 +192,19 @@ pub enum Error {
     #[error("parent PID not a valid number in /proc/{pid}/stat: {parent}")]
     ParentMalformedNumber { parent: String, pid: i64 },
 
-    #[error("cannot open /proc/{pid}/stat for read: {e}")]
+    #[error("cannot open /proc/{pid}/{file} for read: {e}")]
     Open {
         #[source]
         e: glib::Error,
         pid: i64,
+        file: &'static str,
     },
 
-    #[error("error reading from /proc/{pid}/stat: {e}")]
+    #[error("error reading from /proc/{pid}/{file}: {e}")]
     Read {
         #[source]
         e: futures::io::Error,
         pid: i64,
+        file: &'static str,
     },
 }
@@ -1,15 +1,31 @@
-use std::sync::Arc;
+use std::{
+    cell::RefCell,
+    path::PathBuf,
+    rc::Rc,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
 
-use async_channel::Sender;
+use async_channel::{Receiver, Sender};
 use futures::{Stream, StreamExt};
-use waybar_cffi::gtk::glib;
+use waybar_cffi::gtk::{
+    self as gtk,
+    gdk::{self, traits::MonitorExt},
+    glib::{
+        self, JoinHandle, SignalHandlerId,
+        prelude::{Cast, IsA, ObjectExt},
+    },
+    prelude::GtkSettingsExt,
+};
 
 use crate::{
-    config::Config,
+    config::{self, Config},
+    desktop,
     error::Error,
-    icon,
+    icon, launcher,
     niri::{LayoutEvent, Niri, Snapshot, WindowStream},
-    notify::{self, EnrichedNotification},
+    notify::{self, EnrichedNotification, NotifyEvent},
+    process, tray,
 };
 
 /// Global state for the taskbar.
@@ -18,17 +34,83 @@ pub struct State(Arc<Inner>);
 
 impl State {
     /// Instantiates the global state.
-    pub fn new(config: Config) -> Self {
+    ///
+    /// If the configuration was loaded from a file, then the path should be provided so that the
+    /// file can be watched for changes.
+    pub fn new(config: Config, config_path: Option<PathBuf>) -> Self {
+        let icon_cache = icon::Cache::new(config.icon_theme().map(String::from));
+
         Self(Arc::new(Inner {
-            config,
-            icon_cache: icon::Cache::default(),
+            config: RwLock::new(Arc::new(config)),
+            config_path,
+            desktop_cache: desktop::Cache::default(),
+            process_cache: process::Cache::default(),
+            icon_cache,
             niri: Niri::new(),
+            orientation: Mutex::default(),
+            window_order: Mutex::default(),
+            services: Mutex::default(),
+            widget_events: async_channel::unbounded(),
         }))
     }
 
+    /// Returns the orientation of the taskbar, which is the configured orientation if there is
+    /// one, and otherwise the orientation detected from the bar.
+    pub fn orientation(&self) -> config::Orientation {
+        self.config()
+            .orientation()
+            .unwrap_or_else(|| *self.0.orientation.lock().expect("orientation lock"))
+    }
+
+    /// Reports that the urgent timeout has passed for the button showing the given window, so that
+    /// its notifications are cleared, and the workspace's urgency along with them.
+    pub fn urgent_timed_out(&self, window_id: u64) {
+        self.send_widget_event(Event::UrgentTimedOut(window_id));
+    }
+
+    /// Reports that the app has adopted an icon from a notification, so that the icons of its other
+    /// buttons are loaded again.
+    pub fn icon_adopted(&self, app_id: &str) {
+        self.send_widget_event(Event::IconAdopted(app_id.to_string()));
+    }
+
+    /// Reports that the given window was activated from somewhere other than its own button, such
+    /// as a window list or a pinned launcher, so that its button can react as if it were clicked.
+    pub fn window_activated(&self, window_id: u64) {
+        self.send_widget_event(Event::WindowActivated(window_id));
+    }
+
+    fn send_widget_event(&self, event: Event) {
+        if let Err(e) = self.0.widget_events.0.try_send(event) {
+            tracing::error!(%e, "error sending widget event");
+        }
+    }
+
+    /// Sets the orientation detected from the bar.
+    pub fn set_detected_orientation(&self, orientation: config::Orientation) {
+        *self.0.orientation.lock().expect("orientation lock") = orientation;
+    }
+
     /// Returns the taskbar configuration.
-    pub fn config(&self) -> &Config {
-        &self.0.config
+    ///
+    /// The configuration can be reloaded at any time, so this shouldn't be held across updates.
+    pub fn config(&self) -> Arc<Config> {
+        self.0.config.read().expect("config lock").clone()
+    }
+
+    /// Replaces the taskbar configuration.
+    pub fn set_config(&self, config: impl Into<Arc<Config>>) {
+        *self.0.config.write().expect("config lock") = config.into();
+    }
+
+    /// Accesses the global desktop entry cache.
+    pub fn desktop_cache(&self) -> &desktop::Cache {
+        &self.0.desktop_cache
+    }
+
+    /// Accesses the global cache of process ancestors.
+    pub fn process_cache(&self) -> &process::Cache {
+        &self.0.process_cache
     }
 
     /// Accesses the global icon cache.
@@ -41,57 +123,473 @@ impl State {
         &self.0.niri
     }
 
+    /// Returns the windows currently displayed in the taskbar, in display order.
+    pub fn window_order(&self) -> WindowOrder {
+        self.0
+            .window_order
+            .lock()
+            .expect("window order lock")
+            .clone()
+    }
+
+    /// Replaces the windows currently displayed in the taskbar.
+    pub fn set_window_order(&self, order: WindowOrder) {
+        *self.0.window_order.lock().expect("window order lock") = order;
+    }
+
     pub fn event_stream(&self) -> Result<impl Stream<Item = Event> + use<>, Error> {
         let (tx, rx) = async_channel::unbounded();
 
-        if self.config().notifications_enabled() {
-            glib::spawn_future_local(notify_stream(tx.clone()));
+        self.0.services.lock().expect("services lock").tx = Some(tx.clone());
+        self.update_services();
+
+        if let Some(path) = &self.0.config_path {
+            glib::spawn_future_local(config_stream(tx.clone(), path.clone()));
         }
 
-        glib::spawn_future_local(window_stream(tx.clone(), self.niri().window_stream()));
+        // The settings, display, and monitors outlive the module if Waybar reloads it, so their
+        // handlers are disconnected when the stream is dropped.
+        let handlers = SignalHandlers::default();
+
+        if let Some(settings) = gtk::Settings::default() {
+            let tx = tx.clone();
+            let id = settings.connect_gtk_icon_theme_name_notify(move |settings| {
+                let theme = settings.gtk_icon_theme_name().map(String::from);
+                if let Err(e) = tx.try_send(Event::IconThemeChanged(theme)) {
+                    tracing::error!(%e, "error sending icon theme change");
+                }
+            });
+            handlers.push(&settings, id);
+        }
 
+        // Docking or undocking a laptop can change which output the bar is on, or how the outputs
+        // are laid out, which the output filter depends on.
+        if let Some(display) = gdk::Display::default() {
+            for monitor in (0..display.n_monitors()).filter_map(|i| display.monitor(i)) {
+                watch_monitor(&monitor, tx.clone(), &handlers);
+            }
+            let id = display.connect_monitor_added({
+                let tx = tx.clone();
+                let handlers = handlers.clone();
+                move |_, monitor| {
+                    send_monitors_changed(&tx);
+                    watch_monitor(monitor, tx.clone(), &handlers);
+                }
+            });
+            handlers.push(&display, id);
+            let id = display.connect_monitor_removed({
+                let tx = tx.clone();
+                move |_, _| send_monitors_changed(&tx)
+            });
+            handlers.push(&display, id);
+        }
+
+        glib::spawn_future_local(widget_event_stream(
+            tx.clone(),
+            self.0.widget_events.1.clone(),
+        ));
+
+        glib::spawn_future_local(window_stream(
+            tx.clone(),
+            subscribe_windows(self, self.config().poll_interval()),
+        ));
+
+        let handlers = DisconnectOnDrop(handlers);
         Ok(async_stream::stream! {
+            let _handlers = handlers;
             while let Ok(event) = rx.recv().await {
                 yield event;
             }
         })
     }
+
+    /// Starts or stops the notification, launcher entry, and tray attention streams to match the
+    /// configuration, which can change when it's reloaded or overridden for the output.
+    ///
+    /// This does nothing until the event stream has been started.
+    pub fn update_services(&self) {
+        let config = self.config();
+        let mut services = self.0.services.lock().expect("services lock");
+        let Some(tx) = services.tx.clone() else {
+            return;
+        };
+
+        // The notification stream is also restarted if the cache settings change, since they're
+        // only read when it starts.
+        let cache = config
+            .notifications_enabled()
+            .then(|| config.notifications_cache().clone());
+        if services.notifications.as_ref().map(|(cache, _)| cache) != cache.as_ref() {
+            if let Some((_, handle)) = services.notifications.take() {
+                handle.abort();
+            }
+            services.notifications = cache.map(|cache| {
+                let handle = glib::spawn_future_local(notify_stream(tx.clone(), cache.clone()));
+                (cache, handle)
+            });
+        }
+
+        toggle(
+            &mut services.launcher,
+            config.launcher_entries_enabled(),
+            || launcher_stream(tx.clone()),
+        );
+        toggle(&mut services.tray, config.tray_attention_enabled(), || {
+            tray_stream(tx.clone())
+        });
+    }
+}
+
+/// The optional D-Bus streams feeding the event stream, which are started and stopped as the
+/// configuration changes.
+#[derive(Debug, Default)]
+struct Services {
+    // The sender of the event stream, once it has been started.
+    tx: Option<Sender<Event>>,
+    notifications: Option<(config::NotificationCache, JoinHandle<()>)>,
+    launcher: Option<JoinHandle<()>>,
+    tray: Option<JoinHandle<()>>,
+}
+
+/// Starts the task if it's enabled and not running, or stops it if it's running and disabled.
+fn toggle<F>(handle: &mut Option<JoinHandle<()>>, enabled: bool, start: impl FnOnce() -> F)
+where
+    F: Future<Output = ()> + 'static,
+{
+    match (handle.take(), enabled) {
+        (None, true) => *handle = Some(glib::spawn_future_local(start())),
+        (Some(running), false) => running.abort(),
+        (running, _) => *handle = running,
+    }
+}
+
+/// Signal handlers connected to objects that outlive an event stream.
+#[derive(Default, Clone)]
+struct SignalHandlers(Rc<RefCell<Vec<(glib::Object, SignalHandlerId)>>>);
+
+impl SignalHandlers {
+    fn push(&self, object: &impl IsA<glib::Object>, id: SignalHandlerId) {
+        self.0
+            .borrow_mut()
+            .push((object.upcast_ref::<glib::Object>().clone(), id));
+    }
+}
+
+/// Disconnects the signal handlers when the event stream is dropped.
+struct DisconnectOnDrop(SignalHandlers);
+
+impl Drop for DisconnectOnDrop {
+    fn drop(&mut self) {
+        // The handlers are taken first, since disconnecting the monitor added handler drops its
+        // reference to them.
+        let handlers = std::mem::take(&mut *self.0.0.borrow_mut());
+        for (object, id) in handlers {
+            object.disconnect(id);
+        }
+    }
 }
 
 #[derive(Debug)]
 struct Inner {
-    config: Config,
+    config: RwLock<Arc<Config>>,
+    config_path: Option<PathBuf>,
+    desktop_cache: desktop::Cache,
+    process_cache: process::Cache,
     icon_cache: icon::Cache,
     niri: Niri,
+    orientation: Mutex<config::Orientation>,
+    window_order: Mutex<WindowOrder>,
+    services: Mutex<Services>,
+    // Events raised by the taskbar's own widgets, such as urgent timeouts passing, which are passed
+    // on through the event stream.
+    widget_events: (Sender<Event>, Receiver<Event>),
+}
+
+/// A window as displayed in the taskbar.
+#[derive(Debug, Clone)]
+pub struct DisplayedWindow {
+    pub id: u64,
+    pub app_id: Option<String>,
+    pub workspace_id: Option<u64>,
+    pub pos: Option<(usize, usize)>,
+    /// Whether this is the active window on its workspace.
+    pub active: bool,
+}
+
+/// The windows displayed in the taskbar, in the order they are displayed.
+#[derive(Debug, Clone, Default)]
+pub struct WindowOrder(Vec<DisplayedWindow>);
+
+impl WindowOrder {
+    pub fn new(windows: Vec<DisplayedWindow>) -> Self {
+        Self(windows)
+    }
+
+    /// Returns the displayed windows, in display order.
+    pub fn iter(&self) -> impl Iterator<Item = &DisplayedWindow> {
+        self.0.iter()
+    }
+
+    /// Returns the displayed window with the given ID.
+    pub fn get(&self, id: u64) -> Option<&DisplayedWindow> {
+        self.0.iter().find(|window| window.id == id)
+    }
+
+    /// Returns the window that should be focused when cycling from the given window.
+    ///
+    /// Windows with the same app ID are preferred, wrapping around the whole taskbar. If there are
+    /// no other windows with the same app ID, then the next window on the same workspace is used
+    /// instead.
+    pub fn cycle(&self, from: u64, forward: bool) -> Option<u64> {
+        let current = self.get(from)?;
+
+        self.cycle_matching(from, forward, |window| {
+            current.app_id.is_some() && window.app_id == current.app_id
+        })
+        .or_else(|| {
+            self.cycle_matching(from, forward, |window| {
+                window.workspace_id == current.workspace_id
+            })
+        })
+    }
+
+    /// Returns the window that should be focused when cycling through the windows on the given
+    /// workspace.
+    ///
+    /// Cycling starts from the active window on the workspace, or from the first or last window if
+    /// there isn't one.
+    pub fn cycle_workspace(&self, workspace_id: u64, forward: bool) -> Option<u64> {
+        let on_workspace = |window: &DisplayedWindow| window.workspace_id == Some(workspace_id);
+
+        match self
+            .0
+            .iter()
+            .find(|window| on_workspace(window) && window.active)
+        {
+            Some(active) => self.cycle_matching(active.id, forward, on_workspace),
+            None => {
+                let mut windows = self.0.iter().filter(|window| on_workspace(window));
+                if forward {
+                    windows.next().map(|window| window.id)
+                } else {
+                    windows.next_back().map(|window| window.id)
+                }
+            }
+        }
+    }
+
+    fn cycle_matching(
+        &self,
+        from: u64,
+        forward: bool,
+        predicate: impl Fn(&DisplayedWindow) -> bool,
+    ) -> Option<u64> {
+        let candidates: Vec<_> = self.0.iter().filter(|window| predicate(window)).collect();
+        let idx = candidates.iter().position(|window| window.id == from)?;
+        if candidates.len() < 2 {
+            return None;
+        }
+
+        let next = if forward {
+            (idx + 1) % candidates.len()
+        } else {
+            (idx + candidates.len() - 1) % candidates.len()
+        };
+
+        Some(candidates[next].id)
+    }
 }
 
 pub enum Event {
     Notification(Box<EnrichedNotification>),
+    NotificationClosed(u32),
+    LauncherUpdate(launcher::Update),
+    TrayAttention(tray::Attention),
     WindowSnapshot(Snapshot),
-    FloatingClosed,
+    WindowFocused(u64),
+    UrgentTimedOut(u64),
+    IconAdopted(String),
+    WindowActivated(u64),
+    DoNotDisturb(bool),
+    IconThemeChanged(Option<String>),
+    MonitorsChanged,
+    NotificationsDisconnected,
+    NotificationsReconnected,
+    WindowStreamDisconnected,
+    WindowStreamReset,
+    ConfigReloaded(Result<Box<Config>, config::file::Error>),
+}
+
+async fn widget_event_stream(tx: Sender<Event>, rx: Receiver<Event>) {
+    while let Ok(event) = rx.recv().await {
+        if let Err(e) = tx.send(event).await {
+            tracing::error!(%e, "error sending widget event");
+        }
+    }
+}
+
+fn watch_monitor(monitor: &gdk::Monitor, tx: Sender<Event>, handlers: &SignalHandlers) {
+    let id = monitor.connect_geometry_notify(move |_| send_monitors_changed(&tx));
+    handlers.push(monitor, id);
+}
+
+fn send_monitors_changed(tx: &Sender<Event>) {
+    if let Err(e) = tx.try_send(Event::MonitorsChanged) {
+        tracing::error!(%e, "error sending monitor change");
+    }
 }
 
-async fn notify_stream(tx: Sender<Event>) {
-    let mut stream = Box::pin(notify::stream());
+async fn notify_stream(tx: Sender<Event>, cache: config::NotificationCache) {
+    let mut stream = Box::pin(notify::stream(cache));
 
-    while let Some(notification) = stream.next().await {
-        if let Err(e) = tx.send(Event::Notification(Box::new(notification))).await {
+    while let Some(event) = stream.next().await {
+        let event = match event {
+            NotifyEvent::Notification(notification) => Event::Notification(notification),
+            NotifyEvent::Closed(id) => Event::NotificationClosed(id),
+            NotifyEvent::Disconnected => Event::NotificationsDisconnected,
+            NotifyEvent::Reconnected => Event::NotificationsReconnected,
+        };
+        if let Err(e) = tx.send(event).await {
             tracing::error!(%e, "error sending notification");
         }
     }
 }
 
-async fn window_stream(tx: Sender<Event>, window_stream: WindowStream) {
+async fn tray_stream(tx: Sender<Event>) {
+    let mut stream = Box::pin(tray::stream());
+
+    while let Some(attention) = stream.next().await {
+        if let Err(e) = tx.send(Event::TrayAttention(attention)).await {
+            tracing::error!(%e, "error sending tray item attention");
+        }
+    }
+}
+
+async fn launcher_stream(tx: Sender<Event>) {
+    let mut stream = Box::pin(launcher::stream());
+
+    while let Some(update) = stream.next().await {
+        if let Err(e) = tx.send(Event::LauncherUpdate(update)).await {
+            tracing::error!(%e, "error sending launcher entry update");
+        }
+    }
+}
+
+async fn config_stream(tx: Sender<Event>, path: PathBuf) {
+    let mut stream = Box::pin(config::file::watch(path));
+
+    while let Some(config) = stream.next().await {
+        if let Err(e) = tx.send(Event::ConfigReloaded(config.map(Box::new))).await {
+            tracing::error!(%e, "error sending reloaded configuration");
+        }
+    }
+}
+
+/// The Niri window stream, which is shared by every bar in the process, so that each bar doesn't
+/// need its own connection to Niri and its own copy of the window state.
+#[derive(Default)]
+struct SharedWindowStream {
+    // The state of each bar is kept alongside it, so that actions that should only happen once,
+    // rather than once per bar, can still follow the bars' configurations.
+    subscribers: Vec<(Sender<LayoutEvent>, State)>,
+    // The current state, so that bars created after the stream started don't have to wait for the
+    // next change.
+    last: Option<Snapshot>,
+    disconnected: bool,
+}
+
+thread_local! {
+    static WINDOW_STREAM: RefCell<Option<SharedWindowStream>> = const { RefCell::new(None) };
+}
+
+/// Subscribes to the shared window stream, starting it if this is the first bar to do so.
+///
+/// The poll interval is only used when the stream is started.
+fn subscribe_windows(state: &State, poll_interval: Duration) -> Receiver<LayoutEvent> {
+    let (tx, rx) = async_channel::unbounded();
+
+    WINDOW_STREAM.with_borrow_mut(|shared| {
+        let shared = shared.get_or_insert_with(|| {
+            glib::spawn_future_local(broadcast_windows(state.niri().window_stream(poll_interval)));
+            SharedWindowStream::default()
+        });
+
+        if let Some(snapshot) = &shared.last {
+            let _ = tx.try_send(LayoutEvent::Change(snapshot.clone()));
+        }
+        if shared.disconnected {
+            let _ = tx.try_send(LayoutEvent::Disconnected);
+        }
+        shared.subscribers.push((tx, state.clone()));
+    });
+
+    rx
+}
+
+/// Sends each event from the window stream to every subscribed bar.
+async fn broadcast_windows(window_stream: WindowStream) {
     while let Some(layout_event) = window_stream.next().await {
+        WINDOW_STREAM.with_borrow_mut(|shared| {
+            let Some(shared) = shared else {
+                return;
+            };
+
+            match &layout_event {
+                LayoutEvent::Change(snapshot) => shared.last = Some(snapshot.clone()),
+                LayoutEvent::Disconnected => shared.disconnected = true,
+                LayoutEvent::Reset => {
+                    shared.last = None;
+                    shared.disconnected = false;
+                }
+                LayoutEvent::FloatingClosed => {
+                    // Every bar shares the same Niri, so the tiling layout is only focused once,
+                    // however many bars want it to be.
+                    shared.subscribers.retain(|(tx, _)| !tx.is_closed());
+                    if let Some((_, state)) = shared
+                        .subscribers
+                        .iter()
+                        .find(|(_, state)| state.config().close_to_tiling())
+                    {
+                        state
+                            .niri()
+                            .spawn("error focusing tiling layout", Niri::focus_tiling);
+                    }
+                    return;
+                }
+                LayoutEvent::Focused(_) => {}
+            }
+
+            // Bars that have been destroyed have dropped their receivers, so they're forgotten.
+            shared
+                .subscribers
+                .retain(|(tx, _)| tx.try_send(layout_event.clone()).is_ok());
+        });
+    }
+}
+
+async fn window_stream(tx: Sender<Event>, window_stream: Receiver<LayoutEvent>) {
+    while let Ok(layout_event) = window_stream.recv().await {
         match layout_event {
             LayoutEvent::Change(snapshot) => {
                 if let Err(e) = tx.send(Event::WindowSnapshot(snapshot)).await {
                     tracing::error!(%e, "error sending window snapshot");
                 }
             }
-            LayoutEvent::FloatingClosed => {
-                if let Err(e) = tx.send(Event::FloatingClosed).await {
-                    tracing::error!(%e, "error sending floating closed event");
+            // Handled by the shared stream.
+            LayoutEvent::FloatingClosed => {}
+            LayoutEvent::Focused(id) => {
+                if let Err(e) = tx.send(Event::WindowFocused(id)).await {
+                    tracing::error!(%e, "error sending window focus");
+                }
+            }
+            LayoutEvent::Disconnected => {
+                if let Err(e) = tx.send(Event::WindowStreamDisconnected).await {
+                    tracing::error!(%e, "error sending window stream disconnection");
+                }
+            }
+            LayoutEvent::Reset => {
+                if let Err(e) = tx.send(Event::WindowStreamReset).await {
+                    tracing::error!(%e, "error sending window stream reset");
                 }
             }
         }
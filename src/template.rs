@@ -0,0 +1,129 @@
+//! Simple `{placeholder}` templates used for labels and tooltips.
+
+use serde::{Deserialize, Deserializer};
+
+/// A parsed template.
+///
+/// Placeholders are written as `{name}`; literal braces can be written as `{{` and `}}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Template(Vec<Part>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Placeholder(String),
+}
+
+impl Template {
+    /// Parses a template.
+    pub fn new(template: &str) -> Self {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    literal.push(c);
+                    chars.next();
+                }
+                ('{', _) => {
+                    let mut name = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        name.push(c);
+                    }
+
+                    if closed {
+                        if !literal.is_empty() {
+                            parts.push(Part::Literal(std::mem::take(&mut literal)));
+                        }
+                        parts.push(Part::Placeholder(name));
+                    } else {
+                        // An unterminated placeholder is treated as literal text.
+                        literal.push('{');
+                        literal.push_str(&name);
+                    }
+                }
+                _ => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Self(parts)
+    }
+
+    /// Renders the template line by line, omitting any line that contains a placeholder without a
+    /// value.
+    pub fn render_lines(&self, lookup: impl Fn(&str) -> Option<String>) -> String {
+        let mut lines = vec![Vec::new()];
+        for part in self.0.iter() {
+            match part {
+                Part::Literal(text) => {
+                    let mut split = text.split('\n');
+                    if let Some(first) = split.next() {
+                        push_literal(lines.last_mut().expect("at least one line"), first);
+                    }
+                    for rest in split {
+                        let mut line = Vec::new();
+                        push_literal(&mut line, rest);
+                        lines.push(line);
+                    }
+                }
+                Part::Placeholder(_) => lines
+                    .last_mut()
+                    .expect("at least one line")
+                    .push(part.clone()),
+            }
+        }
+
+        lines
+            .into_iter()
+            .filter_map(|parts| Self(parts).render_inner(&lookup).ok())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn render_inner(&self, lookup: &impl Fn(&str) -> Option<String>) -> Result<String, String> {
+        let mut rendered = String::new();
+        let mut complete = true;
+
+        for part in self.0.iter() {
+            match part {
+                Part::Literal(text) => rendered.push_str(text),
+                Part::Placeholder(name) => match lookup(name) {
+                    Some(value) => rendered.push_str(&value),
+                    None => complete = false,
+                },
+            }
+        }
+
+        if complete {
+            Ok(rendered)
+        } else {
+            Err(rendered)
+        }
+    }
+}
+
+fn push_literal(parts: &mut Vec<Part>, text: &str) {
+    if !text.is_empty() {
+        parts.push(Part::Literal(text.to_string()));
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Self::new(&String::deserialize(de)?))
+    }
+}
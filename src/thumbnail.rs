@@ -0,0 +1,163 @@
+//! Window thumbnails, captured via Niri's window screenshot action.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use waybar_cffi::gtk::{
+    Clipboard,
+    gdk::SELECTION_CLIPBOARD,
+    gdk_pixbuf::{InterpType, Pixbuf},
+    glib,
+};
+
+use crate::state::State;
+
+// Pixbufs are only ever used from the main thread, so the cache doesn't need to be shared.
+thread_local! {
+    static THUMBNAILS: RefCell<HashMap<u64, Entry>> = RefCell::default();
+    static CAPTURE: RefCell<Option<Capture>> = const { RefCell::new(None) };
+}
+
+/// The capture in flight, if any.
+///
+/// Every capture goes through the clipboard, so only one can be in flight at once: otherwise, two
+/// captures in quick succession could both read the second window's screenshot.
+struct Capture {
+    window_id: u64,
+    // Callbacks waiting for the thumbnail, since hovering the same window again while it's being
+    // captured shouldn't be dropped.
+    pending: Vec<Box<dyn FnOnce(Pixbuf)>>,
+}
+
+struct Entry {
+    pixbuf: Pixbuf,
+    captured: Instant,
+}
+
+/// How long we wait after Niri handles the screenshot action before reading the clipboard.
+static CLIPBOARD_DELAY: Duration = Duration::from_millis(150);
+
+/// Fetches a thumbnail for the given window, calling `f` with it once available.
+///
+/// Thumbnails are cached for the configured maximum age, so hovering back and forth over a button
+/// doesn't result in a new screenshot each time.
+///
+/// Niri doesn't return screenshots over IPC: instead, it places them on the clipboard. That means
+/// capturing a thumbnail replaces whatever was on the clipboard, which is why this feature is
+/// disabled by default. It also means that only one capture can run at a time, so requests for
+/// other windows while a capture is running are dropped.
+pub fn get(state: &State, window_id: u64, f: impl FnOnce(Pixbuf) + 'static) {
+    let config = state.config();
+    let settings = config.thumbnails();
+
+    let cached = THUMBNAILS.with_borrow(|thumbnails| {
+        thumbnails
+            .get(&window_id)
+            .filter(|entry| entry.captured.elapsed() < settings.max_age())
+            .map(|entry| entry.pixbuf.clone())
+    });
+    if let Some(pixbuf) = cached {
+        f(pixbuf);
+        return;
+    }
+
+    let started = CAPTURE.with_borrow_mut(|capture| match capture {
+        Some(capture) if capture.window_id == window_id => {
+            capture.pending.push(Box::new(f));
+            false
+        }
+        Some(capture) => {
+            tracing::debug!(
+                window_id,
+                capturing = capture.window_id,
+                "dropping thumbnail request while another capture is running"
+            );
+            false
+        }
+        None => {
+            *capture = Some(Capture {
+                window_id,
+                pending: vec![Box::new(f)],
+            });
+            true
+        }
+    });
+    if !started {
+        return;
+    }
+
+    let size = settings.size();
+    let niri = state.niri().clone();
+    glib::spawn_future_local(async move {
+        if let Err(e) = niri
+            .run(move |niri| niri.screenshot_window(window_id))
+            .await
+        {
+            tracing::warn!(%e, window_id, "cannot capture window thumbnail");
+            finish(None);
+            return;
+        }
+
+        // Niri handles the action before the clipboard is actually updated, so we'll give it a
+        // moment before we go looking.
+        glib::timeout_future(CLIPBOARD_DELAY).await;
+
+        Clipboard::get(&SELECTION_CLIPBOARD).request_image(move |_, pixbuf| {
+            let Some(pixbuf) = pixbuf.and_then(|pixbuf| scale(pixbuf, size)) else {
+                tracing::info!(window_id, "no image on clipboard after window screenshot");
+                finish(None);
+                return;
+            };
+
+            THUMBNAILS.with_borrow_mut(|thumbnails| {
+                thumbnails.insert(
+                    window_id,
+                    Entry {
+                        pixbuf: pixbuf.clone(),
+                        captured: Instant::now(),
+                    },
+                );
+            });
+
+            finish(Some(pixbuf));
+        });
+    });
+}
+
+/// Ends the capture in flight, passing the thumbnail to everything waiting for it.
+fn finish(pixbuf: Option<Pixbuf>) {
+    let Some(capture) = CAPTURE.take() else {
+        return;
+    };
+
+    if let Some(pixbuf) = pixbuf {
+        for f in capture.pending {
+            f(pixbuf.clone());
+        }
+    }
+}
+
+/// Removes any cached thumbnails for windows that `f` returns false for.
+pub fn retain(f: impl Fn(u64) -> bool) {
+    THUMBNAILS.with_borrow_mut(|thumbnails| {
+        thumbnails.retain(|window_id, _| f(*window_id));
+    });
+}
+
+/// Scales the pixbuf so that its longest side is no longer than `size`.
+fn scale(pixbuf: &Pixbuf, size: i32) -> Option<Pixbuf> {
+    let (width, height) = (pixbuf.width(), pixbuf.height());
+    if width <= size && height <= size {
+        return Some(pixbuf.clone());
+    }
+
+    let ratio = f64::from(size) / f64::from(width.max(height));
+    pixbuf.scale_simple(
+        ((f64::from(width) * ratio) as i32).max(1),
+        ((f64::from(height) * ratio) as i32).max(1),
+        InterpType::Bilinear,
+    )
+}
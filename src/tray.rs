@@ -0,0 +1,196 @@
+//! Support for StatusNotifierItem tray icons, which many chat apps use to request attention
+//! instead of, or as well as, sending notifications.
+
+use async_channel::Sender;
+use futures::{Stream, TryStreamExt};
+use waybar_cffi::gtk::glib;
+use zbus::{
+    Connection, MatchRule, MessageStream,
+    fdo::{DBusProxy, PropertiesProxy},
+    message::Type as MessageType,
+    names::{BusName, InterfaceName},
+    zvariant::{ObjectPath, OwnedValue},
+};
+
+/// Starts a stream of tray items that need attention, or no longer do.
+///
+/// Like launcher entries, status changes are broadcast as signals, so a normal match rule is
+/// enough. Items that already need attention when the stream starts are found by asking the
+/// StatusNotifierWatcher for the registered items.
+pub fn stream() -> impl Stream<Item = Attention> {
+    let (tx, rx) = async_channel::unbounded();
+    glib::spawn_future_local(async move {
+        match listen(tx).await {
+            Ok(()) => tracing::info!("no longer listening for tray item status changes"),
+            Err(e) => tracing::error!(%e, "D-Bus error listening for tray item status changes"),
+        }
+    });
+
+    async_stream::stream! {
+        while let Ok(attention) = rx.recv().await {
+            yield attention;
+        }
+    }
+}
+
+/// A tray item that has changed its status to or from `NeedsAttention`.
+#[derive(Debug, Clone)]
+pub struct Attention {
+    pid: Option<u32>,
+    id: Option<String>,
+    needs_attention: bool,
+}
+
+impl Attention {
+    /// Returns true if the item needs attention, or false if it's gone back to `Active` or
+    /// `Passive`.
+    pub fn needs_attention(&self) -> bool {
+        self.needs_attention
+    }
+
+    /// Returns the PID of the process that owns the item, if available.
+    pub fn pid(&self) -> Option<i64> {
+        self.pid.map(i64::from)
+    }
+
+    /// Returns the item's ID, which is usually the name of the app, if available.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+}
+
+static INTERFACE: &str = "org.kde.StatusNotifierItem";
+static SIGNAL: &str = "NewStatus";
+static ID_PROPERTY: &str = "Id";
+static STATUS_PROPERTY: &str = "Status";
+static NEEDS_ATTENTION: &str = "NeedsAttention";
+// Items registered with just a bus name are at the default path.
+static DEFAULT_ITEM_PATH: &str = "/StatusNotifierItem";
+
+static WATCHER: &str = "org.kde.StatusNotifierWatcher";
+static WATCHER_PATH: &str = "/StatusNotifierWatcher";
+static ITEMS_PROPERTY: &str = "RegisteredStatusNotifierItems";
+
+#[tracing::instrument(level = "TRACE", skip_all, err)]
+async fn listen(tx: Sender<Attention>) -> anyhow::Result<()> {
+    let conn = Connection::session().await?;
+    let dbus_proxy = DBusProxy::new(&conn).await?;
+    let rule = MatchRule::builder()
+        .msg_type(MessageType::Signal)
+        .interface(INTERFACE)?
+        .member(SIGNAL)?
+        .build();
+
+    // The match rule is added first, so that a status change while the registered items are being
+    // checked isn't missed.
+    let mut stream = MessageStream::for_match_rule(rule, &conn, None).await?;
+    send_registered(&conn, &dbus_proxy, &tx).await?;
+
+    while let Some(msg) = stream.try_next().await? {
+        let status: String = match msg.body().deserialize() {
+            Ok(status) => status,
+            Err(e) => {
+                tracing::warn!(%e, ?msg, "cannot parse tray item status");
+                continue;
+            }
+        };
+        let needs_attention = status == NEEDS_ATTENTION;
+
+        let header = msg.header();
+        let (Some(sender), Some(path)) = (header.sender(), header.path()) else {
+            continue;
+        };
+        let sender = BusName::from(sender.clone());
+
+        tx.send(attention(&conn, &dbus_proxy, &sender, path, needs_attention).await)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Sends the registered items that already need attention, since they won't send a signal until
+/// their status changes again.
+async fn send_registered(
+    conn: &Connection,
+    dbus_proxy: &DBusProxy<'_>,
+    tx: &Sender<Attention>,
+) -> anyhow::Result<()> {
+    let watcher = BusName::from_static_str(WATCHER)?;
+    let watcher_path = ObjectPath::from_static_str_unchecked(WATCHER_PATH);
+    let Some(items) = property(conn, &watcher, &watcher_path, WATCHER, ITEMS_PROPERTY).await else {
+        // There may not be a tray at all.
+        return Ok(());
+    };
+    let items = Vec::<String>::try_from(items).unwrap_or_default();
+
+    for item in items {
+        // Items are registered as a bus name followed by an optional object path.
+        let (service, path) = match item.find('/') {
+            Some(i) => item.split_at(i),
+            None => (item.as_str(), DEFAULT_ITEM_PATH),
+        };
+        let (Ok(service), Ok(path)) = (BusName::try_from(service), ObjectPath::try_from(path))
+        else {
+            tracing::debug!(item, "cannot parse registered tray item");
+            continue;
+        };
+
+        let status = property(conn, &service, &path, INTERFACE, STATUS_PROPERTY)
+            .await
+            .and_then(|value| String::try_from(value).ok());
+        if status.as_deref() == Some(NEEDS_ATTENTION) {
+            tx.send(attention(conn, dbus_proxy, &service, &path, true).await)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Looks up the process and ID of the tray item at the given bus name and path.
+async fn attention(
+    conn: &Connection,
+    dbus_proxy: &DBusProxy<'_>,
+    service: &BusName<'_>,
+    path: &ObjectPath<'_>,
+    needs_attention: bool,
+) -> Attention {
+    let pid = dbus_proxy
+        .get_connection_unix_process_id(service.clone())
+        .await
+        .ok();
+    let id = property(conn, service, path, INTERFACE, ID_PROPERTY)
+        .await
+        .and_then(|value| String::try_from(value).ok());
+
+    Attention {
+        pid,
+        id,
+        needs_attention,
+    }
+}
+
+/// Gets a property of the given object.
+async fn property(
+    conn: &Connection,
+    service: &BusName<'_>,
+    path: &ObjectPath<'_>,
+    interface: &'static str,
+    name: &str,
+) -> Option<OwnedValue> {
+    let proxy = PropertiesProxy::builder(conn)
+        .destination(service.to_owned())
+        .ok()?
+        .path(path.to_owned())
+        .ok()?
+        .build()
+        .await
+        .ok()?;
+
+    proxy
+        .get(InterfaceName::from_static_str_unchecked(interface), name)
+        .await
+        .inspect_err(|e| tracing::debug!(%e, %service, %path, name, "cannot get D-Bus property"))
+        .ok()
+}